@@ -0,0 +1,128 @@
+//! Generates the C header for the functions in `src/capi.rs`, into `$OUT_DIR/stk.h`.
+//!
+//! This only understands the subset of Rust used there: `extern "C"` functions with their whole
+//! signature on one line, and a few argument types.
+
+use std::{env, fs::{read_to_string, write}, path::Path};
+
+const SOURCE: &str = "src/capi.rs";
+
+fn main() {
+    println!("cargo::rerun-if-changed={SOURCE}");
+    println!("cargo::rerun-if-changed=build.rs");
+
+    let source = read_to_string(SOURCE).expect("couldn't read C API source");
+    let mut header = String::from("\
+/* Generated from src/capi.rs by build.rs - do not edit. */
+
+#ifndef STK_H
+#define STK_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern \"C\" {
+#endif
+
+/* An interpreter, along with the last error that happened while using it. */
+typedef struct StkInterpreter StkInterpreter;
+");
+
+    let mut docs = vec![];
+    for line in source.lines().map(str::trim) {
+        if let Some(doc) = line.strip_prefix("///") {
+            docs.push(doc.trim().to_owned());
+            continue;
+        }
+
+        let signature = line.strip_prefix("pub unsafe extern \"C\" fn ")
+            .or_else(|| line.strip_prefix("pub extern \"C\" fn "));
+        if let Some(signature) = signature {
+            header.push('\n');
+            header.push_str(&c_comment(&docs));
+            header.push_str(&c_declaration(signature));
+        }
+
+        // Attributes come between a function's docs and its signature
+        if !line.starts_with("#[") {
+            docs.clear();
+        }
+    }
+
+    header.push_str("
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* STK_H */
+");
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    write(Path::new(&out_dir).join("stk.h"), header).expect("couldn't write C header");
+}
+
+fn c_comment(docs: &[String]) -> String {
+    // Rust's `# Safety` heading reads oddly in C
+    let docs = docs.iter()
+        .map(|d| if d == "# Safety" { "Safety:" } else { d })
+        .map(|d| d.replace('`', ""))
+        .collect::<Vec<_>>();
+
+    let mut comment = String::from("/*\n");
+    for line in docs {
+        comment.push_str(&format!(" *{}{line}\n", if line.is_empty() { "" } else { " " }));
+    }
+    comment.push_str(" */\n");
+    comment
+}
+
+/// Converts `name(arg: Type, ...) -> Type {` into a C declaration.
+fn c_declaration(signature: &str) -> String {
+    let (name, rest) = signature.split_once('(').expect("missing arguments");
+    let (args, rest) = rest.split_once(')').expect("missing arguments");
+    let rest = rest.trim_end_matches('{').trim();
+    let return_type = match rest.strip_prefix("->") {
+        Some(t) => c_type(t.trim()),
+        None => "void".to_owned(),
+    };
+
+    let args = args.split(',')
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .map(|arg| {
+            let (arg_name, arg_type) = arg.split_once(':').expect("missing argument type");
+            c_variable(&c_type(arg_type.trim()), arg_name.trim())
+        })
+        .collect::<Vec<_>>();
+    let args = if args.is_empty() { "void".to_owned() } else { args.join(", ") };
+
+    format!("{};\n", c_variable(&return_type, &format!("{name}({args})")))
+}
+
+/// Declares a name with a type, keeping pointer stars next to the name, as in `char *name`.
+fn c_variable(c_type: &str, name: &str) -> String {
+    match c_type.ends_with('*') {
+        true => format!("{c_type}{name}"),
+        false => format!("{c_type} {name}"),
+    }
+}
+
+fn c_type(rust_type: &str) -> String {
+    if let Some(pointee) = rust_type.strip_prefix("*mut ") {
+        return c_variable(&c_type(pointee), "*");
+    }
+    if let Some(pointee) = rust_type.strip_prefix("*const ") {
+        return c_variable(&format!("const {}", c_type(pointee)), "*");
+    }
+
+    match rust_type {
+        "bool" => "bool",
+        "isize" => "intptr_t",
+        "usize" => "size_t",
+        "c_char" => "char",
+        "StkInterpreter" => "StkInterpreter",
+        _ => panic!("no C equivalent for `{rust_type}`"),
+    }.to_owned()
+}
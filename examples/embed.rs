@@ -0,0 +1,42 @@
+//! Embeds the interpreter: runs a small script against some input, then reads back the values it
+//! left on the stack.
+//!
+//!     cargo run --example embed
+
+use std::error::Error;
+
+use advent_of_code_2024::{code_to_node, load_stdlib, Interpreter, Value};
+
+const SCRIPT: &str = "
+// Sum each line of numbers, and then sum those
+$input lines
+{ wsplit { int } map sum } map
+dup sum
+";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new();
+    interpreter.execute(&load_stdlib()?)?;
+    interpreter.set_top_level_binding("$input", Value::from_string("1 2 3\n10 20\n4 5 6"));
+
+    let root = code_to_node(SCRIPT, "(script)")?;
+    if let Err(e) = interpreter.execute(&root) {
+        // Errors know where they happened
+        match e.loc() {
+            Some(loc) => {
+                let (line, col) = loc.line_col();
+                eprintln!("error at {}:{line}:{col}: {e}", loc.source.name);
+            },
+            None => eprintln!("error: {e}"),
+        }
+        return Err(e.into());
+    }
+
+    // The top of the stack is last
+    let total = interpreter.pop()?.into_integer()?;
+    let sums = interpreter.pop()?.into_integer_array()?;
+    println!("sums: {sums:?}");
+    println!("total: {total}");
+
+    Ok(())
+}
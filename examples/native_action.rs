@@ -0,0 +1,46 @@
+//! Registers native Rust actions with an embedded interpreter, and calls them from a script.
+//!
+//!     cargo run --example native_action
+
+use std::error::Error;
+
+use advent_of_code_2024::{builtins::NativeAction, code_to_node, eval::ActionCall, load_stdlib, ExecutionError, Interpreter, Value};
+
+/// An action implemented as a type, which can hold its own state.
+struct Scale {
+    factor: isize,
+}
+
+impl NativeAction for Scale {
+    fn name(&self) -> &str { "scale" }
+    fn effect(&self) -> &str { "int -- int" }
+    fn doc(&self) -> &str { "Multiply an integer by the configured factor." }
+
+    fn execute(&self, interpreter: &mut Interpreter, _call: ActionCall<'_>) -> Result<(), ExecutionError> {
+        let i = interpreter.pop()?.into_integer()?;
+        interpreter.push(Value::Integer(i * self.factor));
+        Ok(())
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut interpreter = Interpreter::new();
+    interpreter.execute(&load_stdlib()?)?;
+
+    interpreter.register_action(Scale { factor: 10 })?;
+
+    // Simple actions can be closures
+    interpreter.register_fn("max", "a b -- int", "The larger of two integers.", |interpreter, _| {
+        let b = interpreter.pop()?.into_integer()?;
+        let a = interpreter.pop()?.into_integer()?;
+        interpreter.push(Value::Integer(a.max(b)));
+        Ok(())
+    })?;
+
+    // Names can't clash with builtins, other native actions, or user actions
+    let clash = interpreter.register_fn("map", "--", "", |_, _| Ok(()));
+    println!("registering `map` again: {}", clash.unwrap_err());
+
+    interpreter.execute(&code_to_node("3 scale 25 max println", "(script)")?)?;
+    Ok(())
+}
@@ -0,0 +1,214 @@
+use std::{error::Error, io::{self, sink, Write}, time::{Duration, Instant}};
+
+use crate::{code_to_node, json::Json, prepare_interpreter};
+
+/// The phases of running a solution which are timed separately, in the order they happen.
+pub const PHASES: [&str; 3] = ["parse", "stdlib", "execute"];
+
+/// Summary statistics of the times taken by a phase across several runs, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub mean: f64,
+    pub median: f64,
+    pub min: f64,
+    pub stddev: f64,
+}
+
+impl Stats {
+    pub fn from_samples(samples: &[Duration]) -> Stats {
+        let mut millis = samples.iter().map(|d| d.as_secs_f64() * 1000.0).collect::<Vec<_>>();
+        millis.sort_by(f64::total_cmp);
+
+        let n = millis.len() as f64;
+        let mean = millis.iter().sum::<f64>() / n;
+        let median = match millis.len() % 2 {
+            0 => (millis[millis.len() / 2 - 1] + millis[millis.len() / 2]) / 2.0,
+            _ => millis[millis.len() / 2],
+        };
+        let variance = millis.iter().map(|m| (m - mean).powi(2)).sum::<f64>() / n;
+
+        Stats { mean, median, min: millis[0], stddev: variance.sqrt() }
+    }
+
+    fn to_json(self) -> Json {
+        Json::object([
+            ("mean", self.mean.into()),
+            ("median", self.median.into()),
+            ("min", self.min.into()),
+            ("stddev", self.stddev.into()),
+        ])
+    }
+
+    fn from_json(json: &Json) -> Option<Stats> {
+        Some(Stats {
+            mean: json.get("mean")?.as_float()?,
+            median: json.get("median")?.as_float()?,
+            min: json.get("min")?.as_float()?,
+            stddev: json.get("stddev")?.as_float()?,
+        })
+    }
+}
+
+/// The results of benchmarking a code file, which can be saved as a baseline to compare against.
+#[derive(Debug, Clone)]
+pub struct Benchmark {
+    pub runs: usize,
+
+    /// Statistics for each of the [PHASES], followed by the `total` of all of them.
+    pub phases: Vec<(String, Stats)>,
+}
+
+impl Benchmark {
+    /// Runs a code file `warmup + runs` times, timing each phase of the measured runs. Anything the
+    /// code prints is discarded.
+    pub fn run(code_path: &str, input_path: Option<&str>, warmup: usize, runs: usize) -> Result<Benchmark, Box<dyn Error>> {
+        if runs == 0 {
+            return Err("need at least one measured run".into());
+        }
+
+        let code = std::fs::read_to_string(code_path)?;
+        let mut samples = vec![vec![]; PHASES.len() + 1];
+        for i in 0..(warmup + runs) {
+            let start = Instant::now();
+            let root = code_to_node(&code, code_path)?;
+            let parsed = Instant::now();
+            let mut interpreter = prepare_interpreter(input_path)?;
+            interpreter.set_output(Box::new(sink()));
+            let loaded = Instant::now();
+            interpreter.execute(&root)?;
+            let executed = Instant::now();
+
+            if i >= warmup {
+                samples[0].push(parsed - start);
+                samples[1].push(loaded - parsed);
+                samples[2].push(executed - loaded);
+                samples[3].push(executed - start);
+            }
+        }
+
+        let phases = PHASES.iter().chain(&["total"])
+            .zip(&samples)
+            .map(|(phase, samples)| (phase.to_string(), Stats::from_samples(samples)))
+            .collect();
+        Ok(Benchmark { runs, phases })
+    }
+
+    pub fn to_json(&self) -> Json {
+        Json::object([
+            ("runs", self.runs.into()),
+            ("phases", Json::object(self.phases.iter().map(|(name, stats)| (name.as_str(), stats.to_json())))),
+        ])
+    }
+
+    pub fn from_json(json: &Json) -> Result<Benchmark, Box<dyn Error>> {
+        let invalid = || "invalid benchmark baseline";
+        let runs = json.get("runs").and_then(|r| r.as_integer()).ok_or_else(invalid)? as usize;
+        let Some(Json::Object(phases)) = json.get("phases") else { return Err(invalid().into()) };
+        let phases = phases.iter()
+            .map(|(name, stats)| Ok((name.clone(), Stats::from_json(stats).ok_or_else(invalid)?)))
+            .collect::<Result<_, Box<dyn Error>>>()?;
+
+        Ok(Benchmark { runs, phases })
+    }
+
+    pub fn write_report(&self, output: &mut impl Write) -> io::Result<()> {
+        writeln!(output, "{:<10} {:>12} {:>12} {:>12} {:>12}", "phase", "mean ms", "median ms", "min ms", "stddev ms")?;
+        for (name, stats) in &self.phases {
+            writeln!(
+                output, "{name:<10} {:>12.3} {:>12.3} {:>12.3} {:>12.3}",
+                stats.mean, stats.median, stats.min, stats.stddev,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Compares median times against a baseline, and writes the change in each phase. Returns the
+    /// names of phases which got slower by more than `threshold` percent.
+    pub fn compare(&self, baseline: &Benchmark, threshold: f64, output: &mut impl Write) -> io::Result<Vec<String>> {
+        let mut regressions = vec![];
+
+        writeln!(output, "{:<10} {:>12} {:>12} {:>9}", "phase", "baseline ms", "median ms", "change")?;
+        for (name, stats) in &self.phases {
+            let Some((_, before)) = baseline.phases.iter().find(|(n, _)| n == name) else { continue };
+
+            let change = (stats.median - before.median) / before.median * 100.0;
+            let regressed = change > threshold;
+            writeln!(
+                output, "{name:<10} {:>12.3} {:>12.3} {change:>+8.1}%{}",
+                before.median, stats.median, if regressed { "  REGRESSION" } else { "" },
+            )?;
+
+            if regressed {
+                regressions.push(name.clone());
+            }
+        }
+
+        Ok(regressions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, fs::{remove_file, write}};
+
+    use super::*;
+
+    fn stats(median: f64) -> Stats {
+        Stats { mean: median, median, min: median, stddev: 0.0 }
+    }
+
+    fn benchmark(medians: [f64; 2]) -> Benchmark {
+        Benchmark { runs: 3, phases: vec![("parse".to_owned(), stats(medians[0])), ("execute".to_owned(), stats(medians[1]))] }
+    }
+
+    #[test]
+    fn statistics() {
+        let millis = |ms: &[u64]| ms.iter().map(|m| Duration::from_millis(*m)).collect::<Vec<_>>();
+        assert_eq!(Stats::from_samples(&millis(&[4, 2, 6])), Stats { mean: 4.0, median: 4.0, min: 2.0, stddev: (8.0f64 / 3.0).sqrt() });
+        assert_eq!(Stats::from_samples(&millis(&[5, 1, 3, 7])).median, 4.0);
+    }
+
+    #[test]
+    fn baselines_round_trip_through_json() {
+        let original = benchmark([1.5, 20.25]);
+        let parsed = Benchmark::from_json(&Json::parse(&original.to_json().to_string()).unwrap()).unwrap();
+        assert_eq!(parsed.runs, 3);
+        assert_eq!(parsed.phases.len(), 2);
+        for (name, stats) in &original.phases {
+            assert!(parsed.phases.contains(&(name.clone(), *stats)), "missing {name}");
+        }
+
+        let invalid = Json::parse(r#"{ "runs": 3, "phases": { "parse": { "mean": 1 } } }"#).unwrap();
+        assert_eq!(Benchmark::from_json(&invalid).unwrap_err().to_string(), "invalid benchmark baseline");
+    }
+
+    #[test]
+    fn comparing_flags_regressions() {
+        let baseline = benchmark([10.0, 100.0]);
+        let mut output = vec![];
+        let regressions = benchmark([10.5, 120.0]).compare(&baseline, 10.0, &mut output).unwrap();
+        assert_eq!(regressions, vec!["execute"]);
+        assert_eq!(String::from_utf8(output).unwrap(), "\
+phase       baseline ms    median ms    change
+parse            10.000       10.500     +5.0%
+execute         100.000      120.000    +20.0%  REGRESSION
+");
+    }
+
+    #[test]
+    fn running() {
+        let code_path = temp_dir().join(format!("stk-bench-{}.stk", std::process::id()));
+        write(&code_path, "[ 1 , 2 ] sum println").unwrap();
+        let code_path = code_path.to_string_lossy().to_string();
+
+        let benchmark = Benchmark::run(&code_path, None, 1, 2);
+        let no_runs = Benchmark::run(&code_path, None, 1, 0);
+        remove_file(&code_path).unwrap();
+
+        let benchmark = benchmark.unwrap();
+        assert_eq!(benchmark.runs, 2);
+        assert_eq!(benchmark.phases.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), ["parse", "stdlib", "execute", "total"]);
+        assert_eq!(no_runs.unwrap_err().to_string(), "need at least one measured run");
+        assert!(Benchmark::run("nonexistent.stk", None, 0, 1).is_err());
+    }
+}
@@ -0,0 +1,1412 @@
+use std::{cell::RefCell, collections::VecDeque, fmt::Debug, rc::Rc};
+
+use crate::{check::Checker, containers::{is_orderable, Heap, Ref}, eval::{ActionCall, Assignment, ExecutionError, Interpreter, Value}, grid::{Grid, Point, ALL_DIRECTIONS, ORTHOGONAL}, json::Json, parser::{Node, StackEffect}, regex::{Captures, Regex}, scan::Template, search::{self, Path}};
+
+/// An action implemented in Rust rather than in code. Builtins are native actions, and embedders
+/// can add their own with [Interpreter::register_action].
+pub trait NativeAction {
+    fn name(&self) -> &str;
+
+    /// Stack effect, written like the inside of a `( ... )` annotation.
+    fn effect(&self) -> &str;
+
+    /// Description for generated documentation.
+    fn doc(&self) -> &str {
+        ""
+    }
+
+    /// Runs the action, popping its inputs from the interpreter's stack and pushing its outputs.
+    fn execute(&self, interpreter: &mut Interpreter, call: ActionCall<'_>) -> Result<(), ExecutionError>;
+}
+
+impl<T: NativeAction + ?Sized> NativeAction for &T {
+    fn name(&self) -> &str { (**self).name() }
+    fn effect(&self) -> &str { (**self).effect() }
+    fn doc(&self) -> &str { (**self).doc() }
+
+    fn execute(&self, interpreter: &mut Interpreter, call: ActionCall<'_>) -> Result<(), ExecutionError> {
+        (**self).execute(interpreter, call)
+    }
+}
+
+/// A [NativeAction] implemented by a closure. See [Interpreter::register_fn].
+pub struct NativeFn<F> {
+    pub name: String,
+    pub effect: String,
+    pub doc: String,
+    pub run: F,
+}
+
+impl<F: Fn(&mut Interpreter, ActionCall<'_>) -> Result<(), ExecutionError>> NativeAction for NativeFn<F> {
+    fn name(&self) -> &str { &self.name }
+    fn effect(&self) -> &str { &self.effect }
+    fn doc(&self) -> &str { &self.doc }
+
+    fn execute(&self, interpreter: &mut Interpreter, call: ActionCall<'_>) -> Result<(), ExecutionError> {
+        (self.run)(interpreter, call)
+    }
+}
+
+/// A native action which is always available.
+///
+/// These are also known to static tools like the checker and documentation generator, which don't
+/// have an [Interpreter] to hand.
+pub struct Builtin {
+    pub name: &'static str,
+
+    /// Stack effect, written like the inside of a `( ... )` annotation.
+    pub effect: &'static str,
+
+    /// Description for generated documentation.
+    pub doc: &'static str,
+
+    pub run: fn(&mut Interpreter, ActionCall<'_>) -> Result<(), ExecutionError>,
+}
+
+impl Builtin {
+    pub fn stack_effect(&self) -> StackEffect {
+        StackEffect::parse(self.effect).expect("invalid builtin stack effect")
+    }
+}
+
+// Builtins have unique names, so they're identified by them
+impl PartialEq for Builtin {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+impl Eq for Builtin {}
+
+impl Debug for Builtin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Builtin").field("name", &self.name).finish_non_exhaustive()
+    }
+}
+
+impl NativeAction for Builtin {
+    fn name(&self) -> &str { self.name }
+    fn effect(&self) -> &str { self.effect }
+    fn doc(&self) -> &str { self.doc }
+
+    fn execute(&self, interpreter: &mut Interpreter, call: ActionCall<'_>) -> Result<(), ExecutionError> {
+        (self.run)(interpreter, call)
+    }
+}
+
+pub const BUILTINS: &[Builtin] = &[
+    // Core machinery
+    Builtin { name: ":", effect: "value binding --", doc: "Assign a value to an unbound binding in the current scope. Errors if the binding is already bound, here or in an outer scope.", run: |i, call| assign(i, call, Assignment::Bind) },
+    Builtin { name: ":=", effect: "value binding --", doc: "Reassign the nearest existing binding with this name, in whichever scope it was bound.", run: |i, call| assign(i, call, Assignment::Reassign) },
+    Builtin { name: ":shadow", effect: "value binding --", doc: "Assign a value to a new binding in the current scope, hiding any binding with the same name in an outer scope until this one ends.", run: |i, call| assign(i, call, Assignment::Shadow) },
+    Builtin { name: ":const", effect: "value binding --", doc: "Like `:`, but the binding can't be reassigned with `:=` or shadowed with `:shadow`.", run: |i, call| assign(i, call, Assignment::Const) },
+    Builtin { name: "::", effect: "block binding --", doc: "Define a user action named after the binding, which executes the block when called.", run: define },
+    Builtin { name: "::memo", effect: "block binding --", doc: "Like `::`, but caches the action's results by the values it consumes, so calling it again with the same arguments skips executing it. The block's effect comes from its annotation, or is inferred if it has none, and it's an error for the action to read any deeper than the values it consumes.", run: define_memo },
+    Builtin { name: "memo-clear", effect: "binding --", doc: "Forget the cached results of the action defined with `::memo` named after the binding.", run: memo_clear },
+    Builtin { name: "#", effect: "block --", doc: "Execute a block. The block's own stack effect applies on top of this.", run: execute },
+    Builtin { name: "true", effect: "-- bool", doc: "Push `true`.", run: |i, _| { i.push(Value::Boolean(true)); Ok(()) } },
+    Builtin { name: "false", effect: "-- bool", doc: "Push `false`.", run: |i, _| { i.push(Value::Boolean(false)); Ok(()) } },
+    Builtin { name: "=", effect: "a b -- bool", doc: "Whether two values are equal. Works on any kind of value, including arrays.", run: equal },
+    Builtin { name: "?", effect: "cond falsey truthy -- chosen", doc: "Pick `truthy` if `cond` is true, else `falsey`. Commonly used with blocks, followed by `#`. If both blocks are annotated, they must change the stack height by the same amount; unannotated branches are only compared by `check`.", run: choose },
+    Builtin { name: "|", effect: "a b -- bool", doc: "Boolean OR.", run: |i, _| boolean_op(i, |a, b| a || b) },
+    Builtin { name: "&", effect: "a b -- bool", doc: "Boolean AND.", run: |i, _| boolean_op(i, |a, b| a && b) },
+    Builtin { name: "!", effect: "bool -- bool", doc: "Boolean NOT.", run: not },
+    Builtin { name: "while", effect: "action cond --", doc: "Repeatedly execute `cond`, which must push a boolean, then execute `action` if it was true. Stops once `cond` is false.", run: while_loop },
+
+    // Basic arithmetic
+    Builtin { name: "+", effect: "a b -- int", doc: "Add two integers.", run: |i, _| integer_op(i, |a, b| Value::Integer(a + b)) },
+    Builtin { name: "-", effect: "a b -- int", doc: "Subtract `b` from `a`.", run: |i, _| integer_op(i, |a, b| Value::Integer(a - b)) },
+    Builtin { name: "*", effect: "a b -- int", doc: "Multiply two integers.", run: |i, _| integer_op(i, |a, b| Value::Integer(a * b)) },
+    Builtin { name: "/", effect: "a b -- int", doc: "Divide `a` by `b`, rounding towards zero.", run: |i, _| integer_op(i, |a, b| Value::Integer(a / b)) },
+
+    // Numeric comparison
+    Builtin { name: ">", effect: "a b -- bool", doc: "Whether `a` is greater than `b`.", run: |i, _| integer_op(i, |a, b| Value::Boolean(a > b)) },
+    Builtin { name: "<", effect: "a b -- bool", doc: "Whether `a` is less than `b`.", run: |i, _| integer_op(i, |a, b| Value::Boolean(a < b)) },
+
+    // Unary arithmetic
+    Builtin { name: "neg", effect: "int -- int", doc: "Negate an integer.", run: |i, _| unary_integer_op(i, |x| -x) },
+    Builtin { name: "abs", effect: "int -- int", doc: "Absolute value of an integer.", run: |i, _| unary_integer_op(i, |x| x.abs()) },
+
+    // Stack unpack
+    Builtin { name: ".", effect: "arr -- a", doc: "Unpack an array of exactly one item onto the stack.", run: unpack },
+    Builtin { name: "..", effect: "arr -- a b", doc: "Unpack an array of exactly two items onto the stack.", run: unpack },
+    Builtin { name: "...", effect: "arr -- a b c", doc: "Unpack an array of exactly three items onto the stack.", run: unpack },
+    Builtin { name: "....", effect: "arr -- a b c d", doc: "Unpack an array of exactly four items onto the stack.", run: unpack },
+    Builtin { name: ".....", effect: "arr -- a b c d e", doc: "Unpack an array of exactly five items onto the stack.", run: unpack },
+    Builtin { name: "......", effect: "arr -- a b c d e f", doc: "Unpack an array of exactly six items onto the stack.", run: unpack },
+
+    // Array operations
+    Builtin { name: "[]", effect: "-- arr", doc: "Push an empty array.", run: |i, _| { i.push(Value::Array(vec![])); Ok(()) } },
+    Builtin { name: "@", effect: "arr index -- item", doc: "Get the item at a zero-based index of an array. Errors if out of range.", run: index },
+    Builtin { name: "length", effect: "arr -- int", doc: "Number of items in an array.", run: length },
+    Builtin { name: "append", effect: "arr item -- arr", doc: "Add an item onto the end of an array.", run: append },
+    Builtin { name: "range", effect: "start end -- arr", doc: "Array of integers from `start` to `end`, inclusive.", run: range },
+    Builtin { name: "map", effect: "arr block -- arr", doc: "Create a new array by executing `block` on each item. The block takes one item and must leave one.", run: map },
+    Builtin { name: "each", effect: "arr block --", doc: "Like `map`, but the block takes one item and leaves nothing, so there's no new array.", run: each },
+    Builtin { name: "++", effect: "a b -- arr", doc: "Concatenate two arrays.", run: concat },
+    Builtin { name: "fold", effect: "arr block acc -- acc", doc: "Reduce an array to a single value. `block` is called with the accumulator and then the item on top, and must leave the new accumulator.", run: fold },
+    Builtin { name: "sort", effect: "arr -- arr", doc: "Sort an array of integers in ascending order.", run: sort },
+    Builtin { name: "shift", effect: "arr -- rest first", doc: "Remove the first item from an array, pushing the rest of the array and then the item.", run: shift },
+    Builtin { name: "break", effect: "arr pred -- arr", doc: "Split an array into sub-arrays wherever `pred` is true for an item. Each matching item is kept, in its own array, between the arrays of items around it.", run: break_on },
+    Builtin { name: "reverse", effect: "arr -- arr", doc: "Reverse an array.", run: reverse },
+
+    // String operations
+    Builtin { name: "lines", effect: "str -- arr", doc: "Split a string on newlines.", run: lines },
+    Builtin { name: "wsplit", effect: "str -- arr", doc: "Split a string on runs of whitespace, discarding empty parts.", run: wsplit },
+    Builtin { name: "int", effect: "str -- int", doc: "Parse a string as an integer. Errors if it isn't one.", run: int },
+    Builtin { name: "ints", effect: "str -- arr", doc: "Find every integer in a string, ignoring anything between them. A `-` directly before digits makes the integer negative, unless it comes straight after another integer, as in ranges like `3-5`.", run: ints },
+    Builtin { name: "split", effect: "arr delim -- arr", doc: "Split an array, usually a string, wherever the delimiter array appears. Keeps empty parts.", run: split },
+    Builtin { name: "join", effect: "arr delim -- arr", doc: "Concatenate an array of arrays, usually strings, with the delimiter array between each.", run: join },
+    Builtin { name: "trim", effect: "str -- str", doc: "Remove whitespace from both ends of a string.", run: |i, _| string_op(i, |s| s.trim().to_owned()) },
+    Builtin { name: "trim-start", effect: "str -- str", doc: "Remove whitespace from the start of a string.", run: |i, _| string_op(i, |s| s.trim_start().to_owned()) },
+    Builtin { name: "trim-end", effect: "str -- str", doc: "Remove whitespace from the end of a string.", run: |i, _| string_op(i, |s| s.trim_end().to_owned()) },
+    Builtin { name: "upper", effect: "str -- str", doc: "Convert a string to uppercase.", run: |i, _| string_op(i, |s| s.to_uppercase()) },
+    Builtin { name: "lower", effect: "str -- str", doc: "Convert a string to lowercase.", run: |i, _| string_op(i, |s| s.to_lowercase()) },
+    Builtin { name: "chars", effect: "str -- arr", doc: "Split a string into an array of one-character strings.", run: chars },
+    Builtin { name: "str", effect: "value -- str", doc: "Convert any value to a string, the same way `print` would show it.", run: to_str },
+    Builtin { name: "starts-with?", effect: "arr prefix -- bool", doc: "Whether an array, usually a string, starts with the items of another.", run: |i, _| subarray_op(i, |a, b| a.starts_with(b)) },
+    Builtin { name: "ends-with?", effect: "arr suffix -- bool", doc: "Whether an array, usually a string, ends with the items of another.", run: |i, _| subarray_op(i, |a, b| a.ends_with(b)) },
+    Builtin { name: "contains?", effect: "arr sub -- bool", doc: "Whether the items of `sub` appear consecutively anywhere in an array, usually a string.", run: |i, _| subarray_op(i, |a, b| find(a, b, 0).is_some()) },
+    Builtin { name: "replace", effect: "arr from to -- arr", doc: "Replace every non-overlapping occurrence of `from` in an array, usually a string, with `to`.", run: replace },
+    Builtin { name: "pad-left", effect: "arr width fill -- arr", doc: "Add `fill` items to the start of an array, usually a string, until it has at least `width` items.", run: |i, _| pad(i, true) },
+    Builtin { name: "pad-right", effect: "arr width fill -- arr", doc: "Add `fill` items to the end of an array, usually a string, until it has at least `width` items.", run: |i, _| pad(i, false) },
+
+    // Character operations
+    Builtin { name: "digit?", effect: "char -- bool", doc: "Whether a character is a decimal digit.", run: is_digit },
+
+    // Grids
+    Builtin { name: "grid", effect: "str -- grid", doc: "Make a grid of characters from lines of text, which must all be the same length. Grids print as text, one row per line. Points in a grid are `[x y]`, counting from `[0 0]` at the top left.", run: grid },
+    Builtin { name: "grid-width", effect: "grid -- int", doc: "Number of columns in a grid.", run: |i, _| grid_size(i, |g| g.width()) },
+    Builtin { name: "grid-height", effect: "grid -- int", doc: "Number of rows in a grid.", run: |i, _| grid_size(i, |g| g.height()) },
+    Builtin { name: "grid-get", effect: "grid point -- value", doc: "Get the value at a point. Errors if it's out of bounds.", run: grid_get },
+    Builtin { name: "grid-set", effect: "grid point value -- grid", doc: "Replace the value at a point. Errors if it's out of bounds.", run: grid_set },
+    Builtin { name: "in-bounds?", effect: "grid point -- bool", doc: "Whether a point is inside a grid.", run: in_bounds },
+    Builtin { name: "neighbours4", effect: "grid point -- arr", doc: "The points above, right of, below and left of a point, leaving out any outside the grid.", run: |i, _| neighbours(i, &ORTHOGONAL) },
+    Builtin { name: "neighbours8", effect: "grid point -- arr", doc: "All eight points around a point including diagonals, clockwise from above, leaving out any outside the grid.", run: |i, _| neighbours(i, &ALL_DIRECTIONS) },
+    Builtin { name: "find-all", effect: "grid value -- arr", doc: "Every point in a grid holding a value, in reading order.", run: find_all },
+    Builtin { name: "grid-rows", effect: "grid -- arr", doc: "The rows of a grid, as arrays.", run: grid_rows },
+    Builtin { name: "grid-row", effect: "grid y -- arr", doc: "One row of a grid, as an array.", run: |i, _| grid_line(i, Grid::row, "row") },
+    Builtin { name: "grid-column", effect: "grid x -- arr", doc: "One column of a grid, as an array from top to bottom.", run: |i, _| grid_line(i, Grid::column, "column") },
+    Builtin { name: "grid-diagonals", effect: "grid -- arr", doc: "Every diagonal of a grid: those running down and right, from the bottom left corner to the top right, then those running down and left, from the top left corner to the bottom right.", run: grid_diagonals },
+    Builtin { name: "grid-ray", effect: "grid point direction -- arr", doc: "The values from a point onwards, stepping by the `[dx dy]` direction until leaving the grid.", run: grid_ray },
+    Builtin { name: "flood-fill", effect: "grid point -- arr", doc: "The points connected to a point through neighbours above, below, left or right with the same value, including the point itself.", run: flood_fill },
+    Builtin { name: "regions", effect: "grid -- arr", doc: "Split a grid into regions of connected points with the same value, like `flood-fill`. Each region is an array of points.", run: regions },
+
+    // Containers
+    Builtin { name: "heap", effect: "-- heap", doc: "Push an empty heap, a priority queue which gives out items with the lowest priority first. Priorities can be integers, characters, booleans or arrays of them. Items with equal priorities come out in the order they went in.", run: |i, _| { i.push(Value::Heap(Rc::new(Heap::default()))); Ok(()) } },
+    Builtin { name: "heap-push", effect: "heap item priority -- heap", doc: "Add an item to a heap with a priority.", run: heap_push },
+    Builtin { name: "heap-pop", effect: "heap -- heap item priority", doc: "Remove the item with the lowest priority from a heap. Errors if the heap is empty.", run: heap_pop },
+    Builtin { name: "heap-peek", effect: "heap -- heap item priority", doc: "Get the item with the lowest priority from a heap, without removing it. Errors if the heap is empty.", run: heap_peek },
+    Builtin { name: "heap-size", effect: "heap -- int", doc: "Number of items in a heap.", run: heap_size },
+    Builtin { name: "array-to-heap", effect: "arr -- heap", doc: "Make a heap from an array of `[item priority]` pairs.", run: array_to_heap },
+    Builtin { name: "heap-to-array", effect: "heap -- arr", doc: "Every item in a heap as `[item priority]` pairs, in the order they'd be popped.", run: heap_to_array },
+    Builtin { name: "deque", effect: "-- deque", doc: "Push an empty deque, a queue which can be added to and removed from at either end in constant time. Use one end as a stack, or both as a queue.", run: |i, _| { i.push(Value::Deque(Rc::new(VecDeque::new()))); Ok(()) } },
+    Builtin { name: "push-front", effect: "deque item -- deque", doc: "Add an item to the front of a deque.", run: |i, _| deque_push(i, VecDeque::push_front) },
+    Builtin { name: "push-back", effect: "deque item -- deque", doc: "Add an item to the back of a deque.", run: |i, _| deque_push(i, VecDeque::push_back) },
+    Builtin { name: "pop-front", effect: "deque -- deque item", doc: "Remove the item at the front of a deque. Errors if the deque is empty.", run: |i, _| deque_pop(i, VecDeque::pop_front) },
+    Builtin { name: "pop-back", effect: "deque -- deque item", doc: "Remove the item at the back of a deque. Errors if the deque is empty.", run: |i, _| deque_pop(i, VecDeque::pop_back) },
+    Builtin { name: "peek-front", effect: "deque -- deque item", doc: "Get the item at the front of a deque, without removing it. Errors if the deque is empty.", run: |i, _| deque_peek(i, VecDeque::front) },
+    Builtin { name: "peek-back", effect: "deque -- deque item", doc: "Get the item at the back of a deque, without removing it. Errors if the deque is empty.", run: |i, _| deque_peek(i, VecDeque::back) },
+    Builtin { name: "deque-size", effect: "deque -- int", doc: "Number of items in a deque.", run: deque_size },
+    Builtin { name: "array-to-deque", effect: "arr -- deque", doc: "Make a deque from an array, with the first item at the front.", run: array_to_deque },
+    Builtin { name: "deque-to-array", effect: "deque -- arr", doc: "Every item in a deque, from front to back.", run: deque_to_array },
+    Builtin { name: "ref", effect: "value -- ref", doc: "Push a new ref, a mutable cell holding a value. Copies of a ref all share the same cell, so a change made through one is seen through every other, even in bindings.", run: new_ref },
+    Builtin { name: "deref", effect: "ref -- value", doc: "The value currently held by a ref.", run: deref },
+    Builtin { name: "set!", effect: "ref value --", doc: "Replace the value held by a ref.", run: set_ref },
+    Builtin { name: "update!", effect: "ref block --", doc: "Replace the value held by a ref with the result of executing a block on it. The block must take one value and leave one.", run: update_ref },
+
+    // Graph search
+    Builtin { name: "bfs", effect: "start neighbours goal -- result", doc: "Breadth-first search for a path with the fewest steps. `neighbours` takes a state and leaves an array of the states next to it, and `goal` takes a state and leaves whether it's the goal. States can be any values. Leaves `[distance path]`, where `path` is every state from the start to the goal, or `[]` if the goal can't be reached.", run: bfs },
+    Builtin { name: "dfs", effect: "start neighbours goal -- result", doc: "Depth-first search for any path, exploring neighbours in the order they're given. Takes and leaves the same as `bfs`.", run: dfs },
+    Builtin { name: "dijkstra", effect: "start neighbours goal -- result", doc: "Dijkstra's algorithm, searching for the cheapest path. Like `bfs`, except `neighbours` leaves an array of `[state cost]` pairs, and the distance is the total cost. Costs can't be negative.", run: dijkstra },
+    Builtin { name: "astar", effect: "start neighbours goal heuristic -- result", doc: "A* search for the cheapest path, like `dijkstra`, guided by `heuristic`, which takes a state and leaves an estimate of the cost from it to the goal. The estimate must never be too high, or the path found might not be the cheapest.", run: astar },
+
+    // Regular expressions
+    Builtin { name: "re", effect: "str -- regex", doc: "Compile a regular expression, so it can be reused without compiling it again. The other `re-` actions also accept patterns as strings.", run: re },
+    Builtin { name: "re-match?", effect: "str pattern -- bool", doc: "Whether a regular expression matches anywhere in a string.", run: re_is_match },
+    Builtin { name: "re-find", effect: "str pattern -- match", doc: "Find the first match of a regular expression in a string, or `[]` if there isn't one. A match is an array of `[text offset]` pairs for each capture group, starting with the whole match as group 0. Groups which didn't take part in the match have an empty string and offset `-1`.", run: re_find },
+    Builtin { name: "re-find-all", effect: "str pattern -- arr", doc: "Find every non-overlapping match of a regular expression in a string, in the same format as `re-find`.", run: re_find_all },
+    Builtin { name: "re-replace", effect: "str pattern replacement -- str", doc: "Replace every match of a regular expression in a string. `$0` to `$9` in the replacement insert the text of a capture group, and `$$` inserts a `$`.", run: re_replace },
+
+    // Templates
+    Builtin { name: "scan", effect: "str template -- arr", doc: "Match a whole string against a template like `\"{int}-{int} {char}: {str}\"`, and push the value of each placeholder. `{int}` is an integer, `{char}` a character, `{word}` a run of non-whitespace and `{str}` any text. Errors if the string doesn't match.", run: scan },
+    Builtin { name: "scan-all", effect: "str template -- arr", doc: "Find every non-overlapping match of a template in a string, pushing an array of the placeholder values for each.", run: scan_all },
+
+    // JSON
+    Builtin { name: "json-parse", effect: "str -- value", doc: "Parse a JSON document. Numbers must be integers, strings become character arrays, and objects become arrays of `[key value]` pairs. `null` isn't supported.", run: json_parse },
+    Builtin { name: "json-stringify", effect: "value -- str", doc: "Serialize a value as compact JSON. Character arrays become strings, so empty arrays are always `[]`. Blocks and bindings can't be serialized.", run: json_stringify },
+
+    // I/O
+    Builtin { name: "print", effect: "value --", doc: "Print a value, without a trailing newline.", run: print },
+    Builtin { name: "println", effect: "value --", doc: "Print a value, followed by a newline.", run: println },
+    Builtin { name: "debug", effect: "--", doc: "Print the entire stack, top first.", run: |i, _| i.print_stack_debug().map_err(|e| ExecutionError::new(e.to_string())) },
+];
+
+/// Looks up a builtin action by name.
+pub fn builtin(name: &str) -> Option<&'static Builtin> {
+    BUILTINS.iter().find(|b| b.name == name)
+}
+
+// Core machinery
+
+fn assign(interpreter: &mut Interpreter, call: ActionCall<'_>, assignment: Assignment) -> Result<(), ExecutionError> {
+    let target = interpreter.pop()?;
+    let Value::Unbound(name) = target else {
+        return Err(ExecutionError::new(format!("bind target `{target}` is not a binding")))
+    };
+
+    let value = interpreter.pop()?;
+    interpreter.assign(&name, value, assignment, call.target.unwrap_or(call.loc))
+}
+
+fn define(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let name = pop_action_name(interpreter)?;
+    let block = interpreter.pop()?.into_block()?;
+    interpreter.define_action(&name, block)
+}
+
+fn define_memo(interpreter: &mut Interpreter, call: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let name = pop_action_name(interpreter)?;
+    let block = interpreter.pop()?.into_block()?;
+
+    let (inputs, outputs) = match block.stack_effect() {
+        Some(effect) => (effect.inputs.len(), effect.outputs.len()),
+        None => {
+            // Infer it, knowing about every other action defined so far
+            let mut checker = Checker::new();
+            for (other, body) in interpreter.user_actions() {
+                checker.add_definition(other, body, &body.loc);
+            }
+            checker.add_definition(&name, &block, call.loc);
+            checker.effect_of(&name).ok_or_else(|| ExecutionError::new(format!(
+                "can't work out which values `{name}` consumes, to cache its results by; add a `( ... -- ... )` annotation to its body"
+            )))?
+        },
+    };
+
+    interpreter.define_memoized_action(&name, block, inputs, outputs)
+}
+
+fn memo_clear(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let name = pop_action_name(interpreter)?;
+    interpreter.clear_memo(&name)
+}
+
+/// Pops a binding naming an action, like the `$name` in `{ ... } $name ::`, and returns the name
+/// without its `$`.
+fn pop_action_name(interpreter: &mut Interpreter) -> Result<String, ExecutionError> {
+    let target = interpreter.pop()?;
+    let Value::Unbound(name) = target else {
+        return Err(ExecutionError::new(format!("bind target `{target}` is not a binding; has it already been assigned?")))
+    };
+    Ok(name.strip_prefix('$').unwrap().to_owned())
+}
+
+fn execute(interpreter: &mut Interpreter, call: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let block = interpreter.pop()?.into_block()?;
+    match block.stack_effect() {
+        Some(effect) => {
+            let (inputs, outputs) = (effect.inputs.len(), effect.outputs.len());
+            interpreter.execute_block_checked(&block, inputs, outputs, "annotated block", call)
+        },
+        None => interpreter.execute_block(&block, call),
+    }
+}
+
+fn equal(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let b = interpreter.pop()?;
+    let a = interpreter.pop()?;
+
+    interpreter.push(Value::Boolean(a == b));
+    Ok(())
+}
+
+fn choose(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let if_truthy = interpreter.pop()?;
+    let if_falsey = interpreter.pop()?;
+    let cond = interpreter.pop()?.into_boolean()?;
+
+    // Only the chosen branch runs, so the other can't be measured. Annotated branches can at least
+    // be compared, though, and the rest are left to the static checker
+    if let (Value::Block(falsey), Value::Block(truthy)) = (&if_falsey, &if_truthy)
+        && let (Some(falsey), Some(truthy)) = (falsey.stack_effect(), truthy.stack_effect())
+        && falsey.outputs.len() as isize - falsey.inputs.len() as isize != truthy.outputs.len() as isize - truthy.inputs.len() as isize {
+        return Err(ExecutionError::new(format!(
+            "unbalanced `?` branches: falsey branch is annotated `{falsey}`, but truthy branch is annotated `{truthy}`"
+        )));
+    }
+
+    if cond {
+        interpreter.push(if_truthy);
+    } else {
+        interpreter.push(if_falsey);
+    }
+    Ok(())
+}
+
+fn boolean_op(interpreter: &mut Interpreter, op: fn(bool, bool) -> bool) -> Result<(), ExecutionError> {
+    let b = interpreter.pop()?.into_boolean()?;
+    let a = interpreter.pop()?.into_boolean()?;
+
+    interpreter.push(Value::Boolean(op(a, b)));
+    Ok(())
+}
+
+fn not(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let x = interpreter.pop()?.into_boolean()?;
+    interpreter.push(Value::Boolean(!x));
+    Ok(())
+}
+
+fn while_loop(interpreter: &mut Interpreter, call: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let cond = interpreter.pop()?.into_block()?;
+    let action = interpreter.pop()?.into_block()?;
+
+    loop {
+        interpreter.execute_block_checked(&cond, 0, 1, "condition block passed to `while`", call)?;
+        let b = interpreter.pop()?.into_boolean()?;
+
+        if b {
+            interpreter.execute_block_checked(&action, 0, 0, "action block passed to `while`", call)?;
+        } else {
+            break
+        }
+    }
+    Ok(())
+}
+
+// Arithmetic and comparison
+
+fn integer_op(interpreter: &mut Interpreter, op: fn(isize, isize) -> Value) -> Result<(), ExecutionError> {
+    let b = interpreter.pop()?.into_integer()?;
+    let a = interpreter.pop()?.into_integer()?;
+    interpreter.push(op(a, b));
+    Ok(())
+}
+
+fn unary_integer_op(interpreter: &mut Interpreter, op: fn(isize) -> isize) -> Result<(), ExecutionError> {
+    let i = interpreter.pop()?.into_integer()?;
+    interpreter.push(Value::Integer(op(i)));
+    Ok(())
+}
+
+// Stack unpack
+
+fn unpack(interpreter: &mut Interpreter, call: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let name = call.name;
+    let a = interpreter.pop()?.into_array()?;
+    let expected_count = name.len();
+
+    if expected_count != a.len() {
+        return Err(ExecutionError::new(format!("unpack action `{name}` expected {expected_count} items but got {}", a.len())))
+    }
+
+    for item in a {
+        interpreter.push(item);
+    }
+    Ok(())
+}
+
+// Array operations
+
+fn index(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let index = interpreter.pop()?.into_integer()?;
+    let mut arr = interpreter.pop()?.into_array()?;
+
+    if index < 0 || index >= arr.len() as isize {
+        return Err(ExecutionError::new(format!("index out of range `{index}`")))
+    }
+
+    interpreter.push(arr.remove(index as usize));
+    Ok(())
+}
+
+fn length(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let arr = interpreter.pop()?.into_array()?;
+    interpreter.push(Value::Integer(arr.len() as isize));
+    Ok(())
+}
+
+fn append(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let v = interpreter.pop()?;
+    let mut arr = interpreter.pop()?.into_array()?;
+    arr.push(v);
+
+    interpreter.push(Value::Array(arr));
+    Ok(())
+}
+
+fn range(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let end = interpreter.pop()?.into_integer()?;
+    let start = interpreter.pop()?.into_integer()?;
+
+    interpreter.push(Value::Array(
+        (start..=end).map(Value::Integer).collect()
+    ));
+    Ok(())
+}
+
+fn map(interpreter: &mut Interpreter, call: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let op = interpreter.pop()?.into_block()?;
+    let arr = interpreter.pop()?.into_array()?;
+
+    let mut new_arr = vec![];
+    for item in arr {
+        interpreter.push(item);
+        interpreter.execute_block_checked(&op, 1, 1, "block passed to `map`", call)?;
+        new_arr.push(interpreter.pop()?);
+    }
+
+    interpreter.push(Value::Array(new_arr));
+    Ok(())
+}
+
+fn each(interpreter: &mut Interpreter, call: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let op = interpreter.pop()?.into_block()?;
+    let arr = interpreter.pop()?.into_array()?;
+
+    for item in arr {
+        interpreter.push(item);
+        interpreter.execute_block_checked(&op, 1, 0, "block passed to `each`", call)?;
+    }
+    Ok(())
+}
+
+fn concat(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let b = interpreter.pop()?.into_array()?;
+    let a = interpreter.pop()?.into_array()?;
+
+    interpreter.push(Value::Array([a, b].concat()));
+    Ok(())
+}
+
+fn fold(interpreter: &mut Interpreter, call: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let mut acc = interpreter.pop()?;
+    let op = interpreter.pop()?.into_block()?; // called with array item on top, then acc
+    let arr = interpreter.pop()?.into_array()?;
+
+    for item in arr {
+        interpreter.push(acc);
+        interpreter.push(item);
+        interpreter.execute_block_checked(&op, 2, 1, "block passed to `fold`", call)?;
+        acc = interpreter.pop()?;
+    }
+
+    interpreter.push(acc);
+    Ok(())
+}
+
+fn sort(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let mut arr = interpreter.pop()?.into_integer_array()?;
+    arr.sort();
+
+    interpreter.push(Value::Array(
+        arr.into_iter().map(Value::Integer).collect()
+    ));
+    Ok(())
+}
+
+fn shift(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let mut arr = interpreter.pop()?.into_array()?;
+    let first = arr.remove(0);
+
+    interpreter.push(Value::Array(arr));
+    interpreter.push(first);
+    Ok(())
+}
+
+fn break_on(interpreter: &mut Interpreter, call: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let pred = interpreter.pop()?.into_block()?;
+    let arr = interpreter.pop()?.into_array()?;
+
+    // Create a new array whenever an item matches the predicate
+    // but KEEP the item which satisfied the predicate
+    // (That's why we're called `break` and not `split`, though I don't think it's a
+    //  great name...)
+    let mut result = vec![vec![]];
+    for item in arr {
+        // Invoke predicate
+        interpreter.push(item.clone());
+        interpreter.execute_block_checked(&pred, 1, 1, "predicate block passed to `break`", call)?;
+        let is_delimiter = interpreter.pop()?.into_boolean()?;
+
+        if is_delimiter {
+            // Delimiter: add new array containing just this
+            // (Wrapped in an array so you can `map` over the broken array and treat
+            //  all items in the same way)
+            result.push(vec![item]);
+
+            // ...then start new list for non-delimiters
+            result.push(vec![]);
+        } else {
+            // Non-delimiter: just keep adding onto the last bit
+            result.last_mut().unwrap().push(item);
+        }
+    }
+
+    // Push result
+    interpreter.push(
+        Value::Array(result.into_iter().map(Value::Array).collect())
+    );
+    Ok(())
+}
+
+fn reverse(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let mut arr = interpreter.pop()?.into_array()?;
+    arr.reverse();
+    interpreter.push(Value::Array(arr));
+    Ok(())
+}
+
+// String operations
+// Where it makes sense, these work on any array, with strings as the common case
+
+fn lines(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let s = interpreter.pop()?.into_string()?;
+
+    interpreter.push(Value::Array(
+        s.split("\n")
+            .map(Value::from_string)
+            .collect()
+    ));
+    Ok(())
+}
+
+fn wsplit(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let s = interpreter.pop()?.into_string()?;
+
+    interpreter.push(Value::Array(
+        s.split_ascii_whitespace()
+            .map(Value::from_string)
+            .collect()
+    ));
+    Ok(())
+}
+
+fn int(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let s = interpreter.pop()?.into_string()?;
+    match s.parse() {
+        Ok(i) => interpreter.push(Value::Integer(i)),
+        Err(_) => return Err(ExecutionError::new(format!("not convertible to integer: `{s}`"))),
+    }
+    Ok(())
+}
+
+fn ints(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let chars = interpreter.pop()?.into_string()?.chars().collect::<Vec<_>>();
+
+    let mut result = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let after_digit = i > 0 && chars[i - 1].is_ascii_digit();
+        let negative = chars[i] == '-' && !after_digit && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit());
+        if !negative && !chars[i].is_ascii_digit() {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        i += 1;
+        while i < chars.len() && chars[i].is_ascii_digit() {
+            i += 1;
+        }
+
+        let text = chars[start..i].iter().collect::<String>();
+        let n = text.parse().map_err(|_| ExecutionError::new(format!("integer out of range: `{text}`")))?;
+        result.push(Value::Integer(n));
+    }
+
+    interpreter.push(Value::Array(result));
+    Ok(())
+}
+
+/// The index of the first occurrence of `needle` in `haystack`, starting the search at `from`.
+fn find(haystack: &[Value], needle: &[Value], from: usize) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(from);
+    }
+    haystack.get(from..)?
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|i| i + from)
+}
+
+fn split(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let delim = interpreter.pop()?.into_array()?;
+    let arr = interpreter.pop()?.into_array()?;
+    if delim.is_empty() {
+        return Err(ExecutionError::new("can't split on an empty delimiter"));
+    }
+
+    let mut parts = vec![];
+    let mut start = 0;
+    while let Some(i) = find(&arr, &delim, start) {
+        parts.push(Value::Array(arr[start..i].to_vec()));
+        start = i + delim.len();
+    }
+    parts.push(Value::Array(arr[start..].to_vec()));
+
+    interpreter.push(Value::Array(parts));
+    Ok(())
+}
+
+fn join(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let delim = interpreter.pop()?.into_array()?;
+    let parts = interpreter.pop()?.into_array()?;
+
+    let mut result = vec![];
+    for (i, part) in parts.into_iter().enumerate() {
+        if i > 0 {
+            result.extend(delim.iter().cloned());
+        }
+        result.extend(part.into_array()?);
+    }
+
+    interpreter.push(Value::Array(result));
+    Ok(())
+}
+
+fn chars(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let s = interpreter.pop()?.into_string()?;
+    interpreter.push(Value::Array(
+        s.chars().map(|c| Value::Array(vec![Value::Char(c)])).collect()
+    ));
+    Ok(())
+}
+
+fn to_str(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let value = interpreter.pop()?;
+    interpreter.push(Value::from_string(&value.to_string()));
+    Ok(())
+}
+
+fn replace(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let to = interpreter.pop()?.into_array()?;
+    let from = interpreter.pop()?.into_array()?;
+    let arr = interpreter.pop()?.into_array()?;
+    if from.is_empty() {
+        return Err(ExecutionError::new("can't replace an empty array"));
+    }
+
+    let mut result = vec![];
+    let mut start = 0;
+    while let Some(i) = find(&arr, &from, start) {
+        result.extend_from_slice(&arr[start..i]);
+        result.extend(to.iter().cloned());
+        start = i + from.len();
+    }
+    result.extend_from_slice(&arr[start..]);
+
+    interpreter.push(Value::Array(result));
+    Ok(())
+}
+
+fn pad(interpreter: &mut Interpreter, left: bool) -> Result<(), ExecutionError> {
+    let fill = interpreter.pop()?;
+    let width = interpreter.pop()?.into_integer()?;
+    let mut arr = interpreter.pop()?.into_array()?;
+
+    let padding = (width.max(0) as usize).saturating_sub(arr.len());
+    let fills = std::iter::repeat_n(fill, padding);
+    if left {
+        arr.splice(0..0, fills);
+    } else {
+        arr.extend(fills);
+    }
+
+    interpreter.push(Value::Array(arr));
+    Ok(())
+}
+
+fn string_op(interpreter: &mut Interpreter, op: fn(&str) -> String) -> Result<(), ExecutionError> {
+    let s = interpreter.pop()?.into_string()?;
+    interpreter.push(Value::from_string(&op(&s)));
+    Ok(())
+}
+
+fn subarray_op(interpreter: &mut Interpreter, op: fn(&[Value], &[Value]) -> bool) -> Result<(), ExecutionError> {
+    let b = interpreter.pop()?.into_array()?;
+    let a = interpreter.pop()?.into_array()?;
+    interpreter.push(Value::Boolean(op(&a, &b)));
+    Ok(())
+}
+
+// Character operations
+
+fn is_digit(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let c = interpreter.pop()?.into_char()?;
+    interpreter.push(Value::Boolean(c.is_ascii_digit()));
+    Ok(())
+}
+
+// Grids
+
+fn points_to_value(points: Vec<Point>) -> Value {
+    Value::Array(points.into_iter().map(Value::from_point).collect())
+}
+
+fn grid(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let text = interpreter.pop()?.into_string()?;
+    interpreter.push(Value::Grid(Rc::new(Grid::parse(&text)?)));
+    Ok(())
+}
+
+fn grid_size(interpreter: &mut Interpreter, size: fn(&Grid) -> usize) -> Result<(), ExecutionError> {
+    let grid = interpreter.pop()?.into_grid()?;
+    interpreter.push(Value::Integer(size(&grid) as isize));
+    Ok(())
+}
+
+fn grid_get(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let point = interpreter.pop()?.into_point()?;
+    let grid = interpreter.pop()?.into_grid()?;
+
+    let Some(value) = grid.get(point) else {
+        return Err(ExecutionError::new(format!("point {} is outside the {}x{} grid", Value::from_point(point), grid.width(), grid.height())));
+    };
+    interpreter.push(value.clone());
+    Ok(())
+}
+
+fn grid_set(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let value = interpreter.pop()?;
+    let point = interpreter.pop()?.into_point()?;
+    let mut grid = interpreter.pop()?.into_grid()?;
+
+    // Only copies the grid if something else still holds it
+    if !Rc::make_mut(&mut grid).set(point, value) {
+        return Err(ExecutionError::new(format!("point {} is outside the {}x{} grid", Value::from_point(point), grid.width(), grid.height())));
+    }
+    interpreter.push(Value::Grid(grid));
+    Ok(())
+}
+
+fn in_bounds(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let point = interpreter.pop()?.into_point()?;
+    let grid = interpreter.pop()?.into_grid()?;
+    interpreter.push(Value::Boolean(grid.in_bounds(point)));
+    Ok(())
+}
+
+fn neighbours(interpreter: &mut Interpreter, offsets: &[Point]) -> Result<(), ExecutionError> {
+    let point = interpreter.pop()?.into_point()?;
+    let grid = interpreter.pop()?.into_grid()?;
+    interpreter.push(points_to_value(grid.neighbours(point, offsets)));
+    Ok(())
+}
+
+fn find_all(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let value = interpreter.pop()?;
+    let grid = interpreter.pop()?.into_grid()?;
+    interpreter.push(points_to_value(grid.find_all(&value)));
+    Ok(())
+}
+
+fn grid_rows(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let grid = interpreter.pop()?.into_grid()?;
+    interpreter.push(Value::Array(grid.rows().into_iter().map(Value::Array).collect()));
+    Ok(())
+}
+
+fn grid_line(interpreter: &mut Interpreter, line: fn(&Grid, usize) -> Option<Vec<Value>>, kind: &str) -> Result<(), ExecutionError> {
+    let index = interpreter.pop()?.into_integer()?;
+    let grid = interpreter.pop()?.into_grid()?;
+
+    let Some(values) = usize::try_from(index).ok().and_then(|i| line(&grid, i)) else {
+        return Err(ExecutionError::new(format!("{kind} {index} is outside the {}x{} grid", grid.width(), grid.height())));
+    };
+    interpreter.push(Value::Array(values));
+    Ok(())
+}
+
+fn grid_diagonals(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let grid = interpreter.pop()?.into_grid()?;
+    interpreter.push(Value::Array(grid.diagonals().into_iter().map(Value::Array).collect()));
+    Ok(())
+}
+
+fn grid_ray(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let direction = interpreter.pop()?.into_point()?;
+    let start = interpreter.pop()?.into_point()?;
+    let grid = interpreter.pop()?.into_grid()?;
+    if direction == (0, 0) {
+        return Err(ExecutionError::new("ray direction can't be `[0 0]`"));
+    }
+
+    interpreter.push(Value::Array(grid.ray(start, direction)));
+    Ok(())
+}
+
+fn flood_fill(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let point = interpreter.pop()?.into_point()?;
+    let grid = interpreter.pop()?.into_grid()?;
+    interpreter.push(points_to_value(grid.flood_fill(point)));
+    Ok(())
+}
+
+fn regions(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let grid = interpreter.pop()?.into_grid()?;
+    interpreter.push(Value::Array(grid.regions().into_iter().map(points_to_value).collect()));
+    Ok(())
+}
+
+// Containers
+// These modify containers in place when nothing else holds them, such as when they're only ever on
+// the stack, but have to copy them first if they're also bound to a name
+
+fn heap_push(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let priority = interpreter.pop()?;
+    let item = interpreter.pop()?;
+    let mut heap = interpreter.pop()?.into_heap()?;
+
+    if !is_orderable(&priority) {
+        return Err(ExecutionError::new(format!("heap priority `{priority}` can't be ordered")));
+    }
+    Rc::make_mut(&mut heap).push(item, priority);
+    interpreter.push(Value::Heap(heap));
+    Ok(())
+}
+
+fn heap_pop(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let mut heap = interpreter.pop()?.into_heap()?;
+    let Some((item, priority)) = Rc::make_mut(&mut heap).pop() else {
+        return Err(ExecutionError::new("can't pop from an empty heap"));
+    };
+
+    interpreter.push(Value::Heap(heap));
+    interpreter.push(item);
+    interpreter.push(priority);
+    Ok(())
+}
+
+fn heap_peek(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let heap = interpreter.pop()?.into_heap()?;
+    let Some((item, priority)) = heap.peek().map(|(i, p)| (i.clone(), p.clone())) else {
+        return Err(ExecutionError::new("can't peek into an empty heap"));
+    };
+
+    interpreter.push(Value::Heap(heap));
+    interpreter.push(item);
+    interpreter.push(priority);
+    Ok(())
+}
+
+fn heap_size(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let heap = interpreter.pop()?.into_heap()?;
+    interpreter.push(Value::Integer(heap.len() as isize));
+    Ok(())
+}
+
+fn array_to_heap(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let pairs = interpreter.pop()?.into_array()?;
+
+    let mut heap = Heap::default();
+    for pair in pairs {
+        let Ok([item, priority]) = <[Value; 2]>::try_from(pair.into_array()?) else {
+            return Err(ExecutionError::new("heap items must be `[item priority]` pairs"));
+        };
+        if !heap.push(item, priority.clone()) {
+            return Err(ExecutionError::new(format!("heap priority `{priority}` can't be ordered")));
+        }
+    }
+
+    interpreter.push(Value::Heap(Rc::new(heap)));
+    Ok(())
+}
+
+fn heap_to_array(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let heap = interpreter.pop()?.into_heap()?;
+    interpreter.push(Value::Array(
+        heap.to_sorted_vec().into_iter().map(|(item, priority)| Value::Array(vec![item, priority])).collect()
+    ));
+    Ok(())
+}
+
+fn deque_push(interpreter: &mut Interpreter, push: fn(&mut VecDeque<Value>, Value)) -> Result<(), ExecutionError> {
+    let item = interpreter.pop()?;
+    let mut deque = interpreter.pop()?.into_deque()?;
+    push(Rc::make_mut(&mut deque), item);
+    interpreter.push(Value::Deque(deque));
+    Ok(())
+}
+
+fn deque_pop(interpreter: &mut Interpreter, pop: fn(&mut VecDeque<Value>) -> Option<Value>) -> Result<(), ExecutionError> {
+    let mut deque = interpreter.pop()?.into_deque()?;
+    let Some(item) = pop(Rc::make_mut(&mut deque)) else {
+        return Err(ExecutionError::new("can't pop from an empty deque"));
+    };
+
+    interpreter.push(Value::Deque(deque));
+    interpreter.push(item);
+    Ok(())
+}
+
+fn deque_peek(interpreter: &mut Interpreter, peek: fn(&VecDeque<Value>) -> Option<&Value>) -> Result<(), ExecutionError> {
+    let deque = interpreter.pop()?.into_deque()?;
+    let Some(item) = peek(&deque).cloned() else {
+        return Err(ExecutionError::new("can't peek into an empty deque"));
+    };
+
+    interpreter.push(Value::Deque(deque));
+    interpreter.push(item);
+    Ok(())
+}
+
+fn deque_size(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let deque = interpreter.pop()?.into_deque()?;
+    interpreter.push(Value::Integer(deque.len() as isize));
+    Ok(())
+}
+
+fn array_to_deque(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let items = interpreter.pop()?.into_array()?;
+    interpreter.push(Value::Deque(Rc::new(items.into())));
+    Ok(())
+}
+
+fn deque_to_array(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let deque = interpreter.pop()?.into_deque()?;
+    interpreter.push(Value::Array(deque.iter().cloned().collect()));
+    Ok(())
+}
+
+// Refs are the exception: they're always shared, and modified in place for every holder
+
+fn new_ref(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let value = interpreter.pop()?;
+    interpreter.push(Value::Ref(Ref::new(value)));
+    Ok(())
+}
+
+fn deref(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let r = interpreter.pop()?.into_ref()?;
+    interpreter.push(r.get());
+    Ok(())
+}
+
+fn set_ref(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let value = interpreter.pop()?;
+    let r = interpreter.pop()?.into_ref()?;
+    r.set(value);
+    Ok(())
+}
+
+fn update_ref(interpreter: &mut Interpreter, call: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let block = interpreter.pop()?.into_block()?;
+    let r = interpreter.pop()?.into_ref()?;
+
+    interpreter.push(r.get());
+    interpreter.execute_block_checked(&block, 1, 1, "block passed to `update!`", call)?;
+    r.set(interpreter.pop()?);
+    Ok(())
+}
+
+// Graph search
+
+/// Executes a block on a state, expecting it to leave one value.
+fn call_block(interpreter: &RefCell<&mut Interpreter>, block: &Node, state: &Value, description: &str, call: ActionCall<'_>) -> Result<Value, ExecutionError> {
+    let mut interpreter = interpreter.borrow_mut();
+    interpreter.push(state.clone());
+    interpreter.execute_block_checked(block, 1, 1, description, call)?;
+    interpreter.pop()
+}
+
+fn path_to_value(path: Option<Path>) -> Value {
+    match path {
+        Some(path) => Value::Array(vec![Value::Integer(path.cost), Value::Array(path.states)]),
+        None => Value::Array(vec![]),
+    }
+}
+
+fn pop_search_blocks(interpreter: &mut Interpreter) -> Result<(Value, Rc<Node>, Rc<Node>), ExecutionError> {
+    let goal = interpreter.pop()?.into_block()?;
+    let neighbours = interpreter.pop()?.into_block()?;
+    let start = interpreter.pop()?;
+    Ok((start, neighbours, goal))
+}
+
+type UnweightedSearch = fn(Value, &mut search::Neighbours, &mut search::Predicate) -> Result<Option<Path>, ExecutionError>;
+
+fn unweighted_search(interpreter: &mut Interpreter, call: ActionCall<'_>, search: UnweightedSearch) -> Result<(), ExecutionError> {
+    let (start, neighbours, goal) = pop_search_blocks(interpreter)?;
+    let neighbours_description = format!("neighbours block passed to `{}`", call.name);
+    let goal_description = format!("goal block passed to `{}`", call.name);
+
+    let cell = RefCell::new(interpreter);
+    let path = search(
+        start,
+        &mut |state| call_block(&cell, &neighbours, state, &neighbours_description, call)?.into_array(),
+        &mut |state| call_block(&cell, &goal, state, &goal_description, call)?.into_boolean(),
+    )?;
+
+    cell.into_inner().push(path_to_value(path));
+    Ok(())
+}
+
+fn bfs(interpreter: &mut Interpreter, call: ActionCall<'_>) -> Result<(), ExecutionError> {
+    unweighted_search(interpreter, call, search::bfs)
+}
+
+fn dfs(interpreter: &mut Interpreter, call: ActionCall<'_>) -> Result<(), ExecutionError> {
+    unweighted_search(interpreter, call, search::dfs)
+}
+
+/// Converts the result of a weighted neighbours block to `(state, cost)` pairs.
+fn into_weighted_neighbours(value: Value) -> Result<Vec<(Value, isize)>, ExecutionError> {
+    value.into_array()?
+        .into_iter()
+        .map(|pair| match pair.into_array()?.as_slice() {
+            [state, Value::Integer(cost)] => Ok((state.clone(), *cost)),
+            _ => Err(ExecutionError::new("weighted neighbours must be `[state cost]` pairs, with an integer cost")),
+        })
+        .collect()
+}
+
+fn dijkstra(interpreter: &mut Interpreter, call: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let (start, neighbours, goal) = pop_search_blocks(interpreter)?;
+
+    let cell = RefCell::new(interpreter);
+    let path = search::dijkstra(
+        start,
+        &mut |state| into_weighted_neighbours(call_block(&cell, &neighbours, state, "neighbours block passed to `dijkstra`", call)?),
+        &mut |state| call_block(&cell, &goal, state, "goal block passed to `dijkstra`", call)?.into_boolean(),
+    )?;
+
+    cell.into_inner().push(path_to_value(path));
+    Ok(())
+}
+
+fn astar(interpreter: &mut Interpreter, call: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let heuristic = interpreter.pop()?.into_block()?;
+    let (start, neighbours, goal) = pop_search_blocks(interpreter)?;
+
+    let cell = RefCell::new(interpreter);
+    let path = search::astar(
+        start,
+        &mut |state| into_weighted_neighbours(call_block(&cell, &neighbours, state, "neighbours block passed to `astar`", call)?),
+        &mut |state| call_block(&cell, &goal, state, "goal block passed to `astar`", call)?.into_boolean(),
+        &mut |state| call_block(&cell, &heuristic, state, "heuristic block passed to `astar`", call)?.into_integer(),
+    )?;
+
+    cell.into_inner().push(path_to_value(path));
+    Ok(())
+}
+
+// Regular expressions
+
+/// Pops a regex, compiling it first if it's a string.
+fn pop_regex(interpreter: &mut Interpreter) -> Result<Rc<Regex>, ExecutionError> {
+    match interpreter.pop()? {
+        Value::Regex(regex) => Ok(regex),
+        value => {
+            let pattern = value.into_string()?;
+            let regex = Regex::new(&pattern).map_err(|e| ExecutionError::new(format!("{e}, in `{pattern}`")))?;
+            Ok(Rc::new(regex))
+        },
+    }
+}
+
+fn pop_chars(interpreter: &mut Interpreter) -> Result<Vec<char>, ExecutionError> {
+    Ok(interpreter.pop()?.into_string()?.chars().collect())
+}
+
+/// Converts a match to `[text offset]` pairs for each group.
+fn captures_to_value(text: &[char], captures: Captures) -> Value {
+    Value::Array(
+        captures.into_iter()
+            .map(|group| match group {
+                Some((start, end)) => Value::Array(vec![
+                    Value::Array(text[start..end].iter().copied().map(Value::Char).collect()),
+                    Value::Integer(start as isize),
+                ]),
+                None => Value::Array(vec![Value::Array(vec![]), Value::Integer(-1)]),
+            })
+            .collect()
+    )
+}
+
+fn re(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let regex = pop_regex(interpreter)?;
+    interpreter.push(Value::Regex(regex));
+    Ok(())
+}
+
+fn re_is_match(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let regex = pop_regex(interpreter)?;
+    let text = pop_chars(interpreter)?;
+    interpreter.push(Value::Boolean(regex.is_match(&text)));
+    Ok(())
+}
+
+fn re_find(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let regex = pop_regex(interpreter)?;
+    let text = pop_chars(interpreter)?;
+    interpreter.push(match regex.find_at(&text, 0) {
+        Some(captures) => captures_to_value(&text, captures),
+        None => Value::Array(vec![]),
+    });
+    Ok(())
+}
+
+fn re_find_all(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let regex = pop_regex(interpreter)?;
+    let text = pop_chars(interpreter)?;
+    interpreter.push(Value::Array(
+        regex.find_all(&text).into_iter().map(|captures| captures_to_value(&text, captures)).collect()
+    ));
+    Ok(())
+}
+
+fn re_replace(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let replacement = pop_chars(interpreter)?;
+    let regex = pop_regex(interpreter)?;
+    let text = pop_chars(interpreter)?;
+    interpreter.push(Value::Array(regex.replace_all(&text, &replacement).into_iter().map(Value::Char).collect()));
+    Ok(())
+}
+
+// Templates
+
+fn pop_template(interpreter: &mut Interpreter) -> Result<Template, ExecutionError> {
+    let template = interpreter.pop()?.into_string()?;
+    Template::new(&template).map_err(|e| ExecutionError::new(format!("invalid template `{template}`: {e}")))
+}
+
+fn scan(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let template = pop_template(interpreter)?;
+    let text = pop_chars(interpreter)?;
+
+    match template.scan(&text) {
+        Some(values) => {
+            let values = values.map_err(|e| ExecutionError::new(e.to_string()))?;
+            interpreter.push(Value::Array(values));
+            Ok(())
+        },
+        None => {
+            let text = Value::Array(text.into_iter().map(Value::Char).collect());
+            Err(ExecutionError::new(format!("`{}` doesn't match template `{}`", text.summary(60), template.template())))
+        },
+    }
+}
+
+fn scan_all(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let template = pop_template(interpreter)?;
+    let text = pop_chars(interpreter)?;
+
+    let matches = template.scan_all(&text).map_err(|e| ExecutionError::new(e.to_string()))?;
+    interpreter.push(Value::Array(matches.into_iter().map(Value::Array).collect()));
+    Ok(())
+}
+
+// JSON
+
+fn json_parse(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let s = interpreter.pop()?.into_string()?;
+    let json = Json::parse(&s).map_err(|e| ExecutionError::new(e.to_string()))?;
+    interpreter.push(json_to_value(json)?);
+    Ok(())
+}
+
+fn json_stringify(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let value = interpreter.pop()?;
+    interpreter.push(Value::from_string(&value_to_json(value)?.to_string()));
+    Ok(())
+}
+
+fn json_to_value(json: Json) -> Result<Value, ExecutionError> {
+    Ok(match json {
+        Json::Boolean(b) => Value::Boolean(b),
+        Json::Integer(i) => Value::Integer(i),
+        Json::String(s) => Value::from_string(&s),
+        Json::Array(items) => Value::Array(items.into_iter().map(json_to_value).collect::<Result<_, _>>()?),
+        Json::Object(pairs) => Value::Array(
+            pairs.into_iter()
+                .map(|(k, v)| Ok(Value::Array(vec![Value::from_string(&k), json_to_value(v)?])))
+                .collect::<Result<_, ExecutionError>>()?
+        ),
+
+        Json::Float(n) => return Err(ExecutionError::new(format!("JSON number `{n}` is not an integer"))),
+        Json::Null => return Err(ExecutionError::new("JSON `null` has no equivalent value")),
+    })
+}
+
+fn value_to_json(value: Value) -> Result<Json, ExecutionError> {
+    Ok(match value {
+        Value::Boolean(b) => Json::Boolean(b),
+        Value::Integer(i) => Json::Integer(i),
+        Value::Char(c) => Json::String(c.to_string()),
+
+        // Non-empty arrays of only characters are strings
+        Value::Array(items) if !items.is_empty() && items.iter().all(|item| matches!(item, Value::Char(_))) =>
+            Json::String(Value::Array(items).into_string()?),
+        Value::Array(items) => Json::Array(items.into_iter().map(value_to_json).collect::<Result<_, _>>()?),
+
+        Value::Heap(heap) => Json::Array(
+            heap.to_sorted_vec().into_iter()
+                .map(|(item, priority)| Ok(Json::Array(vec![value_to_json(item)?, value_to_json(priority)?])))
+                .collect::<Result<_, ExecutionError>>()?
+        ),
+        Value::Deque(deque) => Json::Array(deque.iter().cloned().map(value_to_json).collect::<Result<_, _>>()?),
+        Value::Grid(grid) => Json::Array(grid.rows().into_iter().map(|row| value_to_json(Value::Array(row))).collect::<Result<_, _>>()?),
+
+        Value::Block(_) | Value::Unbound(_) | Value::Regex(_) | Value::Ref(_) => return Err(ExecutionError::new(format!("can't convert `{value}` to JSON"))),
+    })
+}
+
+// I/O
+
+fn print(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let value = interpreter.pop()?;
+    write!(interpreter.output(), "{value}").map_err(|e| ExecutionError::new(e.to_string()))
+}
+
+fn println(interpreter: &mut Interpreter, _: ActionCall<'_>) -> Result<(), ExecutionError> {
+    let value = interpreter.pop()?;
+    writeln!(interpreter.output(), "{value}").map_err(|e| ExecutionError::new(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{code_to_node, load_stdlib};
+
+    use super::*;
+
+    fn run(code: &str) -> Result<Vec<Value>, ExecutionError> {
+        let mut interpreter = Interpreter::new();
+        interpreter.execute(&load_stdlib().unwrap())?;
+        interpreter.execute(&code_to_node(code, "test").unwrap())?;
+        Ok(interpreter.stack().to_vec())
+    }
+
+    fn error(code: &str) -> String {
+        run(code).unwrap_err().message().to_owned()
+    }
+
+    fn string(s: &str) -> Value {
+        Value::from_string(s)
+    }
+
+    fn strings(items: &[&str]) -> Value {
+        Value::Array(items.iter().map(|s| string(s)).collect())
+    }
+
+    #[test]
+    fn ints() {
+        assert_eq!(run(r#""x=12, y=-3 in 3-5" ints"#).unwrap(), [Value::Array([12, -3, 3, 5].map(Value::Integer).to_vec())]);
+        assert_eq!(error(r#""99999999999999999999" ints"#), "integer out of range: `99999999999999999999`");
+    }
+
+    #[test]
+    fn split_and_join() {
+        assert_eq!(run(r#""a,,b" "," split"#).unwrap(), [strings(&["a", "", "b"])]);
+        assert_eq!(run(r#""a,,b" "," split "-" join"#).unwrap(), [string("a--b")]);
+        assert_eq!(error(r#""a,b" "" split"#), "can't split on an empty delimiter");
+        assert_eq!(error(r#"[ 1 , 2 ] "-" join"#), "expected array, got `Integer(1)`");
+    }
+
+    #[test]
+    fn string_ops() {
+        assert_eq!(run(r#""  Hi  " trim  "  Hi  " trim-start  "  Hi  " trim-end"#).unwrap(), [string("Hi"), string("Hi  "), string("  Hi")]);
+        assert_eq!(run(r#""Hi" upper  "Hi" lower"#).unwrap(), [string("HI"), string("hi")]);
+        assert_eq!(run(r#""héllo" chars"#).unwrap(), [strings(&["h", "é", "l", "l", "o"])]);
+        assert_eq!(run("[ 1 , 2 ] str").unwrap(), [string("[1, 2]")]);
+        assert_eq!(error("[ 1 , 2 ] upper"), "all items in array must be characters");
+    }
+
+    #[test]
+    fn searching() {
+        assert_eq!(
+            run(r#""hello" "he" starts-with?  "hello" "lo" ends-with?  "hello" "ll" contains?  "hello" "x" contains?"#).unwrap(),
+            [true, true, true, false].map(Value::Boolean),
+        );
+        assert_eq!(run(r#""a-b-c" "-" "+" replace"#).unwrap(), [string("a+b+c")]);
+        assert_eq!(run(r#""aaa" "aa" "b" replace"#).unwrap(), [string("ba")]);
+        assert_eq!(error(r#""abc" "" "x" replace"#), "can't replace an empty array");
+        assert_eq!(error(r#"1 "a" starts-with?"#), "expected array, got `Integer(1)`");
+    }
+
+    #[test]
+    fn padding() {
+        assert_eq!(run(r#""7" 3 '0' pad-left  "ab" 4 '.' pad-right  "long" 2 '*' pad-left"#).unwrap(), [string("007"), string("ab.."), string("long")]);
+        assert_eq!(error(r#""7" "3" '0' pad-left"#), "expected integer, got `Array([Char('3')])`");
+    }
+
+    #[test]
+    fn grids() {
+        let grid = r#""ab\ncd" grid"#;
+        assert_eq!(run(&format!("{grid} grid-width  {grid} grid-height")).unwrap(), [Value::Integer(2), Value::Integer(2)]);
+        assert_eq!(run(&format!("{grid} [ 1 , 0 ] grid-get")).unwrap(), [Value::Char('b')]);
+        assert_eq!(run(&format!("{grid} [ 1 , 0 ] 'x' grid-set str")).unwrap(), [string("ax\ncd")]);
+        assert_eq!(run(&format!("{grid} [ 2 , 0 ] in-bounds?")).unwrap(), [Value::Boolean(false)]);
+        assert_eq!(run(&format!("{grid} 1 grid-column  {grid} [ 0 , 0 ] [ 1 , 1 ] grid-ray")).unwrap(), [string("bd"), string("ad")]);
+        assert_eq!(
+            run(&format!("{grid} [ 0 , 0 ] neighbours4")).unwrap(),
+            [Value::Array(vec![Value::from_point((1, 0)), Value::from_point((0, 1))])],
+        );
+
+        assert_eq!(error(r#""ab\nc" grid"#), "grid rows must all be the same length, but row 0 has 2 characters and row 1 has 1");
+        assert_eq!(error(&format!("{grid} [ 2 , 0 ] grid-get")), "point [2, 0] is outside the 2x2 grid");
+        assert_eq!(error(&format!("{grid} [ 0 , 5 ] 'x' grid-set")), "point [0, 5] is outside the 2x2 grid");
+        assert_eq!(error(&format!("{grid} -1 grid-row")), "row -1 is outside the 2x2 grid");
+        assert_eq!(error(&format!("{grid} [ 0 , 0 ] [ 0 , 0 ] grid-ray")), "ray direction can't be `[0 0]`");
+        assert_eq!(error(&format!("{grid} [ 0 ] in-bounds?")), "expected point `[x y]`, got array of 1 items");
+    }
+
+    #[test]
+    fn heaps() {
+        assert_eq!(
+            run("heap 'b' 2 heap-push 'a' 1 heap-push 'c' 2 heap-push  heap-pop pull2 heap-pop pull2 heap-size").unwrap(),
+            [Value::Char('a'), Value::Integer(1), Value::Char('b'), Value::Integer(2), Value::Integer(1)],
+        );
+        assert_eq!(run("[ [ 'x' , 3 ] , [ 'y' , 1 ] ] array-to-heap heap-peek").unwrap()[1..], [Value::Char('y'), Value::Integer(1)]);
+        assert_eq!(
+            run("[ [ 'x' , 3 ] , [ 'y' , 1 ] ] array-to-heap heap-to-array").unwrap(),
+            [Value::Array(vec![
+                Value::Array(vec![Value::Char('y'), Value::Integer(1)]),
+                Value::Array(vec![Value::Char('x'), Value::Integer(3)]),
+            ])],
+        );
+
+        assert_eq!(error("heap heap-pop"), "can't pop from an empty heap");
+        assert_eq!(error("heap heap-peek"), "can't peek into an empty heap");
+        assert_eq!(error("heap 1 { } heap-push"), "heap priority `(block)` can't be ordered");
+        assert_eq!(error("[ [ 1 ] ] array-to-heap"), "heap items must be `[item priority]` pairs");
+    }
+
+    #[test]
+    fn deques() {
+        assert_eq!(
+            run("deque 2 push-back 1 push-front 3 push-back  pop-front swap pop-back swap deque-size").unwrap(),
+            [Value::Integer(1), Value::Integer(3), Value::Integer(1)],
+        );
+        assert_eq!(run("[ 1 , 2 ] array-to-deque peek-front swap peek-back swap deque-to-array").unwrap(), [
+            Value::Integer(1), Value::Integer(2), Value::Array(vec![Value::Integer(1), Value::Integer(2)]),
+        ]);
+
+        // Copies bound to a name aren't changed
+        assert_eq!(run("deque 1 push-back $d :  $d 2 push-back drop  $d deque-size").unwrap(), [Value::Integer(1)]);
+
+        assert_eq!(error("deque pop-back"), "can't pop from an empty deque");
+        assert_eq!(error("deque peek-front"), "can't peek into an empty deque");
+    }
+
+    #[test]
+    fn refs() {
+        assert_eq!(run("1 ref $r :  $r 2 set!  $r deref").unwrap(), [Value::Integer(2)]);
+        assert_eq!(run("1 ref $r :  $r { 10 + } update!  $r deref").unwrap(), [Value::Integer(11)]);
+
+        // Changes made inside a block are seen outside, unlike with plain bindings
+        assert_eq!(run("0 ref $count :  [ 1 , 2 , 3 ] { $item :  $count { $item + } update! } each  $count deref").unwrap(), [Value::Integer(6)]);
+
+        assert_eq!(error("1 deref"), "expected ref, got `Integer(1)`");
+        assert_eq!(error("0 ref { drop } update!"), "block passed to `update!` should consume 1 value(s) and leave 1, but it consumed 1 and left 0");
+    }
+
+    #[test]
+    fn each() {
+        assert_eq!(run("0 $sum :  [ 1 , 2 , 3 ] { $sum + $sum := } each  $sum").unwrap(), [Value::Integer(6)]);
+        assert_eq!(run("[] { drop } each").unwrap(), []);
+
+        let error = run("[ 1 ] { dup } each").unwrap_err();
+        assert_eq!(error.message(), "block passed to `each` should consume 1 value(s) and leave 0, but it consumed 1 and left 2");
+    }
+}
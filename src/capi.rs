@@ -0,0 +1,286 @@
+//! A C ABI over the interpreter, for embedding it in programs not written in Rust.
+//!
+//! The C header is generated from this file by the build script, and is available as [C_HEADER]
+//! or from the `c-header` subcommand.
+//!
+//! Functions which can fail return `false` or `NULL`, after which [stk_last_error] and
+//! [stk_last_error_location] describe what went wrong. Values are passed through the interpreter's
+//! stack: push inputs before executing code, and pop its results afterwards.
+
+use std::{error::Error, ffi::{c_char, CStr, CString}, ptr::null};
+
+use crate::{code_to_node, load_stdlib, parser::SyntaxError, ExecutionError, Interpreter, Loc, Value};
+
+/// The generated C header declaring these functions.
+pub const C_HEADER: &str = include_str!(concat!(env!("OUT_DIR"), "/stk.h"));
+
+/// An interpreter, along with the last error that happened while using it.
+pub struct StkInterpreter {
+    interpreter: Interpreter,
+    last_error: Option<LastError>,
+}
+
+struct LastError {
+    message: CString,
+
+    /// The source name, line and column, if the error has a location.
+    location: Option<(CString, usize, usize)>,
+}
+
+impl StkInterpreter {
+    fn fail(&mut self, error: &dyn Error, loc: Option<&Loc>) {
+        let location = loc.map(|loc| {
+            let (line, col) = loc.line_col();
+            (c_string(&loc.source.name), line, col)
+        });
+        self.last_error = Some(LastError { message: c_string(&error.to_string()), location });
+    }
+
+    /// Records the error from a result, if it's one, and returns whether it succeeded.
+    fn check(&mut self, result: Result<(), ExecutionError>) -> bool {
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                let loc = e.loc().cloned();
+                self.fail(&e, loc.as_ref());
+                false
+            },
+        }
+    }
+
+    /// Like [StkInterpreter::check], but for errors from parsing, which might be [SyntaxError]s.
+    fn check_parse<T>(&mut self, result: Result<T, Box<dyn Error>>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                let loc = e.downcast_ref::<SyntaxError>().map(|e| e.loc.clone());
+                self.fail(e.as_ref(), loc.as_ref());
+                None
+            },
+        }
+    }
+}
+
+/// Converts to a C string, replacing any interior NULs which C couldn't represent.
+fn c_string(s: &str) -> CString {
+    CString::new(s.replace('\0', "\\0")).unwrap()
+}
+
+/// Creates an interpreter, without the stdlib loaded. Free it with `stk_interpreter_free`.
+#[unsafe(no_mangle)]
+pub extern "C" fn stk_interpreter_new() -> *mut StkInterpreter {
+    Box::into_raw(Box::new(StkInterpreter { interpreter: Interpreter::new(), last_error: None }))
+}
+
+/// Frees an interpreter. Does nothing if it's NULL.
+///
+/// # Safety
+/// `interpreter` must be NULL or from `stk_interpreter_new`, and not already freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn stk_interpreter_free(interpreter: *mut StkInterpreter) {
+    unsafe {
+        if !interpreter.is_null() {
+            drop(Box::from_raw(interpreter));
+        }
+    }
+}
+
+/// Loads the stdlib into an interpreter.
+///
+/// # Safety
+/// `interpreter` must be a live interpreter from `stk_interpreter_new`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn stk_load_stdlib(interpreter: *mut StkInterpreter) -> bool {
+    unsafe {
+        let interpreter = &mut *interpreter;
+        let Some(stdlib) = interpreter.check_parse(load_stdlib()) else { return false };
+        let result = interpreter.interpreter.execute(&stdlib);
+        interpreter.check(result)
+    }
+}
+
+/// Parses and executes code. `name` identifies the code in error locations, and may be NULL.
+///
+/// # Safety
+/// `interpreter` must be a live interpreter from `stk_interpreter_new`. `code` must be a
+/// NUL-terminated UTF-8 string, and so must `name` if it isn't NULL.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn stk_execute(interpreter: *mut StkInterpreter, code: *const c_char, name: *const c_char) -> bool {
+    unsafe {
+        let interpreter = &mut *interpreter;
+        let Some(code) = interpreter.check_parse(CStr::from_ptr(code).to_str().map_err(|e| e.into())) else { return false };
+        let name = match name.is_null() {
+            true => "(c)",
+            false => match interpreter.check_parse(CStr::from_ptr(name).to_str().map_err(|e| e.into())) {
+                Some(name) => name,
+                None => return false,
+            },
+        };
+
+        let Some(root) = interpreter.check_parse(code_to_node(code, name)) else { return false };
+        let result = interpreter.interpreter.execute(&root);
+        interpreter.check(result)
+    }
+}
+
+/// How many values are on the stack.
+///
+/// # Safety
+/// `interpreter` must be a live interpreter from `stk_interpreter_new`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn stk_stack_size(interpreter: *mut StkInterpreter) -> usize {
+    unsafe {
+        (*interpreter).interpreter.stack().len()
+    }
+}
+
+/// Pushes an integer onto the stack.
+///
+/// # Safety
+/// `interpreter` must be a live interpreter from `stk_interpreter_new`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn stk_push_integer(interpreter: *mut StkInterpreter, value: isize) {
+    unsafe {
+        (*interpreter).interpreter.push(Value::Integer(value));
+    }
+}
+
+/// Pops an integer from the stack into `out`. Fails if the stack is empty or the top value isn't
+/// an integer, in which case the value is still popped.
+///
+/// # Safety
+/// `interpreter` must be a live interpreter from `stk_interpreter_new`, and `out` must be valid to
+/// write to.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn stk_pop_integer(interpreter: *mut StkInterpreter, out: *mut isize) -> bool {
+    unsafe {
+        let interpreter = &mut *interpreter;
+        let result = interpreter.interpreter.pop().and_then(|v| v.into_integer());
+        interpreter.check(result.map(|i| *out = i))
+    }
+}
+
+/// Pushes a string onto the stack, as an array of characters.
+///
+/// # Safety
+/// `interpreter` must be a live interpreter from `stk_interpreter_new`, and `value` must be a
+/// NUL-terminated UTF-8 string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn stk_push_string(interpreter: *mut StkInterpreter, value: *const c_char) -> bool {
+    unsafe {
+        let interpreter = &mut *interpreter;
+        let Some(value) = interpreter.check_parse(CStr::from_ptr(value).to_str().map_err(|e| e.into())) else { return false };
+        interpreter.interpreter.push(Value::from_string(value));
+        true
+    }
+}
+
+/// Pops a string from the stack. Returns NULL if the stack is empty or the top value isn't a
+/// string, in which case the value is still popped. Free the string with `stk_string_free`.
+///
+/// # Safety
+/// `interpreter` must be a live interpreter from `stk_interpreter_new`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn stk_pop_string(interpreter: *mut StkInterpreter) -> *mut c_char {
+    unsafe {
+        let interpreter = &mut *interpreter;
+        match interpreter.interpreter.pop().and_then(|v| v.into_string()) {
+            Ok(s) => c_string(&s).into_raw(),
+            Err(e) => {
+                interpreter.check(Err(e));
+                std::ptr::null_mut()
+            },
+        }
+    }
+}
+
+/// Frees a string from `stk_pop_string`. Does nothing if it's NULL.
+///
+/// # Safety
+/// `value` must be NULL or from `stk_pop_string`, and not already freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn stk_string_free(value: *mut c_char) {
+    unsafe {
+        if !value.is_null() {
+            drop(CString::from_raw(value));
+        }
+    }
+}
+
+/// Pops `count` values from the stack and pushes them as an array, with the top value last.
+/// Fails if there aren't enough values, in which case the stack is unchanged.
+///
+/// # Safety
+/// `interpreter` must be a live interpreter from `stk_interpreter_new`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn stk_push_array(interpreter: *mut StkInterpreter, count: usize) -> bool {
+    unsafe {
+        let interpreter = &mut *interpreter;
+        let size = interpreter.interpreter.stack().len();
+        if count > size {
+            let error = ExecutionError::new(format!("can't make an array of {count} values from a stack of {size}"));
+            return interpreter.check(Err(error));
+        }
+
+        let mut items = (0..count).map(|_| interpreter.interpreter.pop().unwrap()).collect::<Vec<_>>();
+        items.reverse();
+        interpreter.interpreter.push(Value::Array(items));
+        true
+    }
+}
+
+/// Pops an array from the stack and pushes its items, so that its last item is on top. Writes
+/// the number of items to `count`. Fails if the stack is empty or the top value isn't an array,
+/// in which case the value is still popped.
+///
+/// # Safety
+/// `interpreter` must be a live interpreter from `stk_interpreter_new`, and `count` must be valid
+/// to write to.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn stk_pop_array(interpreter: *mut StkInterpreter, count: *mut usize) -> bool {
+    unsafe {
+        let interpreter = &mut *interpreter;
+        let result = interpreter.interpreter.pop().and_then(|v| v.into_array()).map(|items| {
+            *count = items.len();
+            for item in items {
+                interpreter.interpreter.push(item);
+            }
+        });
+        interpreter.check(result)
+    }
+}
+
+/// The message of the last error, or NULL if nothing has failed yet. Valid until the next call
+/// which fails, or the interpreter is freed.
+///
+/// # Safety
+/// `interpreter` must be a live interpreter from `stk_interpreter_new`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn stk_last_error(interpreter: *mut StkInterpreter) -> *const c_char {
+    unsafe {
+        match &(*interpreter).last_error {
+            Some(error) => error.message.as_ptr(),
+            None => null(),
+        }
+    }
+}
+
+/// Writes the source name, line and column of the last error. Fails if there hasn't been an error,
+/// or it doesn't have a location. The name is valid for as long as `stk_last_error`'s message.
+///
+/// # Safety
+/// `interpreter` must be a live interpreter from `stk_interpreter_new`, and the other arguments
+/// must be valid to write to.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn stk_last_error_location(interpreter: *mut StkInterpreter, name: *mut *const c_char, line: *mut usize, column: *mut usize) -> bool {
+    unsafe {
+        let Some(LastError { location: Some((error_name, error_line, error_column)), .. }) = &(*interpreter).last_error else {
+            return false
+        };
+
+        *name = error_name.as_ptr();
+        *line = *error_line;
+        *column = *error_column;
+        true
+    }
+}
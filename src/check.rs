@@ -0,0 +1,580 @@
+use std::{collections::{HashMap, HashSet}, fmt::Display, rc::Rc};
+
+use crate::{builtins::builtin, loc::Loc, parser::{is_assignment_action, is_definition_action, Node, NodeKind}, token::Atom};
+
+/// How deeply blocks may be inlined into each other before we give up.
+/// Only really matters for pathological code which executes a block from within itself.
+const MAX_INLINE_DEPTH: usize = 64;
+
+/// A problem found by the [Checker].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub loc: Loc,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (line, col) = self.loc.line_col();
+        write!(f, "{}:{line}:{col}: {}", self.loc.source.name, self.message)
+    }
+}
+
+/// A value on the stack, as far as the [Checker] can tell.
+#[derive(Debug, Clone)]
+enum Abstract {
+    /// Some value we don't know anything about.
+    Unknown,
+
+    /// A binding which didn't have a value when it was pushed, so is probably about to be assigned.
+    Unbound(String),
+
+    Block(Rc<Node>),
+
+    /// One of two values, picked by the `?` at the given location.
+    Either(Box<Abstract>, Box<Abstract>, Loc),
+}
+
+/// Signals that checking can't continue past this point, either because something happened which
+/// can't be reasoned about statically (like executing a block we know nothing about), or because
+/// an error has already been reported and continuing would only give confusing follow-on errors.
+struct Indeterminate;
+
+/// The number of items taken from beneath the starting stack, and the number of items which were
+/// left on top of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Inferred {
+    consumed: usize,
+    produced: usize,
+}
+
+/// Abstract interpreter state, mirroring the real [Interpreter](crate::eval::Interpreter).
+#[derive(Clone)]
+struct State {
+    stack: Vec<Abstract>,
+
+    /// How many items have been popped from beneath the stack we started with.
+    consumed: usize,
+
+    /// How many items may be popped from beneath the starting stack before it's an underflow, or
+    /// `None` if we're inferring how many items are consumed.
+    available: Option<usize>,
+
+    binding_frames: Vec<HashMap<String, Abstract>>,
+}
+
+impl State {
+    fn new(available: Option<usize>) -> Self {
+        State {
+            stack: vec![],
+            consumed: 0,
+            available,
+            binding_frames: vec![HashMap::new()],
+        }
+    }
+
+    /// The height of the stack relative to where we started - negative if items were consumed.
+    fn height(&self) -> isize {
+        self.stack.len() as isize - self.consumed as isize
+    }
+
+    fn lookup(&self, name: &str) -> Option<Abstract> {
+        self.binding_frames.iter().rev().find_map(|frame| frame.get(name).cloned())
+    }
+
+    /// Pops an item, or returns `None` if that would underflow.
+    fn pop(&mut self) -> Option<Abstract> {
+        if let Some(value) = self.stack.pop() {
+            return Some(value);
+        }
+
+        // Take from beneath the starting stack, if we're allowed to
+        if let Some(available) = self.available && self.consumed >= available {
+            return None;
+        }
+        self.consumed += 1;
+        Some(Abstract::Unknown)
+    }
+}
+
+/// A user action definition found while scanning the program.
+#[derive(Clone)]
+struct Definition {
+    body: Node,
+
+    /// The location of the `$name` the action was defined with.
+    loc: Loc,
+}
+
+/// Statically checks stack effects by abstractly interpreting programs, tracking how many items
+/// are on the stack but (mostly) not their values.
+///
+/// Stack effects of user actions are taken from their `( ... -- ... )` annotation if they have one,
+/// otherwise they are inferred from their body. Anything the checker can't follow, like executing
+/// a block passed in as an argument, stops checking quietly rather than guessing.
+pub struct Checker {
+    definitions: HashMap<String, Definition>,
+    inferred: HashMap<String, Option<Inferred>>,
+    inferring: HashSet<String>,
+    inline_depth: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Checker {
+    pub fn new() -> Self {
+        Checker {
+            definitions: HashMap::new(),
+            inferred: HashMap::new(),
+            inferring: HashSet::new(),
+            inline_depth: 0,
+            diagnostics: vec![],
+        }
+    }
+
+    /// Finds user action definitions anywhere within a program, so that their effects are known
+    /// when checking calls to them.
+    ///
+    /// Like the interpreter, the first definition of a name wins, so a program redefining a stdlib
+    /// action doesn't change how calls to it are checked. The redefinition will fail at runtime.
+    pub fn add_definitions(&mut self, node: &Node) {
+        for definition in node.definitions() {
+            self.definitions.entry(definition.name.to_owned())
+                .or_insert_with(|| Definition { body: definition.body.clone(), loc: definition.loc.clone() });
+        }
+    }
+
+    /// Adds a single user action definition, such as one which has already been executed. Like
+    /// [Checker::add_definitions], this does nothing if the name is already defined.
+    pub fn add_definition(&mut self, name: &str, body: &Node, loc: &Loc) {
+        self.definitions.entry(name.to_owned())
+            .or_insert_with(|| Definition { body: body.clone(), loc: loc.clone() });
+    }
+
+    /// The number of values a user action consumes and leaves, from its annotation or inferred
+    /// from its body, if that can be worked out.
+    pub fn effect_of(&mut self, name: &str) -> Option<(usize, usize)> {
+        self.action_effect(name).map(|e| (e.consumed, e.produced))
+    }
+
+    /// Checks the bodies of all user actions found by [Checker::add_definitions].
+    pub fn check_actions(&mut self) {
+        let mut names = self.definitions.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        for name in names {
+            self.action_effect(&name);
+        }
+    }
+
+    /// Checks a top-level program, which starts with an empty stack.
+    ///
+    /// If checking stops partway through a statement, because of an error or something which
+    /// can't be followed, it carries on from the next statement with an empty stack, so that one
+    /// problem doesn't hide the rest. A statement ends with a top-level assignment or definition,
+    /// or at a line without any code on it.
+    pub fn check_program(&mut self, node: &Node) {
+        let NodeKind::Sequence(ns) = &node.kind else {
+            let _ = self.run(node, &mut State::new(Some(0)));
+            return;
+        };
+
+        let mut state = State::new(Some(0));
+        let mut stopped = false;
+        for i in 0..ns.len() {
+            if !stopped {
+                stopped = self.run_in_sequence(ns, i, &mut state).is_err();
+            }
+
+            if stopped && ends_statement(&ns[i], ns.get(i + 1)) {
+                // Whatever was bound before is still known
+                state = State { binding_frames: state.binding_frames, ..State::new(Some(0)) };
+                stopped = false;
+            }
+        }
+    }
+
+    /// Consumes the checker and returns everything it found, in source order.
+    pub fn into_diagnostics(mut self) -> Vec<Diagnostic> {
+        // The same block can be analysed several times, e.g. when it's inlined into different
+        // places, so it may have been reported more than once
+        self.diagnostics.sort_by_key(|d| (d.loc.source.name.clone(), d.loc.pos));
+        self.diagnostics.dedup();
+        self.diagnostics
+    }
+
+    fn report(&mut self, loc: &Loc, message: impl Into<String>) {
+        self.diagnostics.push(Diagnostic { message: message.into(), loc: loc.clone() });
+    }
+
+    /// Gets the effect of a user action, checking its body if this is the first time we've seen it.
+    fn action_effect(&mut self, name: &str) -> Option<Inferred> {
+        let definition = self.definitions.get(name)?.clone();
+
+        // Check the body exactly once. If we're already inside it, this is recursion, and we'll
+        // have to rely on the declared effect
+        if !self.inferred.contains_key(name) && !self.inferring.contains(name) {
+            self.inferring.insert(name.to_owned());
+            let result = self.check_action(name, &definition);
+            self.inferring.remove(name);
+            self.inferred.insert(name.to_owned(), result);
+        }
+
+        let declared = definition.body.stack_effect()
+            .map(|e| Inferred { consumed: e.inputs.len(), produced: e.outputs.len() });
+        declared.or_else(|| self.inferred.get(name).copied().flatten())
+    }
+
+    fn check_action(&mut self, name: &str, definition: &Definition) -> Option<Inferred> {
+        let declared = definition.body.stack_effect().cloned();
+
+        let mut state = State::new(declared.as_ref().map(|e| e.inputs.len()));
+        self.run(&definition.body, &mut state).ok()?;
+
+        if let Some(declared) = declared {
+            // Inputs which the body didn't touch are still on the stack afterwards
+            let leaves = declared.inputs.len() - state.consumed + state.stack.len();
+            if leaves != declared.outputs.len() {
+                self.report(&definition.loc, format!(
+                    "`{name}` is declared as `{declared}`, but its body leaves {leaves} value(s) instead of {}",
+                    declared.outputs.len(),
+                ));
+            }
+        }
+
+        Some(Inferred { consumed: state.consumed, produced: state.stack.len() })
+    }
+
+    fn run(&mut self, node: &Node, state: &mut State) -> Result<(), Indeterminate> {
+        match &node.kind {
+            NodeKind::Atom(atom) => match atom {
+                Atom::LiteralInteger(_) | Atom::LiteralChar(_) | Atom::LiteralString(_) => state.stack.push(Abstract::Unknown),
+                Atom::Action(a) => self.run_action(a, &node.loc, state)?,
+                Atom::Binding(b) => {
+                    let value = state.lookup(b).unwrap_or_else(|| Abstract::Unbound(b.clone()));
+                    state.stack.push(value);
+                }
+            }
+
+            NodeKind::Sequence(ns) => {
+                for i in 0..ns.len() {
+                    self.run_in_sequence(ns, i, state)?;
+                }
+            }
+
+            NodeKind::Block(body) => state.stack.push(Abstract::Block(body.clone())),
+
+            NodeKind::StackEffect(_) => (),
+        }
+
+        Ok(())
+    }
+
+    /// Runs the `i`th node of a sequence.
+    fn run_in_sequence(&mut self, ns: &[Node], i: usize, state: &mut State) -> Result<(), Indeterminate> {
+        // Like the interpreter, bindings about to be assigned are pushed by name
+        match (&ns[i].kind, ns.get(i + 1).map(|next| &next.kind)) {
+            (NodeKind::Atom(Atom::Binding(b)), Some(NodeKind::Atom(Atom::Action(a)))) if is_assignment_action(a) => {
+                state.stack.push(Abstract::Unbound(b.clone()));
+                Ok(())
+            },
+            _ => self.run(&ns[i], state),
+        }
+    }
+
+    fn pop(&mut self, state: &mut State, name: &str, loc: &Loc) -> Result<Abstract, Indeterminate> {
+        match state.pop() {
+            Some(value) => Ok(value),
+            None => {
+                self.report(loc, format!("stack underflow: not enough values on the stack for `{name}`"));
+                Err(Indeterminate)
+            }
+        }
+    }
+
+    fn run_action(&mut self, name: &str, loc: &Loc, state: &mut State) -> Result<(), Indeterminate> {
+        match name {
+            ":" | ":=" | ":shadow" | ":const" => {
+                let target = self.pop(state, name, loc)?;
+                let value = self.pop(state, name, loc)?;
+
+                // If the target isn't unbound, we might just not know about its value, so don't
+                // complain - the interpreter will at runtime
+                if let Abstract::Unbound(binding) = target {
+                    let frame = match name {
+                        ":=" => state.binding_frames.iter_mut().rev().find(|frame| frame.contains_key(&binding)),
+                        _ => state.binding_frames.last_mut(),
+                    };
+                    if let Some(frame) = frame {
+                        frame.insert(binding, value);
+                    }
+                }
+            },
+            "#" => {
+                let block = self.pop(state, name, loc)?;
+                self.execute(block, state)?;
+            },
+            "?" => {
+                let if_truthy = self.pop(state, name, loc)?;
+                let if_falsey = self.pop(state, name, loc)?;
+                self.pop(state, name, loc)?;
+
+                state.stack.push(Abstract::Either(Box::new(if_falsey), Box::new(if_truthy), loc.clone()));
+            },
+            "while" => {
+                let cond = self.pop(state, name, loc)?;
+                let action = self.pop(state, name, loc)?;
+
+                self.check_block(&cond, name, 0, 1, state);
+                self.check_block(&action, name, 0, 0, state);
+            },
+            "map" => {
+                let op = self.pop(state, name, loc)?;
+                self.pop(state, name, loc)?;
+
+                self.check_block(&op, name, 1, 1, state);
+                state.stack.push(Abstract::Unknown);
+            },
+            "each" => {
+                let op = self.pop(state, name, loc)?;
+                self.pop(state, name, loc)?;
+
+                self.check_block(&op, name, 1, 0, state);
+            },
+            "fold" => {
+                self.pop(state, name, loc)?;
+                let op = self.pop(state, name, loc)?;
+                self.pop(state, name, loc)?;
+
+                self.check_block(&op, name, 2, 1, state);
+                state.stack.push(Abstract::Unknown);
+            },
+            "update!" => {
+                let op = self.pop(state, name, loc)?;
+                self.pop(state, name, loc)?;
+
+                self.check_block(&op, name, 1, 1, state);
+            },
+            "break" => {
+                let pred = self.pop(state, name, loc)?;
+                self.pop(state, name, loc)?;
+
+                self.check_block(&pred, name, 1, 1, state);
+                state.stack.push(Abstract::Unknown);
+            },
+
+            _ => {
+                // Builtins take priority over user actions, just like in the interpreter
+                let effect = if let Some(builtin) = builtin(name) {
+                    let effect = builtin.stack_effect();
+                    Inferred { consumed: effect.inputs.len(), produced: effect.outputs.len() }
+                } else {
+                    // Unknown actions, or ones we couldn't infer the effect of - give up
+                    self.action_effect(name).ok_or(Indeterminate)?
+                };
+
+                for _ in 0..effect.consumed {
+                    self.pop(state, name, loc)?;
+                }
+                for _ in 0..effect.produced {
+                    state.stack.push(Abstract::Unknown);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Executes a block value on the current stack, like `#` does.
+    fn execute(&mut self, value: Abstract, state: &mut State) -> Result<(), Indeterminate> {
+        match value {
+            Abstract::Block(body) => {
+                if self.inline_depth >= MAX_INLINE_DEPTH {
+                    return Err(Indeterminate);
+                }
+
+                self.inline_depth += 1;
+                state.binding_frames.push(HashMap::new());
+                let result = self.run(&body, state);
+                state.binding_frames.pop();
+                self.inline_depth -= 1;
+
+                result
+            },
+
+            Abstract::Either(if_falsey, if_truthy, choice_loc) => {
+                // Try both branches, and make sure they agree
+                let start_height = state.height();
+                let mut falsey_state = state.clone();
+                let mut truthy_state = state.clone();
+                let falsey_result = self.execute(*if_falsey, &mut falsey_state);
+                let truthy_result = self.execute(*if_truthy, &mut truthy_state);
+
+                match (falsey_result, truthy_result) {
+                    (Ok(()), Ok(())) => {
+                        if falsey_state.height() != truthy_state.height() {
+                            self.report(&choice_loc, format!(
+                                "unbalanced `?` branches: falsey branch changes the stack height by {:+}, but truthy branch changes it by {:+}",
+                                falsey_state.height() - start_height,
+                                truthy_state.height() - start_height,
+                            ));
+                            return Err(Indeterminate);
+                        }
+                        // Carry on from whichever branch reached deeper, so that what's inferred
+                        // to be consumed covers both
+                        *state = if truthy_state.consumed > falsey_state.consumed { truthy_state } else { falsey_state };
+                    },
+
+                    // If only one branch could be followed, it's probably the base case of some
+                    // recursion, so carry on assuming that the branches are balanced
+                    (Ok(()), Err(_)) => *state = falsey_state,
+                    (Err(_), Ok(())) => *state = truthy_state,
+                    (Err(_), Err(_)) => return Err(Indeterminate),
+                }
+
+                Ok(())
+            },
+
+            Abstract::Unknown | Abstract::Unbound(_) => Err(Indeterminate),
+        }
+    }
+
+    /// Checks that a block passed to a combinator like `map` takes the given number of items and
+    /// leaves the right number behind. The block isn't executed on the current stack.
+    fn check_block(&mut self, value: &Abstract, combinator: &str, inputs: usize, outputs: usize, state: &State) {
+        match value {
+            Abstract::Block(body) => {
+                let mut block_state = State {
+                    stack: vec![Abstract::Unknown; inputs],
+                    consumed: 0,
+                    available: None, // Allowed to dig into the outer stack, so long as it's balanced
+                    binding_frames: state.binding_frames.clone(),
+                };
+                block_state.binding_frames.push(HashMap::new());
+
+                if self.run(body, &mut block_state).is_err() {
+                    return;
+                }
+
+                let leaves = block_state.height();
+                if leaves < 0 {
+                    self.report(&body.loc, format!(
+                        "block passed to `{combinator}` should take {inputs} value(s) and leave {outputs}, but it consumes {} more from the stack beneath",
+                        -leaves,
+                    ));
+                } else if leaves != outputs as isize {
+                    self.report(&body.loc, format!(
+                        "block passed to `{combinator}` should take {inputs} value(s) and leave {outputs}, but it leaves {leaves}",
+                    ));
+                }
+            },
+
+            Abstract::Either(a, b, _) => {
+                self.check_block(a, combinator, inputs, outputs, state);
+                self.check_block(b, combinator, inputs, outputs, state);
+            },
+
+            Abstract::Unknown | Abstract::Unbound(_) => (),
+        }
+    }
+}
+
+/// Whether a top-level statement ends after a node, because it assigns or defines something, or
+/// because there's a line without any code on it before the next node.
+fn ends_statement(node: &Node, next: Option<&Node>) -> bool {
+    if let NodeKind::Atom(Atom::Action(a)) = &node.kind && (is_assignment_action(a) || is_definition_action(a)) {
+        return true;
+    }
+
+    next.is_some_and(|next| {
+        let end = node.loc.range().end;
+        let gap = Loc::new(node.loc.source.clone(), end, next.loc.pos - end);
+        gap.contents().matches('\n').count() >= 2
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{code_to_node, load_stdlib};
+
+    use super::*;
+
+    /// Checks a program along with the stdlib, and returns the diagnostics as they'd be printed.
+    fn check(code: &str) -> Vec<String> {
+        let stdlib = load_stdlib().unwrap();
+        let program = code_to_node(code, "test").unwrap();
+
+        let mut checker = Checker::new();
+        checker.add_definitions(&stdlib);
+        checker.add_definitions(&program);
+        checker.check_actions();
+        checker.check_program(&program);
+        checker.into_diagnostics().iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn underflow_is_reported() {
+        assert_eq!(check("1 +"), vec!["test:1:3: stack underflow: not enough values on the stack for `+`"]);
+        assert_eq!(check("1 2 + println"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn checking_resumes_at_the_next_statement() {
+        let code = "\
+1 +
+2 +
+
+2 3 + println
++ 1 $x :
+4 $x + println
+";
+        assert_eq!(check(code), vec![
+            "test:1:3: stack underflow: not enough values on the stack for `+`",
+            "test:5:1: stack underflow: not enough values on the stack for `+`",
+        ]);
+    }
+
+    #[test]
+    fn effects_are_inferred() {
+        let program = code_to_node("{ + 1 } $f ::\n{ swap drop } $g ::", "test").unwrap();
+        let mut checker = Checker::new();
+        checker.add_definitions(&load_stdlib().unwrap());
+        checker.add_definitions(&program);
+        assert_eq!(checker.effect_of("f"), Some((2, 2)));
+        assert_eq!(checker.effect_of("g"), Some((2, 1)));
+        assert_eq!(checker.effect_of("h"), None);
+    }
+
+    #[test]
+    fn annotations_must_match_bodies() {
+        assert_eq!(check("{ ( a -- b ) drop } $f ::"), vec![
+            "test:1:21: `f` is declared as `( a -- b )`, but its body leaves 0 value(s) instead of 1",
+        ]);
+        assert_eq!(check("{ ( a -- b ) 1 + } $f ::"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn choice_branches_must_agree() {
+        assert_eq!(check("1 true { drop } { } ? #"), vec![
+            "test:1:21: unbalanced `?` branches: falsey branch changes the stack height by -1, but truthy branch changes it by +0",
+        ]);
+        assert_eq!(check("1 true { 1 + } { 2 + } ? # println"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn redefinitions_dont_replace_stdlib_effects() {
+        let stdlib = load_stdlib().unwrap();
+        let program = code_to_node("{ ( -- ) } $dup ::\n1 dup + println", "test").unwrap();
+
+        let mut checker = Checker::new();
+        checker.add_definitions(&stdlib);
+        checker.add_definitions(&program);
+        checker.check_actions();
+        checker.check_program(&program);
+        assert_eq!(checker.into_diagnostics(), vec![]);
+    }
+}
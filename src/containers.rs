@@ -0,0 +1,249 @@
+//! Container values beyond arrays: a priority queue ([Heap]) and a double-ended queue, used by the
+//! `heap-*` and `deque-*` builtins, and mutable reference cells ([Ref]).
+
+use std::{cell::RefCell, cmp::{Ordering, Reverse}, collections::BinaryHeap, fmt::{Debug, Display}, hash::{Hash, Hasher}, rc::Rc};
+
+use crate::eval::Value;
+
+/// Compares two values, if they can be ordered.
+///
+/// Integers, characters and booleans are ordered naturally, and arrays lexicographically. Values
+/// of different kinds are ordered by kind, in that order, so any two orderable values compare.
+/// Blocks and other values can't be ordered at all.
+pub fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    fn rank(value: &Value) -> Option<u8> {
+        match value {
+            Value::Integer(_) => Some(0),
+            Value::Char(_) => Some(1),
+            Value::Boolean(_) => Some(2),
+            Value::Array(_) => Some(3),
+            _ => None,
+        }
+    }
+
+    match (a, b) {
+        (Value::Integer(a), Value::Integer(b)) => Some(a.cmp(b)),
+        (Value::Char(a), Value::Char(b)) => Some(a.cmp(b)),
+        (Value::Boolean(a), Value::Boolean(b)) => Some(a.cmp(b)),
+        (Value::Array(a), Value::Array(b)) => {
+            for (a, b) in a.iter().zip(b) {
+                match compare(a, b)? {
+                    Ordering::Equal => (),
+                    ordering => return Some(ordering),
+                }
+            }
+            Some(a.len().cmp(&b.len()))
+        },
+        _ => Some(rank(a)?.cmp(&rank(b)?)),
+    }
+}
+
+/// Whether a value can be ordered by [compare].
+pub fn is_orderable(value: &Value) -> bool {
+    compare(value, value).is_some()
+}
+
+/// A min-priority queue. Items with equal priorities come out in the order they went in.
+#[derive(Debug, Clone, Default)]
+pub struct Heap {
+    entries: BinaryHeap<Reverse<HeapEntry>>,
+
+    /// Number of items ever pushed, to order items with equal priorities.
+    pushed: usize,
+}
+
+#[derive(Debug, Clone)]
+struct HeapEntry {
+    priority: Value,
+    sequence: usize,
+    item: Value,
+}
+
+// Priorities are checked with `is_orderable` before they're pushed, so they always compare
+impl Ord for HeapEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare(&self.priority, &other.priority).unwrap()
+            .then(self.sequence.cmp(&other.sequence))
+    }
+}
+impl PartialOrd for HeapEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl PartialEq for HeapEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for HeapEntry {}
+
+impl Heap {
+    /// Adds an item. Returns false, without adding it, if the priority can't be ordered.
+    pub fn push(&mut self, item: Value, priority: Value) -> bool {
+        if !is_orderable(&priority) {
+            return false;
+        }
+
+        self.entries.push(Reverse(HeapEntry { priority, sequence: self.pushed, item }));
+        self.pushed += 1;
+        true
+    }
+
+    /// Removes the item with the lowest priority, returning it and its priority.
+    pub fn pop(&mut self) -> Option<(Value, Value)> {
+        self.entries.pop().map(|Reverse(entry)| (entry.item, entry.priority))
+    }
+
+    /// The item with the lowest priority, and its priority.
+    pub fn peek(&self) -> Option<(&Value, &Value)> {
+        self.entries.peek().map(|Reverse(entry)| (&entry.item, &entry.priority))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Every item and its priority, from lowest priority to highest.
+    pub fn to_sorted_vec(&self) -> Vec<(Value, Value)> {
+        let mut entries = self.entries.iter().map(|Reverse(entry)| entry).collect::<Vec<_>>();
+        entries.sort();
+        entries.into_iter().map(|entry| (entry.item.clone(), entry.priority.clone())).collect()
+    }
+}
+
+// Heaps are equal if they'd give out the same items in the same order
+impl PartialEq for Heap {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_sorted_vec() == other.to_sorted_vec()
+    }
+}
+impl Eq for Heap {}
+
+impl Hash for Heap {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.to_sorted_vec().hash(state);
+    }
+}
+
+/// A mutable cell holding one value, created by `ref`.
+///
+/// Unlike every other value, copying a ref doesn't copy what's inside: all copies share the same
+/// cell, so a change made through one is seen through all of them. Refs are only equal to
+/// themselves, whatever they hold.
+#[derive(Clone)]
+pub struct Ref(Rc<RefCell<Value>>);
+
+impl Ref {
+    pub fn new(value: Value) -> Self {
+        Ref(Rc::new(RefCell::new(value)))
+    }
+
+    /// A copy of the value inside.
+    pub fn get(&self) -> Value {
+        self.0.borrow().clone()
+    }
+
+    pub fn set(&self, value: Value) {
+        *self.0.borrow_mut() = value;
+    }
+
+    /// Writes the value inside, or `...` if this ref is already being written further up, because
+    /// it holds itself somewhere.
+    fn write_contents(&self, f: &mut std::fmt::Formatter<'_>, write: fn(&Value, &mut std::fmt::Formatter<'_>) -> std::fmt::Result) -> std::fmt::Result {
+        // Holding a mutable borrow marks this ref as in progress
+        match self.0.try_borrow_mut() {
+            Ok(value) => write(&value, f),
+            Err(_) => write!(f, "..."),
+        }
+    }
+}
+
+impl PartialEq for Ref {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+impl Eq for Ref {}
+
+impl Hash for Ref {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Rc::as_ptr(&self.0).hash(state);
+    }
+}
+
+impl Display for Ref {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "(ref: ")?;
+        self.write_contents(f, |value, f| write!(f, "{value}"))?;
+        write!(f, ")")
+    }
+}
+
+impl Debug for Ref {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Ref(")?;
+        self.write_contents(f, |value, f| write!(f, "{value:?}"))?;
+        write!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comparing() {
+        let array = |items: &[isize]| Value::Array(items.iter().copied().map(Value::Integer).collect());
+        assert_eq!(compare(&Value::Integer(2), &Value::Integer(10)), Some(Ordering::Less));
+        assert_eq!(compare(&array(&[1, 5]), &array(&[2])), Some(Ordering::Less));
+        assert_eq!(compare(&array(&[1, 2]), &array(&[1])), Some(Ordering::Greater));
+        assert_eq!(compare(&Value::Char('a'), &Value::Integer(100)), Some(Ordering::Greater));
+
+        let unorderable = Value::Unbound("$x".to_owned());
+        assert_eq!(compare(&unorderable, &Value::Integer(1)), None);
+        assert_eq!(compare(&Value::Array(vec![unorderable.clone()]), &Value::Array(vec![Value::Integer(1)])), None);
+        assert!(!is_orderable(&unorderable));
+    }
+
+    #[test]
+    fn heaps() {
+        let mut heap = Heap::default();
+        assert!(heap.push(Value::Char('b'), Value::Integer(2)));
+        assert!(heap.push(Value::Char('a'), Value::Integer(1)));
+        assert!(heap.push(Value::Char('c'), Value::Integer(2)));
+        assert!(!heap.push(Value::Char('x'), Value::Unbound("$x".to_owned())));
+        assert_eq!(heap.len(), 3);
+
+        assert_eq!(heap.peek(), Some((&Value::Char('a'), &Value::Integer(1))));
+        assert_eq!(heap.to_sorted_vec().len(), 3);
+
+        // Equal priorities come out in the order they went in
+        let popped = std::iter::from_fn(|| heap.pop()).map(|(item, _)| item).collect::<Vec<_>>();
+        assert_eq!(popped, [Value::Char('a'), Value::Char('b'), Value::Char('c')]);
+        assert!(heap.is_empty());
+        assert_eq!(heap.pop(), None);
+    }
+
+    #[test]
+    fn refs_are_shared() {
+        let a = Ref::new(Value::Integer(1));
+        let b = a.clone();
+        b.set(Value::Integer(2));
+        assert_eq!(a.get(), Value::Integer(2));
+        assert_eq!(a, b);
+        assert_ne!(a, Ref::new(Value::Integer(2)));
+    }
+
+    #[test]
+    fn refs_holding_themselves_can_be_shown() {
+        let r = Ref::new(Value::Integer(0));
+        r.set(Value::Array(vec![Value::Ref(r.clone())]));
+        assert_eq!(r.to_string(), "(ref: [(ref: ...)])");
+        assert_eq!(format!("{r:?}"), "Ref(Array([Ref(Ref(...))]))");
+    }
+}
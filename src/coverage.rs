@@ -0,0 +1,370 @@
+use std::{cell::RefCell, collections::{BTreeMap, HashMap}, io::{self, Write}, rc::Rc};
+
+use crate::{eval::{ExecutionError, Hook, Interpreter}, loc::Loc, parser::{Node, NodeKind}, token::Atom};
+
+/// Execution counts for the atoms and blocks in one source, by position.
+#[derive(Default)]
+struct SourceCounts {
+    atoms: HashMap<usize, usize>,
+
+    /// Keyed by the position of the block's body.
+    blocks: HashMap<usize, usize>,
+}
+
+/// How many times each atom and block was executed, collected by a [CoverageRecorder].
+#[derive(Default)]
+pub struct Coverage {
+    sources: HashMap<String, SourceCounts>,
+}
+
+impl Coverage {
+    fn counts(&mut self, loc: &Loc) -> &mut SourceCounts {
+        // Avoid allocating the name for every atom
+        if !self.sources.contains_key(&loc.source.name) {
+            self.sources.insert(loc.source.name.clone(), SourceCounts::default());
+        }
+        self.sources.get_mut(&loc.source.name).unwrap()
+    }
+
+    fn atom_count(&self, loc: &Loc) -> usize {
+        self.sources.get(&loc.source.name).and_then(|s| s.atoms.get(&loc.pos)).copied().unwrap_or(0)
+    }
+
+    fn block_count(&self, body: &Node) -> usize {
+        self.sources.get(&body.loc.source.name).and_then(|s| s.blocks.get(&body.loc.pos)).copied().unwrap_or(0)
+    }
+
+    /// Works out the coverage of a parsed source file, using its counts.
+    pub fn report<'a>(&self, root: &'a Node) -> CoverageReport<'a> {
+        let mut report = CoverageReport {
+            root,
+            lines: BTreeMap::new(),
+            branches: vec![],
+            functions: vec![],
+        };
+        self.add_to_report(root, &mut report);
+
+        for definition in root.definitions() {
+            report.functions.push(FunctionCoverage {
+                name: definition.name.to_owned(),
+                line: definition.loc.line_col().0,
+                count: self.block_count(definition.body),
+            });
+        }
+
+        report
+    }
+
+    fn add_to_report(&self, node: &Node, report: &mut CoverageReport<'_>) {
+        match &node.kind {
+            NodeKind::Atom(_) => {
+                let count = self.atom_count(&node.loc);
+                let (line, _) = node.loc.line_col();
+                let line = report.lines.entry(line).or_default();
+                line.count = line.count.max(count);
+                if count == 0 {
+                    line.missed.push(node.loc.clone());
+                }
+            },
+
+            NodeKind::Sequence(items) => {
+                // Conditionals are written `{ if-false } { if-true } ? #`
+                for window in items.windows(4) {
+                    if let [
+                        Node { kind: NodeKind::Block(if_false), .. },
+                        Node { kind: NodeKind::Block(if_true), .. },
+                        Node { kind: NodeKind::Atom(Atom::Action(choose)), loc, .. },
+                        Node { kind: NodeKind::Atom(Atom::Action(execute)), .. },
+                    ] = window && choose == "?" && execute == "#" {
+                        report.branches.push(BranchCoverage {
+                            line: loc.line_col().0,
+                            reached: self.atom_count(loc) > 0,
+                            if_false: self.block_count(if_false),
+                            if_true: self.block_count(if_true),
+                        });
+                    }
+                }
+
+                for item in items {
+                    self.add_to_report(item, report);
+                }
+            },
+
+            NodeKind::Block(body) => self.add_to_report(body, report),
+            NodeKind::StackEffect(_) => (),
+        }
+    }
+}
+
+/// Coverage of one source file, from [Coverage::report].
+pub struct CoverageReport<'a> {
+    root: &'a Node,
+
+    /// Coverage of each line with atoms on it. Lines without any aren't included.
+    pub lines: BTreeMap<usize, LineCoverage>,
+
+    pub branches: Vec<BranchCoverage>,
+
+    /// User actions defined in the source.
+    pub functions: Vec<FunctionCoverage>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LineCoverage {
+    /// The highest number of times any atom on the line was executed.
+    pub count: usize,
+
+    /// Atoms on the line which were never executed.
+    pub missed: Vec<Loc>,
+}
+
+/// A conditional written `{ if-false } { if-true } ? #`.
+#[derive(Debug, Clone)]
+pub struct BranchCoverage {
+    /// The line of the `?`.
+    pub line: usize,
+
+    /// Whether the `?` was executed at all.
+    pub reached: bool,
+
+    /// How many times each block was executed.
+    pub if_false: usize,
+    pub if_true: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctionCoverage {
+    pub name: String,
+
+    /// The line of the definition's `$name`.
+    pub line: usize,
+
+    /// How many times the action was called.
+    pub count: usize,
+}
+
+impl CoverageReport<'_> {
+    fn source_name(&self) -> &str {
+        &self.root.loc.source.name
+    }
+
+    /// A one-line summary, like `code.stk: 120/130 atoms (92.3%), 3/4 branches`.
+    pub fn summary(&self) -> String {
+        let atoms = self.lines.values().map(|l| l.missed.len()).sum::<usize>();
+        let atoms_total = count_atoms(self.root);
+        let atoms_hit = atoms_total - atoms;
+        let percent = if atoms_total == 0 { 100.0 } else { atoms_hit as f64 * 100.0 / atoms_total as f64 };
+
+        format!(
+            "{}: {atoms_hit}/{atoms_total} atoms ({percent:.1}%), {}/{} branches",
+            self.source_name(), self.branches_hit(), self.branches.len() * 2,
+        )
+    }
+
+    /// How many blocks of conditionals were ever chosen. Each conditional has two.
+    fn branches_hit(&self) -> usize {
+        self.branches.iter()
+            .map(|b| (b.if_false > 0) as usize + (b.if_true > 0) as usize)
+            .sum()
+    }
+
+    /// Writes the source with each line prefixed by how many times it was executed, `#####` if it
+    /// never was, or `-` if there's nothing on it to execute. Atoms which weren't executed on
+    /// partially-covered lines are underlined, and the blocks chosen by any conditionals are noted.
+    pub fn write_listing(&self, output: &mut impl Write) -> io::Result<()> {
+        writeln!(output, "==> {}", self.summary())?;
+
+        for (i, text) in self.root.loc.source.contents.lines().enumerate() {
+            let number = i + 1;
+            let Some(line) = self.lines.get(&number) else {
+                writeln!(output, "{:>9} | {text}", "-")?;
+                continue;
+            };
+
+            if line.count == 0 {
+                writeln!(output, "{:>9} | {text}", "#####")?;
+            } else {
+                writeln!(output, "{:>9} | {text}", line.count)?;
+                if !line.missed.is_empty() {
+                    let mut underline = String::new();
+                    for loc in &line.missed {
+                        let (_, col) = loc.line_col();
+                        let len = loc.len.max(1);
+                        underline.extend(std::iter::repeat_n(' ', (col - 1).saturating_sub(underline.chars().count())));
+                        underline.extend(std::iter::repeat_n('#', len));
+                    }
+                    writeln!(output, "{:>9} | {underline}", "")?;
+                }
+            }
+
+            for branch in self.branches.iter().filter(|b| b.line == number && b.reached) {
+                writeln!(
+                    output, "{:>9} | conditional took first block {}, second block {}",
+                    "", times(branch.if_false), times(branch.if_true),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes an LCOV tracefile record, with line, branch and function (user action) coverage.
+    pub fn write_lcov(&self, output: &mut impl Write) -> io::Result<()> {
+        writeln!(output, "TN:")?;
+        writeln!(output, "SF:{}", self.source_name())?;
+
+        for function in &self.functions {
+            writeln!(output, "FN:{},{}", function.line, function.name)?;
+        }
+        for function in &self.functions {
+            writeln!(output, "FNDA:{},{}", function.count, function.name)?;
+        }
+        writeln!(output, "FNF:{}", self.functions.len())?;
+        writeln!(output, "FNH:{}", self.functions.iter().filter(|f| f.count > 0).count())?;
+
+        for (i, branch) in self.branches.iter().enumerate() {
+            for (j, count) in [branch.if_false, branch.if_true].into_iter().enumerate() {
+                let taken = if branch.reached { count.to_string() } else { "-".to_owned() };
+                writeln!(output, "BRDA:{},{i},{j},{taken}", branch.line)?;
+            }
+        }
+        writeln!(output, "BRF:{}", self.branches.len() * 2)?;
+        writeln!(output, "BRH:{}", self.branches_hit())?;
+
+        for (number, line) in &self.lines {
+            writeln!(output, "DA:{number},{}", line.count)?;
+        }
+        writeln!(output, "LF:{}", self.lines.len())?;
+        writeln!(output, "LH:{}", self.lines.values().filter(|l| l.count > 0).count())?;
+
+        writeln!(output, "end_of_record")
+    }
+}
+
+/// A [Hook] which counts how many times each atom and block is executed, recording the results into
+/// a shared [Coverage].
+pub struct CoverageRecorder {
+    coverage: Rc<RefCell<Coverage>>,
+}
+
+impl CoverageRecorder {
+    pub fn new(coverage: Rc<RefCell<Coverage>>) -> Self {
+        CoverageRecorder { coverage }
+    }
+}
+
+impl Hook for CoverageRecorder {
+    fn before_atom(&mut self, _interpreter: &Interpreter, node: &Node) -> Result<(), ExecutionError> {
+        *self.coverage.borrow_mut().counts(&node.loc).atoms.entry(node.loc.pos).or_default() += 1;
+        Ok(())
+    }
+
+    fn before_block(&mut self, _interpreter: &Interpreter, body: &Node) -> Result<(), ExecutionError> {
+        *self.coverage.borrow_mut().counts(&body.loc).blocks.entry(body.loc.pos).or_default() += 1;
+        Ok(())
+    }
+}
+
+fn count_atoms(node: &Node) -> usize {
+    match &node.kind {
+        NodeKind::Atom(_) => 1,
+        NodeKind::Sequence(items) => items.iter().map(count_atoms).sum(),
+        NodeKind::Block(body) => count_atoms(body),
+        NodeKind::StackEffect(_) => 0,
+    }
+}
+
+fn times(count: usize) -> String {
+    match count {
+        0 => "never".to_owned(),
+        1 => "once".to_owned(),
+        n => format!("{n} times"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::code_to_node;
+
+    use super::*;
+
+    /// Runs code while recording coverage, and returns whether it succeeded, the parsed code and
+    /// the coverage.
+    fn cover(code: &str) -> (bool, Node, Coverage) {
+        let root = code_to_node(code, "code.stk").unwrap();
+        let coverage = Rc::new(RefCell::new(Coverage::default()));
+        let mut interpreter = Interpreter::new();
+        interpreter.add_hook(Box::new(CoverageRecorder::new(coverage.clone())));
+        let succeeded = interpreter.execute(&root).is_ok();
+
+        drop(interpreter);
+        (succeeded, root, Rc::into_inner(coverage).unwrap().into_inner())
+    }
+
+    const CONDITIONAL: &str = "\
+{ 0 > { 0 } { 1 } ? # } $positive ::
+5 positive
+{ } $unused ::
+";
+
+    #[test]
+    fn listing() {
+        let (succeeded, root, coverage) = cover(CONDITIONAL);
+        assert!(succeeded);
+
+        let mut listing = vec![];
+        coverage.report(&root).write_listing(&mut listing).unwrap();
+        assert_eq!(String::from_utf8(listing).unwrap(), "\
+==> code.stk: 11/12 atoms (91.7%), 1/2 branches
+        1 | { 0 > { 0 } { 1 } ? # } $positive ::
+          |         #
+          | conditional took first block never, second block once
+        1 | 5 positive
+        1 | { } $unused ::
+");
+    }
+
+    #[test]
+    fn lcov() {
+        let (_, root, coverage) = cover(CONDITIONAL);
+
+        let mut lcov = vec![];
+        coverage.report(&root).write_lcov(&mut lcov).unwrap();
+        assert_eq!(String::from_utf8(lcov).unwrap(), "\
+TN:
+SF:code.stk
+FN:1,positive
+FN:3,unused
+FNDA:1,positive
+FNDA:0,unused
+FNF:2
+FNH:1
+BRDA:1,0,0,0
+BRDA:1,0,1,1
+BRF:2
+BRH:1
+DA:1,1
+DA:2,1
+DA:3,1
+LF:3
+LH:3
+end_of_record
+");
+    }
+
+    #[test]
+    fn lines_after_an_error_are_never_executed() {
+        let (succeeded, root, coverage) = cover("1 2 +\n+ +\n3 4 +");
+        assert!(!succeeded);
+
+        let mut listing = vec![];
+        coverage.report(&root).write_listing(&mut listing).unwrap();
+        assert_eq!(String::from_utf8(listing).unwrap(), "\
+==> code.stk: 4/8 atoms (50.0%), 0/0 branches
+        1 | 1 2 +
+        1 | + +
+          |   #
+    ##### | 3 4 +
+");
+    }
+}
@@ -0,0 +1,118 @@
+use std::error::Error;
+
+use crate::{parser::is_definition_action, token::{Atom, Token, TokenKind}};
+
+/// A node of a concrete syntax tree.
+///
+/// Unlike a [Node](crate::parser::Node), this keeps hold of every token including trivia, so it can
+/// be used to reproduce the original source exactly. It only captures structure - whether the code
+/// is actually valid is still up to the [parser](crate::parser).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CstNode {
+    /// A single token, which may be trivia.
+    Token(Token),
+
+    /// A `{ ... }` block.
+    Block {
+        open: Token,
+        children: Vec<CstNode>,
+        close: Token,
+    },
+
+    /// A `( ... -- ... )` stack effect annotation.
+    StackEffect {
+        open: Token,
+        children: Vec<Token>,
+        close: Token,
+    },
+}
+
+impl CstNode {
+    /// Whether there is a line break anywhere within this node.
+    pub fn is_multiline(&self) -> bool {
+        match self {
+            CstNode::Token(token) => matches!(&token.kind, TokenKind::Whitespace(w) if w.contains('\n')),
+            CstNode::Block { children, .. } => children.iter().any(|c| c.is_multiline()),
+            CstNode::StackEffect { children, .. } =>
+                children.iter().any(|t| matches!(&t.kind, TokenKind::Whitespace(w) if w.contains('\n'))),
+        }
+    }
+}
+
+/// Parses tokens, which should include trivia from
+/// [tokenize_with_trivia](crate::token::tokenize_with_trivia), into a list of top-level CST nodes.
+pub fn parse_cst(mut tokens: Vec<Token>) -> Result<Vec<CstNode>, Box<dyn Error>> {
+    // Reverse tokens so we get a stack which we can pop from
+    tokens.reverse();
+
+    let (nodes, close) = parse_cst_sequence(&mut tokens)?;
+    if close.is_some() {
+        return Err("unexpected end of block while not inside a block".into())
+    }
+
+    Ok(nodes)
+}
+
+/// Parses nodes until the tokens run out, or a closing brace is found. Returns the closing brace
+/// too, if there was one.
+fn parse_cst_sequence(tokens: &mut Vec<Token>) -> Result<(Vec<CstNode>, Option<Token>), Box<dyn Error>> {
+    let mut nodes = vec![];
+
+    while let Some(token) = tokens.pop() {
+        match token.kind {
+            TokenKind::LBrace => {
+                let (children, close) = parse_cst_sequence(tokens)?;
+                let Some(close) = close else {
+                    return Err("ran out of tokens while inside block".into())
+                };
+                nodes.push(CstNode::Block { open: token, children, close });
+            },
+
+            TokenKind::RBrace => return Ok((nodes, Some(token))),
+
+            TokenKind::LParen => {
+                let mut children = vec![];
+                let close = loop {
+                    match tokens.pop() {
+                        Some(close @ Token { kind: TokenKind::RParen, .. }) => break close,
+                        Some(child) => children.push(child),
+                        None => return Err("ran out of tokens while inside stack effect annotation".into()),
+                    }
+                };
+                nodes.push(CstNode::StackEffect { open: token, children, close });
+            },
+
+            _ => nodes.push(CstNode::Token(token)),
+        }
+    }
+
+    Ok((nodes, None))
+}
+
+/// A user action definition, written `{ ... } $name ::`, found within a list of CST nodes.
+pub struct CstDefinition<'a> {
+    /// The index of the block within the list of nodes.
+    pub block_index: usize,
+    pub block: &'a CstNode,
+    pub binding: &'a Token,
+}
+
+/// If the node at `index` is the `::` (or `::memo`) of a `{ ... } $name ::` definition, returns that definition.
+pub fn definition_ending_at(nodes: &[CstNode], index: usize) -> Option<CstDefinition<'_>> {
+    let significant = nodes[..=index].iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, n)| !matches!(n, CstNode::Token(t) if t.is_trivia()))
+        .take(3)
+        .collect::<Vec<_>>();
+
+    match significant.as_slice() {
+        [
+            (end_index, CstNode::Token(Token { kind: TokenKind::Atom(Atom::Action(action)), .. })),
+            (_, CstNode::Token(binding @ Token { kind: TokenKind::Atom(Atom::Binding(_)), .. })),
+            (block_index, block @ CstNode::Block { .. }),
+        ] if is_definition_action(action) && *end_index == index =>
+            Some(CstDefinition { block_index: *block_index, block, binding }),
+        _ => None,
+    }
+}
@@ -0,0 +1,481 @@
+use std::{cell::RefCell, error::Error, fs::{canonicalize, read_to_string}, io::{stdin, stdout, BufRead, Write}, path::Path, rc::Rc};
+
+use crate::{code_to_node, debugger::{backtrace, Breakpoint, PauseControl, PauseReason, StepMode}, eval::{ExecutionError, Hook, Interpreter, Value}, json::Json, loc::{Loc, LocSource}, parser::Node, rpc::{read_message, write_message}};
+
+/// Programs only have one thread.
+const THREAD_ID: isize = 1;
+
+/// How many characters of each value to show before cutting it short.
+const SUMMARY_LENGTH: usize = 200;
+
+/// Something which a client can expand into a list of variables, using its index in
+/// [Session::variables] as a `variablesReference`.
+enum Variables {
+    Stack,
+
+    /// The bindings in the binding frame at this index.
+    Bindings(usize),
+
+    /// The items of an array.
+    Items(Vec<Value>),
+}
+
+/// What to do after handling a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    /// Keep handling requests.
+    Continue,
+
+    /// Carry on executing the program, if it's paused.
+    Resume,
+
+    /// Stop everything.
+    Disconnect,
+}
+
+/// A program to debug, from a `launch` request.
+struct Launch {
+    root: Node,
+    input_path: Option<String>,
+    stop_on_entry: bool,
+}
+
+/// The state of a Debug Adapter Protocol session, shared between the request loop, the [Hook]
+/// which pauses execution, and the interpreter's output.
+struct Session {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    seq: isize,
+
+    control: PauseControl,
+    launch: Option<Launch>,
+    configured: bool,
+    paused_before: bool,
+    disconnected: bool,
+
+    /// Everything which has been given a `variablesReference` since the program last paused.
+    variables: Vec<Variables>,
+
+    /// Sources which aren't files, like the stdlib, so the client has to fetch them by their index
+    /// as a `sourceReference`.
+    sources: Vec<LocSource>,
+
+    /// Program output which hasn't been sent yet, because it doesn't end in a line break.
+    pending_output: String,
+}
+
+impl Session {
+    fn new(input: Box<dyn BufRead>, output: Box<dyn Write>) -> Self {
+        Session {
+            input,
+            output,
+            seq: 0,
+            control: PauseControl::new(StepMode::Continue),
+            launch: None,
+            configured: false,
+            paused_before: false,
+            disconnected: false,
+            variables: vec![],
+            sources: vec![],
+            pending_output: String::new(),
+        }
+    }
+
+    fn read(&mut self) -> Result<Option<Json>, Box<dyn Error>> {
+        read_message(&mut self.input)
+    }
+
+    fn send(&mut self, mut pairs: Vec<(&str, Json)>) -> Result<(), Box<dyn Error>> {
+        self.seq += 1;
+        pairs.insert(0, ("seq", self.seq.into()));
+        write_message(&mut self.output, &Json::object(pairs))
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> Result<(), Box<dyn Error>> {
+        self.send(vec![("type", "event".into()), ("event", event.into()), ("body", body)])
+    }
+
+    fn respond(&mut self, request: &Json, result: Result<Json, String>) -> Result<(), Box<dyn Error>> {
+        let mut pairs = vec![
+            ("type", "response".into()),
+            ("request_seq", request.get("seq").cloned().unwrap_or(Json::Null)),
+            ("command", request.get("command").cloned().unwrap_or(Json::Null)),
+        ];
+        match result {
+            Ok(body) => {
+                pairs.push(("success", true.into()));
+                pairs.push(("body", body));
+            },
+            Err(message) => {
+                pairs.push(("success", false.into()));
+                pairs.push(("message", message.into()));
+            },
+        }
+        self.send(pairs)
+    }
+
+    /// Sends program output as events, a line at a time - or everything, if `all` is set.
+    fn send_output(&mut self, all: bool) -> Result<(), Box<dyn Error>> {
+        let end = match all {
+            true => self.pending_output.len(),
+            false => self.pending_output.rfind('\n').map(|i| i + 1).unwrap_or(0),
+        };
+        if end == 0 {
+            return Ok(());
+        }
+
+        let output = self.pending_output.drain(..end).collect::<String>();
+        self.event("output", Json::object([("category", "stdout".into()), ("output", output.into())]))
+    }
+
+    /// Handles a request. `paused` is where the program is paused, if it's running.
+    fn handle(&mut self, request: &Json, paused: Option<(&Interpreter, &Node)>) -> Result<Outcome, Box<dyn Error>> {
+        let command = request.get("command").and_then(Json::as_str).unwrap_or_default();
+        let args = request.get("arguments").cloned().unwrap_or(Json::Null);
+        let depth = paused.map(|(interpreter, _)| interpreter.call_stack().len()).unwrap_or(0);
+
+        let mut outcome = Outcome::Continue;
+        let result = match command {
+            "initialize" => Ok(Json::object([
+                ("supportsConfigurationDoneRequest", true.into()),
+                ("supportsFunctionBreakpoints", true.into()),
+            ])),
+            "launch" => self.launch(&args).map(|_| Json::Null),
+            "configurationDone" => {
+                self.configured = true;
+                Ok(Json::Null)
+            },
+
+            "setBreakpoints" => Ok(self.set_breakpoints(&args)),
+            "setFunctionBreakpoints" => Ok(self.set_function_breakpoints(&args)),
+
+            "threads" => Ok(Json::object([
+                ("threads", vec![Json::object([("id", THREAD_ID.into()), ("name", "main".into())])].into()),
+            ])),
+            "stackTrace" => Ok(self.stack_trace(paused)),
+            "scopes" => Ok(self.scopes(&args, paused)),
+            "variables" => Ok(self.variables(&args, paused)),
+            "source" => {
+                let reference = args.get("sourceReference").and_then(Json::as_integer).unwrap_or(0);
+                match self.sources.get((reference - 1) as usize) {
+                    Some(source) => Ok(Json::object([("content", source.contents.as_str().into())])),
+                    None => Err(format!("no source with reference {reference}")),
+                }
+            },
+
+            "continue" | "next" | "stepIn" | "stepOut" if paused.is_some() => {
+                self.control.mode = match command {
+                    "continue" => StepMode::Continue,
+                    "next" => StepMode::Next(depth),
+                    "stepIn" => StepMode::Step,
+                    _ => StepMode::Out(depth),
+                };
+                outcome = Outcome::Resume;
+                Ok(Json::object([("allThreadsContinued", true.into())]))
+            },
+            "continue" | "next" | "stepIn" | "stepOut" => Err("the program isn't paused".to_owned()),
+
+            // Execution happens on this thread, so while it's running, we can't receive this
+            "pause" => Ok(Json::Null),
+
+            "disconnect" | "terminate" => {
+                outcome = Outcome::Disconnect;
+                Ok(Json::Null)
+            },
+
+            _ => Err(format!("unsupported command `{command}`")),
+        };
+
+        self.respond(request, result)?;
+        if command == "initialize" {
+            self.event("initialized", Json::Null)?;
+        }
+        if outcome == Outcome::Disconnect {
+            self.disconnected = true;
+        }
+        Ok(outcome)
+    }
+
+    fn launch(&mut self, args: &Json) -> Result<(), String> {
+        let program = args.get("program").and_then(Json::as_str).ok_or("`program` is required")?;
+
+        // Use the absolute path as the source name, so it matches breakpoint paths from the client
+        let path = canonicalize(program).map_err(|e| format!("couldn't find {program}: {e}"))?;
+        let path = path.to_string_lossy().to_string();
+        let code = read_to_string(&path).map_err(|e| format!("couldn't read {path}: {e}"))?;
+        let root = code_to_node(&code, &path).map_err(|e| e.to_string())?;
+
+        self.launch = Some(Launch {
+            root,
+            input_path: args.get("input").and_then(Json::as_str).map(|s| s.to_owned()),
+            stop_on_entry: args.get("stopOnEntry").and_then(Json::as_boolean).unwrap_or(false),
+        });
+        Ok(())
+    }
+
+    /// Replaces all line breakpoints in a file.
+    fn set_breakpoints(&mut self, args: &Json) -> Json {
+        let file = args.get_path(&["source", "path"]).and_then(Json::as_str).unwrap_or_default().to_owned();
+        let lines = args.get("breakpoints").and_then(Json::as_array).unwrap_or_default()
+            .iter()
+            .filter_map(|bp| bp.get("line").and_then(Json::as_integer))
+            .collect::<Vec<_>>();
+
+        self.control.breakpoints.retain(|bp| !matches!(bp, Breakpoint::Line { file: f, .. } if *f == file));
+        self.control.breakpoints.extend(lines.iter().map(|line| Breakpoint::Line { file: file.clone(), line: *line as usize }));
+
+        let breakpoints = lines.iter()
+            .map(|line| Json::object([("verified", true.into()), ("line", (*line).into())]))
+            .collect::<Vec<_>>();
+        Json::object([("breakpoints", breakpoints.into())])
+    }
+
+    /// Replaces all action breakpoints.
+    fn set_function_breakpoints(&mut self, args: &Json) -> Json {
+        let names = args.get("breakpoints").and_then(Json::as_array).unwrap_or_default()
+            .iter()
+            .filter_map(|bp| bp.get("name").and_then(Json::as_str))
+            .map(|name| name.to_owned())
+            .collect::<Vec<_>>();
+
+        self.control.breakpoints.retain(|bp| !matches!(bp, Breakpoint::Action(_)));
+        self.control.breakpoints.extend(names.iter().cloned().map(Breakpoint::Action));
+
+        let breakpoints = names.iter().map(|_| Json::object([("verified", true.into())])).collect::<Vec<_>>();
+        Json::object([("breakpoints", breakpoints.into())])
+    }
+
+    /// The call stack, innermost first. Frame IDs are their index in this list.
+    fn stack_trace(&mut self, paused: Option<(&Interpreter, &Node)>) -> Json {
+        let frames = match paused {
+            Some((interpreter, node)) => backtrace(interpreter, &node.loc),
+            None => vec![],
+        };
+
+        let frames = frames.into_iter()
+            .enumerate()
+            .map(|(id, (name, loc))| {
+                let (line, column) = loc.line_col();
+                Json::object([
+                    ("id", id.into()),
+                    ("name", name.into()),
+                    ("source", self.source(&loc)),
+                    ("line", line.into()),
+                    ("column", column.into()),
+                ])
+            })
+            .collect::<Vec<_>>();
+
+        Json::object([("totalFrames", frames.len().into()), ("stackFrames", frames.into())])
+    }
+
+    /// Describes a [Loc]'s source, either as a file or something to be fetched with `source`.
+    fn source(&mut self, loc: &Loc) -> Json {
+        let name = &loc.source.name;
+        let path = Path::new(name);
+        if path.is_file() {
+            let file_name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or(name.clone());
+            return Json::object([("name", file_name.into()), ("path", name.as_str().into())]);
+        }
+
+        let index = match self.sources.iter().position(|s| s.name == *name) {
+            Some(index) => index,
+            None => {
+                self.sources.push(loc.source.clone());
+                self.sources.len() - 1
+            },
+        };
+        Json::object([("name", name.as_str().into()), ("sourceReference", (index + 1).into())])
+    }
+
+    /// The value stack, and the bindings of a frame.
+    fn scopes(&mut self, args: &Json, paused: Option<(&Interpreter, &Node)>) -> Json {
+        let Some((interpreter, _)) = paused else { return Json::object([("scopes", Json::Array(vec![]))]) };
+
+        // Each call stack frame has a binding frame, plus there's one for the top-level
+        let frame_id = args.get("frameId").and_then(Json::as_integer).unwrap_or(0) as usize;
+        let binding_frame = interpreter.binding_frames().len().saturating_sub(frame_id + 1);
+
+        let scopes = vec![
+            Json::object([
+                ("name", "Stack".into()),
+                ("variablesReference", self.add_variables(Variables::Stack).into()),
+                ("expensive", false.into()),
+            ]),
+            Json::object([
+                ("name", "Bindings".into()),
+                ("variablesReference", self.add_variables(Variables::Bindings(binding_frame)).into()),
+                ("expensive", false.into()),
+            ]),
+        ];
+        Json::object([("scopes", scopes.into())])
+    }
+
+    fn variables(&mut self, args: &Json, paused: Option<(&Interpreter, &Node)>) -> Json {
+        let reference = args.get("variablesReference").and_then(Json::as_integer).unwrap_or(0);
+        let Some((interpreter, _)) = paused else { return Json::object([("variables", Json::Array(vec![]))]) };
+
+        let named_values = match self.variables.get((reference - 1) as usize) {
+            Some(Variables::Stack) => interpreter.stack().iter()
+                .rev()
+                .enumerate()
+                .map(|(i, v)| (i.to_string(), v.clone()))
+                .collect(),
+            Some(Variables::Bindings(index)) => {
+                let bindings = interpreter.binding_frames().get(*index).map(|f| &f.bindings);
+                let mut named = bindings.into_iter()
+                    .flatten()
+                    .map(|(name, v)| (name.clone(), v.clone()))
+                    .collect::<Vec<_>>();
+                named.sort_by(|(a, _), (b, _)| a.cmp(b));
+                named
+            },
+            Some(Variables::Items(items)) => items.iter()
+                .enumerate()
+                .map(|(i, v)| (i.to_string(), v.clone()))
+                .collect(),
+            None => vec![],
+        };
+
+        let variables = named_values.into_iter()
+            .map(|(name, value)| {
+                // Arrays can be expanded, unless they're strings, which are shown in full anyway
+                let reference = match &value {
+                    Value::Array(items) if !items.is_empty() && value.clone().into_string().is_err() =>
+                        self.add_variables(Variables::Items(items.clone())),
+                    _ => 0,
+                };
+                Json::object([
+                    ("name", name.into()),
+                    ("value", value.summary(SUMMARY_LENGTH).into()),
+                    ("variablesReference", reference.into()),
+                ])
+            })
+            .collect::<Vec<_>>();
+        Json::object([("variables", variables.into())])
+    }
+
+    fn add_variables(&mut self, variables: Variables) -> usize {
+        self.variables.push(variables);
+        self.variables.len()
+    }
+
+    /// Pauses if needed before an atom executes, handling requests until the client resumes.
+    fn before_atom(&mut self, interpreter: &Interpreter, node: &Node) -> Result<(), Box<dyn Error>> {
+        let Some(reason) = self.control.should_pause(interpreter, node) else { return Ok(()) };
+
+        let reason = match reason {
+            PauseReason::Breakpoint(_) => "breakpoint",
+            PauseReason::Step if !self.paused_before && self.control.mode == StepMode::Step => "entry",
+            PauseReason::Step => "step",
+        };
+        self.paused_before = true;
+
+        self.send_output(true)?;
+        self.event("stopped", Json::object([
+            ("reason", reason.into()),
+            ("threadId", THREAD_ID.into()),
+            ("allThreadsStopped", true.into()),
+        ]))?;
+
+        while let Some(request) = self.read()? {
+            match self.handle(&request, Some((interpreter, node)))? {
+                Outcome::Continue => (),
+                Outcome::Resume => {
+                    self.variables.clear();
+                    return Ok(());
+                },
+                Outcome::Disconnect => break,
+            }
+        }
+
+        self.disconnected = true;
+        Err("debugging session ended".into())
+    }
+}
+
+/// Pauses execution for a [Session].
+struct SessionHook(Rc<RefCell<Session>>);
+
+impl Hook for SessionHook {
+    fn before_atom(&mut self, interpreter: &Interpreter, node: &Node) -> Result<(), ExecutionError> {
+        self.0.borrow_mut().before_atom(interpreter, node).map_err(|e| ExecutionError::new(e.to_string()))
+    }
+}
+
+/// Sends program output to a [Session]'s client, rather than stdout, which is in use by the
+/// protocol.
+struct SessionOutput(Rc<RefCell<Session>>);
+
+impl Write for SessionOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut session = self.0.borrow_mut();
+        session.pending_output.push_str(&String::from_utf8_lossy(buf));
+        session.send_output(false).map_err(|e| std::io::Error::other(e.to_string()))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Creates an interpreter ready to run a program, with the stdlib loaded and `$input` read from a
+/// path, if given.
+pub type PrepareInterpreter = fn(Option<&str>) -> Result<Interpreter, Box<dyn Error>>;
+
+/// Runs a Debug Adapter Protocol server over stdin and stdout, which debugs a single launch of a
+/// program.
+pub fn serve_stdio(prepare: PrepareInterpreter) -> Result<(), Box<dyn Error>> {
+    let session = Rc::new(RefCell::new(Session::new(Box::new(stdin().lock()), Box::new(stdout()))));
+
+    // Configuration - wait until we know what to run, and the client has set its breakpoints
+    loop {
+        let mut session = session.borrow_mut();
+        let Some(request) = session.read()? else { return Ok(()) };
+        if session.handle(&request, None)? == Outcome::Disconnect {
+            return Ok(());
+        }
+        if session.launch.is_some() && session.configured {
+            break;
+        }
+    }
+
+    let launch = session.borrow_mut().launch.take().unwrap();
+    if launch.stop_on_entry {
+        session.borrow_mut().control.mode = StepMode::Step;
+    }
+
+    let result = prepare(launch.input_path.as_deref()).and_then(|mut interpreter| {
+        interpreter.set_output(Box::new(SessionOutput(session.clone())));
+        interpreter.add_hook(Box::new(SessionHook(session.clone())));
+        Ok(interpreter.execute(&launch.root)?)
+    });
+
+    let mut session = session.borrow_mut();
+    if session.disconnected {
+        return Ok(());
+    }
+
+    session.send_output(true)?;
+    let exit_code: isize = match result {
+        Ok(()) => 0,
+        Err(e) => {
+            session.event("output", Json::object([
+                ("category", "stderr".into()),
+                ("output", format!("Execution error: {e}\n").into()),
+            ]))?;
+            1
+        },
+    };
+    session.event("exited", Json::object([("exitCode", exit_code.into())]))?;
+    session.event("terminated", Json::object([]))?;
+
+    // Answer anything else until the client goes away
+    while let Some(request) = session.read()? {
+        if session.handle(&request, None)? == Outcome::Disconnect {
+            break;
+        }
+    }
+    Ok(())
+}
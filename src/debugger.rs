@@ -0,0 +1,317 @@
+use std::{fmt::Display, io::{stdin, stdout, Write}, process::exit};
+
+use crate::{eval::{ExecutionError, Hook, Interpreter}, loc::Loc, parser::{Node, NodeKind}, token::Atom};
+
+/// How many characters of each value to show before cutting it short.
+const SUMMARY_LENGTH: usize = 100;
+
+/// Somewhere execution should pause.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Pause when reaching a line. See [crate::loc::LocSource::matches_file] for how the file is
+    /// matched.
+    Line { file: String, line: usize },
+
+    /// Pause before calling an action.
+    Action(String),
+}
+
+impl Breakpoint {
+    /// Parses a breakpoint written as `file:line`, `line` (in `default_file`), or an action name.
+    pub fn parse(spec: &str, default_file: &str) -> Breakpoint {
+        if let Ok(line) = spec.parse() {
+            return Breakpoint::Line { file: default_file.to_owned(), line };
+        }
+        if let Some((file, line)) = spec.rsplit_once(':') && let Ok(line) = line.parse() {
+            return Breakpoint::Line { file: file.to_owned(), line };
+        }
+        Breakpoint::Action(spec.to_owned())
+    }
+
+    fn matches(&self, node: &Node, line: usize) -> bool {
+        match self {
+            Breakpoint::Line { file, line: bp_line } =>
+                *bp_line == line && node.loc.source.matches_file(file),
+            Breakpoint::Action(name) =>
+                matches!(&node.kind, NodeKind::Atom(Atom::Action(a)) if a == name),
+        }
+    }
+}
+
+impl Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Breakpoint::Line { file, line } => write!(f, "{file}:{line}"),
+            Breakpoint::Action(name) => write!(f, "action `{name}`"),
+        }
+    }
+}
+
+/// How to proceed after a pause. Depths are the length of the interpreter's
+/// [call stack](Interpreter::call_stack).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepMode {
+    /// Pause before the very next atom.
+    Step,
+
+    /// Pause before the next atom which isn't within something called from the given depth.
+    Next(usize),
+
+    /// Pause before the next atom after leaving the given depth.
+    Out(usize),
+
+    /// Only pause at breakpoints.
+    Continue,
+}
+
+/// Why execution paused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseReason {
+    Step,
+
+    /// Hit the breakpoint with this index.
+    Breakpoint(usize),
+}
+
+/// Decides when execution should pause, based on breakpoints and stepping. Shared by debugger
+/// front-ends, which decide what to do while paused.
+pub struct PauseControl {
+    pub breakpoints: Vec<Breakpoint>,
+    pub mode: StepMode,
+
+    /// The file and line of the previous atom at each depth, so that line breakpoints trigger once
+    /// when their line is reached, rather than for every atom on it or when returning to it.
+    previous_lines: Vec<(String, usize)>,
+}
+
+impl PauseControl {
+    pub fn new(mode: StepMode) -> Self {
+        PauseControl { breakpoints: vec![], mode, previous_lines: vec![] }
+    }
+
+    /// Called before each atom, to see whether to pause before executing it.
+    pub fn should_pause(&mut self, interpreter: &Interpreter, node: &Node) -> Option<PauseReason> {
+        let depth = interpreter.call_stack().len();
+        let file = &node.loc.source.name;
+        let (line, _) = node.loc.line_col();
+
+        self.previous_lines.resize(depth + 1, (String::new(), 0));
+        let new_line = self.previous_lines[depth] != (file.clone(), line);
+        self.previous_lines[depth] = (file.clone(), line);
+
+        let breakpoint = self.breakpoints.iter()
+            .position(|bp| bp.matches(node, line) && (new_line || matches!(bp, Breakpoint::Action(_))));
+        if let Some(index) = breakpoint {
+            return Some(PauseReason::Breakpoint(index));
+        }
+
+        let step = match self.mode {
+            StepMode::Step => true,
+            StepMode::Next(from) => depth <= from,
+            StepMode::Out(from) => depth < from,
+            StepMode::Continue => false,
+        };
+        step.then_some(PauseReason::Step)
+    }
+}
+
+/// A terminal debugger, which pauses before the first atom of the program and then takes
+/// commands from stdin.
+pub struct Debugger {
+    control: PauseControl,
+
+    /// The file being debugged, which line-only breakpoints refer to.
+    main_file: String,
+
+    /// The last command entered, which an empty line repeats.
+    last_command: String,
+}
+
+impl Debugger {
+    pub fn new(main_file: &str) -> Self {
+        Debugger {
+            control: PauseControl::new(StepMode::Step),
+            main_file: main_file.to_owned(),
+            last_command: String::new(),
+        }
+    }
+
+    /// Reads and runs commands until one resumes execution.
+    fn prompt(&mut self, interpreter: &Interpreter, node: &Node) -> Result<(), ExecutionError> {
+        loop {
+            print!("(debug) ");
+            stdout().flush().map_err(|e| ExecutionError::new(e.to_string()))?;
+
+            let mut line = String::new();
+            if stdin().read_line(&mut line).map_err(|e| ExecutionError::new(e.to_string()))? == 0 {
+                // No more commands - let the program run to completion
+                println!();
+                self.control.breakpoints.clear();
+                self.control.mode = StepMode::Continue;
+                return Ok(());
+            }
+
+            let line = match line.trim() {
+                "" => self.last_command.clone(),
+                line => line.to_owned(),
+            };
+            self.last_command = line.clone();
+
+            let (command, argument) = line.split_once(' ').unwrap_or((&line, ""));
+            let argument = argument.trim();
+            let depth = interpreter.call_stack().len();
+
+            match command {
+                "step" | "s" => return self.resume(StepMode::Step),
+                "next" | "n" => return self.resume(StepMode::Next(depth)),
+                "out" | "o" => return self.resume(StepMode::Out(depth)),
+                "continue" | "c" => return self.resume(StepMode::Continue),
+
+                "break" | "b" if !argument.is_empty() => {
+                    let breakpoint = Breakpoint::parse(argument, &self.main_file);
+                    println!("Breakpoint {} at {breakpoint}", self.control.breakpoints.len() + 1);
+                    self.control.breakpoints.push(breakpoint);
+                },
+                "delete" | "d" => match argument.parse::<usize>() {
+                    Ok(n) if n >= 1 && n <= self.control.breakpoints.len() => {
+                        let breakpoint = self.control.breakpoints.remove(n - 1);
+                        println!("Deleted breakpoint {n} at {breakpoint}");
+                    },
+                    _ => println!("No breakpoint `{argument}` - see `breakpoints`"),
+                },
+                "breakpoints" => {
+                    if self.control.breakpoints.is_empty() {
+                        println!("No breakpoints");
+                    }
+                    for (i, breakpoint) in self.control.breakpoints.iter().enumerate() {
+                        println!("{}: {breakpoint}", i + 1);
+                    }
+                },
+
+                "stack" => print_stack(interpreter),
+                "bindings" => print_bindings(interpreter),
+                "where" | "bt" => print_backtrace(interpreter, &node.loc),
+                "list" | "l" => print_source_context(&node.loc, 5),
+                "help" | "h" => print_help(),
+                "quit" | "q" => exit(0),
+
+                _ => println!("Unknown command `{line}` - try `help`"),
+            }
+        }
+    }
+
+    fn resume(&mut self, mode: StepMode) -> Result<(), ExecutionError> {
+        self.control.mode = mode;
+        Ok(())
+    }
+}
+
+impl Hook for Debugger {
+    fn before_atom(&mut self, interpreter: &Interpreter, node: &Node) -> Result<(), ExecutionError> {
+        let Some(reason) = self.control.should_pause(interpreter, node) else { return Ok(()) };
+
+        let (line, col) = node.loc.line_col();
+        let frame = interpreter.call_stack().last()
+            .map(|f| format!("`{f}`"))
+            .unwrap_or("top-level".to_owned());
+        match reason {
+            PauseReason::Step => println!("{}:{line}:{col} in {frame}", node.loc.source.name),
+            PauseReason::Breakpoint(i) => println!(
+                "Breakpoint {} hit at {}:{line}:{col} in {frame}", i + 1, node.loc.source.name,
+            ),
+        }
+        print_source_context(&node.loc, 1);
+
+        self.prompt(interpreter, node)
+    }
+}
+
+fn print_help() {
+    println!("\
+step, s          Execute the next atom, stepping into any action or block it runs
+next, n          Execute the next atom, stepping over any action or block it runs
+out, o           Run until the current action or block finishes
+continue, c      Run until a breakpoint is hit
+break, b <spec>  Add a breakpoint at `file:line`, `line` in the main file, or an action name
+delete, d <n>    Remove a breakpoint
+breakpoints      List breakpoints
+stack            Show the value stack, top first
+bindings         Show the bindings in each frame, innermost first
+where, bt        Show the action and block call stack
+list, l          Show more source around the current position
+quit, q          Stop the program
+An empty line repeats the last command.");
+}
+
+/// Prints the lines around a [Loc], with the [Loc] itself underlined.
+pub fn print_source_context(loc: &Loc, context_lines: usize) {
+    let (line, col) = loc.line_col();
+    let lines = loc.source.contents.lines().collect::<Vec<_>>();
+    let first = line.saturating_sub(context_lines).max(1);
+    let last = (line + context_lines).min(lines.len());
+    let width = last.to_string().len();
+
+    for n in first..=last {
+        let marker = if n == line { "->" } else { "  " };
+        println!("{marker} {n:>width$} | {}", lines[n - 1]);
+        if n == line {
+            // Don't underline past the end of the line, for locs spanning several
+            let remaining = lines[n - 1].chars().count().saturating_sub(col - 1);
+            let underline = loc.len.clamp(1, remaining.max(1));
+            println!("   {:width$} | {}{}", "", " ".repeat(col - 1), "^".repeat(underline));
+        }
+    }
+}
+
+fn print_stack(interpreter: &Interpreter) {
+    if interpreter.stack().is_empty() {
+        println!("(empty)");
+    }
+    for (i, value) in interpreter.stack().iter().rev().enumerate() {
+        println!("{i}: {}", value.summary(SUMMARY_LENGTH));
+    }
+}
+
+fn print_bindings(interpreter: &Interpreter) {
+    let call_stack = interpreter.call_stack();
+
+    for (i, frame) in interpreter.binding_frames().iter().enumerate().rev() {
+        match i {
+            0 => println!("top-level:"),
+            _ => match call_stack.get(i - 1) {
+                Some(f) => println!("`{f}`:"),
+                None => println!("frame {i}:"),
+            },
+        }
+
+        let mut names = frame.bindings.keys().collect::<Vec<_>>();
+        names.sort();
+        if names.is_empty() {
+            println!("    (none)");
+        }
+        for name in names {
+            println!("    {name} = {}", frame.bindings[name].summary(SUMMARY_LENGTH));
+        }
+    }
+}
+
+pub fn print_backtrace(interpreter: &Interpreter, current: &Loc) {
+    for (i, (name, loc)) in backtrace(interpreter, current).iter().enumerate() {
+        let (line, col) = loc.line_col();
+        println!("#{i} {name} at {}:{line}:{col}", loc.source.name);
+    }
+}
+
+/// Describes each frame of the call stack, innermost first, along with where it's currently
+/// executing - `current` for the innermost frame, and otherwise where it called the next one.
+pub fn backtrace(interpreter: &Interpreter, current: &Loc) -> Vec<(String, Loc)> {
+    let mut frames = vec![];
+    let mut position = current;
+    for frame in interpreter.call_stack().iter().rev() {
+        frames.push((frame.to_string(), position.clone()));
+        position = &frame.call_loc;
+    }
+    frames.push(("top-level".to_owned(), position.clone()));
+
+    frames
+}
@@ -0,0 +1,325 @@
+use std::{error::Error, fmt::Display};
+
+use crate::{builtins::BUILTINS, cst::{definition_ending_at, parse_cst, CstNode}, loc::{Loc, LocSource}, parser::StackEffect, token::{tokenize_with_trivia, Atom, TokenKind}};
+
+/// Documentation for a single action.
+#[derive(Debug, Clone)]
+pub struct ActionDoc {
+    pub name: String,
+
+    /// Where the action comes from - either "builtin", or the name of the source defining it.
+    pub origin: String,
+
+    /// The formal stack effect, from the builtin table or a `( ... )` annotation.
+    pub effect: Option<StackEffect>,
+
+    /// The informal usage line from the doc comment, like `[a] [b] swap --> [b] [a]`.
+    pub usage: Option<String>,
+
+    pub description: String,
+    pub loc: Option<Loc>,
+}
+
+impl ActionDoc {
+    /// Where the action is defined, like `file.stk:12`, if it's not a builtin.
+    pub fn location(&self) -> Option<String> {
+        let loc = self.loc.as_ref()?;
+        let (line, _) = loc.line_col();
+        Some(format!("{}:{line}", loc.source.name))
+    }
+}
+
+impl Display for ActionDoc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(effect) = &self.effect {
+            write!(f, " {effect}")?;
+        }
+        write!(f, "    [{}]", self.origin)?;
+
+        if let Some(usage) = &self.usage {
+            write!(f, "\n    {usage}")?;
+        }
+        for line in self.description.lines() {
+            write!(f, "\n    {line}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Documentation for all builtin actions.
+pub fn builtin_docs() -> Vec<ActionDoc> {
+    BUILTINS.iter()
+        .map(|b| ActionDoc {
+            name: b.name.to_owned(),
+            origin: "builtin".to_owned(),
+            effect: Some(b.stack_effect()),
+            usage: None,
+            description: b.doc.to_owned(),
+            loc: None,
+        })
+        .collect()
+}
+
+/// Documentation for user actions defined in some source code.
+///
+/// An action's documentation is the block of `//` comment lines immediately before its definition
+/// (with no blank lines in between). The first line mentioning the action's name is taken to be an
+/// informal usage line, like `[arr] [pred] count`, and the rest is its description.
+pub fn source_docs(source: &LocSource) -> Result<Vec<ActionDoc>, Box<dyn Error>> {
+    let cst = parse_cst(tokenize_with_trivia(source)?)?;
+
+    let mut docs = vec![];
+    find_docs(&cst, source, &mut docs);
+    Ok(docs)
+}
+
+fn find_docs(nodes: &[CstNode], source: &LocSource, docs: &mut Vec<ActionDoc>) {
+    for (i, node) in nodes.iter().enumerate() {
+        if let CstNode::Block { children, .. } = node {
+            find_docs(children, source, docs);
+        }
+
+        let Some(definition) = definition_ending_at(nodes, i) else { continue };
+        let TokenKind::Atom(Atom::Binding(name)) = &definition.binding.kind else { continue };
+        let name = name.strip_prefix('$').unwrap();
+
+        let comment = doc_comment_before(nodes, definition.block_index);
+        let usage = comment.iter()
+            .find(|line| line.split_whitespace().any(|w| w == name || w.strip_prefix('$') == Some(name)))
+            .cloned();
+        let description = comment.iter()
+            .filter(|line| Some(*line) != usage.as_ref())
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        docs.push(ActionDoc {
+            name: name.to_owned(),
+            origin: source.name.clone(),
+            effect: block_stack_effect(definition.block),
+            usage,
+            description: description.trim_matches('\n').to_owned(),
+            loc: Some(definition.binding.loc.clone()),
+        });
+    }
+}
+
+/// Collects the lines of the comment immediately before the node at `index`, without their `//`.
+fn doc_comment_before(nodes: &[CstNode], index: usize) -> Vec<String> {
+    let mut lines = vec![];
+
+    for i in (0..index).rev() {
+        let CstNode::Token(token) = &nodes[i] else { break };
+        match &token.kind {
+            // A blank line separates the comment from the definition
+            TokenKind::Whitespace(w) if w.matches('\n').count() > 1 => break,
+            TokenKind::Whitespace(_) => (),
+
+            TokenKind::Comment(c) => {
+                // Only count comments on their own line, not trailing ones after some other code
+                let own_line = i == 0
+                    || matches!(&nodes[i - 1], CstNode::Token(t) if matches!(&t.kind, TokenKind::Whitespace(w) if w.contains('\n')));
+                if !own_line {
+                    break;
+                }
+
+                let text = c.strip_prefix("//").unwrap();
+                lines.push(text.strip_prefix(' ').unwrap_or(text).trim_end().to_owned());
+            },
+
+            _ => break,
+        }
+    }
+
+    lines.reverse();
+    lines
+}
+
+/// Gets the stack effect annotation at the start of a block, if it has one.
+fn block_stack_effect(block: &CstNode) -> Option<StackEffect> {
+    let CstNode::Block { children, .. } = block else { return None };
+    let first = children.iter().find(|n| !matches!(n, CstNode::Token(t) if t.is_trivia()))?;
+    let CstNode::StackEffect { children, .. } = first else { return None };
+
+    let names = children.iter()
+        .filter_map(|t| match &t.kind {
+            TokenKind::Atom(Atom::Action(name) | Atom::Binding(name)) => Some(name.clone()),
+            _ => None,
+        })
+        .collect();
+    StackEffect::from_names(names).ok()
+}
+
+/// Groups docs by their origin, keeping the order in which each origin first appears.
+fn group_by_origin(docs: &[ActionDoc]) -> Vec<(&str, Vec<&ActionDoc>)> {
+    let mut groups: Vec<(&str, Vec<&ActionDoc>)> = vec![];
+    for doc in docs {
+        match groups.iter_mut().find(|(origin, _)| *origin == doc.origin) {
+            Some((_, group)) => group.push(doc),
+            None => groups.push((&doc.origin, vec![doc])),
+        }
+    }
+    groups
+}
+
+/// Renders a Markdown reference, with a section for each origin.
+pub fn render_markdown(docs: &[ActionDoc]) -> String {
+    let groups = group_by_origin(docs);
+    let mut out = String::from("# Action reference\n\n");
+
+    for (origin, _) in &groups {
+        out.push_str(&format!("- [{origin}](#{})\n", markdown_anchor(origin)));
+    }
+
+    for (origin, group) in &groups {
+        out.push_str(&format!("\n## {origin}\n"));
+
+        for doc in group {
+            let mut parts = vec![format!("### `{}`", doc.name)];
+            if let Some(effect) = &doc.effect {
+                parts.push(format!("Stack effect: `{effect}`"));
+            }
+            if let Some(usage) = &doc.usage {
+                parts.push(format!("Usage: `{usage}`"));
+            }
+            if !doc.description.is_empty() {
+                parts.push(doc.description.clone());
+            }
+            if let Some(location) = doc.location() {
+                parts.push(format!("*Defined at {location}*"));
+            }
+
+            out.push('\n');
+            out.push_str(&parts.join("\n\n"));
+            out.push('\n');
+        }
+    }
+
+    out
+}
+
+/// The anchor which GitHub-flavoured Markdown generates for a heading.
+fn markdown_anchor(heading: &str) -> String {
+    heading.to_lowercase()
+        .chars()
+        .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_' || *c == ' ')
+        .map(|c| if c == ' ' { '-' } else { c })
+        .collect()
+}
+
+/// Renders a standalone HTML page, with an index linking to every action.
+pub fn render_html(docs: &[ActionDoc]) -> String {
+    let groups = group_by_origin(docs);
+    let mut out = String::from(concat!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Action reference</title>\n",
+        "<style>body { font-family: sans-serif; max-width: 50em; margin: auto; } ",
+        "pre, code { background: #eee; } nav a { margin-right: 0.5em; }</style>\n",
+        "</head>\n<body>\n<h1>Action reference</h1>\n",
+    ));
+
+    // Index
+    let mut id = 0;
+    for (origin, group) in &groups {
+        out.push_str(&format!("<h3>{}</h3>\n<nav>\n", escape_html(origin)));
+        for doc in group {
+            out.push_str(&format!("<a href=\"#action-{id}\"><code>{}</code></a>\n", escape_html(&doc.name)));
+            id += 1;
+        }
+        out.push_str("</nav>\n");
+    }
+
+    // Actions
+    let mut id = 0;
+    for (origin, group) in &groups {
+        out.push_str(&format!("<h2>{}</h2>\n", escape_html(origin)));
+        for doc in group {
+            out.push_str(&format!("<h3 id=\"action-{id}\"><code>{}</code></h3>\n", escape_html(&doc.name)));
+            id += 1;
+
+            if let Some(effect) = &doc.effect {
+                out.push_str(&format!("<p>Stack effect: <code>{}</code></p>\n", escape_html(&effect.to_string())));
+            }
+            if let Some(usage) = &doc.usage {
+                out.push_str(&format!("<p>Usage: <code>{}</code></p>\n", escape_html(usage)));
+            }
+            if !doc.description.is_empty() {
+                out.push_str(&format!("<pre>{}</pre>\n", escape_html(&doc.description)));
+            }
+            if let Some(location) = doc.location() {
+                out.push_str(&format!("<p><small>Defined at {}</small></p>\n", escape_html(&location)));
+            }
+        }
+    }
+
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+
+    fn docs(code: &str) -> Vec<ActionDoc> {
+        source_docs(&LocSource::new("test.stk".to_owned(), Rc::new(code.to_owned()))).unwrap()
+    }
+
+    #[test]
+    fn comments_before_definitions_are_docs() {
+        let docs = docs("\
+// [a] [b] add --> [a+b]
+// Adds two numbers.
+// Really.
+{ ( a b -- c ) + } $add ::
+
+// Not about `undocumented`
+
+{ } $undocumented :: // Nor is this
+");
+        assert_eq!(docs.len(), 2);
+        assert_eq!(docs[0].name, "add");
+        assert_eq!(docs[0].usage.as_deref(), Some("[a] [b] add --> [a+b]"));
+        assert_eq!(docs[0].description, "Adds two numbers.\nReally.");
+        assert_eq!(docs[0].effect.as_ref().map(|e| e.to_string()).as_deref(), Some("( a b -- c )"));
+        assert_eq!(docs[0].location().as_deref(), Some("test.stk:4"));
+
+        assert_eq!(docs[1].name, "undocumented");
+        assert_eq!((docs[1].usage.as_ref(), docs[1].description.as_str(), docs[1].effect.as_ref()), (None, "", None));
+    }
+
+    #[test]
+    fn unbalanced_sources_are_errors() {
+        assert!(source_docs(&LocSource::new("test.stk".to_owned(), Rc::new("{ 1 $x :".to_owned()))).is_err());
+        assert!(source_docs(&LocSource::new("test.stk".to_owned(), Rc::new("1 }".to_owned()))).is_err());
+    }
+
+    #[test]
+    fn every_builtin_is_documented() {
+        let docs = builtin_docs();
+        assert_eq!(docs.len(), BUILTINS.len());
+        assert!(docs.iter().all(|d| d.origin == "builtin" && d.effect.is_some() && !d.description.is_empty()));
+    }
+
+    #[test]
+    fn rendering() {
+        let docs = docs("// [a] <tag> --> [b]\n// Uses <angle> brackets & more.\n{ } $<tag> ::");
+
+        let markdown = render_markdown(&docs);
+        assert!(markdown.contains("- [test.stk](#teststk)\n"), "{markdown}");
+        assert!(markdown.contains("### `<tag>`\n\nUsage: `[a] <tag> --> [b]`\n\nUses <angle> brackets & more.\n\n*Defined at test.stk:3*\n"), "{markdown}");
+
+        let html = render_html(&docs);
+        assert!(html.contains("<a href=\"#action-0\"><code>&lt;tag&gt;</code></a>"), "{html}");
+        assert!(html.contains("<pre>Uses &lt;angle&gt; brackets &amp; more.</pre>"), "{html}");
+    }
+}
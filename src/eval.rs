@@ -1,6 +1,6 @@
-use std::{collections::HashMap, error::Error, fmt::Display};
+use std::{cmp::min, collections::{HashMap, VecDeque}, error::Error, fmt::Display, hash::{Hash, Hasher}, io::{stdout, Write}, rc::Rc};
 
-use crate::{loc::Loc, parser::{Node, NodeKind}, token::Atom};
+use crate::{builtins::{NativeAction, NativeFn, BUILTINS}, containers::{Heap, Ref}, grid::Grid, loc::Loc, parser::{is_assignment_action, Node, NodeKind, StackEffect}, regex::Regex, token::Atom};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Value {
@@ -10,7 +10,22 @@ pub enum Value {
     Array(Vec<Value>),
 
     Unbound(String),
-    Block(Node),
+    Block(Rc<Node>),
+
+    /// A compiled regular expression, created by `re`.
+    Regex(Rc<Regex>),
+
+    /// A 2D grid, created by `grid`. Shared until it's modified.
+    Grid(Rc<Grid>),
+
+    /// A min-priority queue, created by `heap`. Shared until it's modified.
+    Heap(Rc<Heap>),
+
+    /// A double-ended queue, created by `deque`. Shared until it's modified.
+    Deque(Rc<VecDeque<Value>>),
+
+    /// A mutable cell, created by `ref`. Always shared, even when it's modified.
+    Ref(Ref),
 }
 
 impl Value {
@@ -70,19 +85,94 @@ impl Value {
             .collect::<Result<Vec<_>, ExecutionError>>()
     }
 
-    pub fn into_block(self) -> Result<Node, ExecutionError> {
+    pub fn into_grid(self) -> Result<Rc<Grid>, ExecutionError> {
+        match self {
+            Value::Grid(g) => Ok(g),
+            _ => Err(ExecutionError::new(format!("expected grid, got `{self:?}`")))
+        }
+    }
+
+    pub fn into_heap(self) -> Result<Rc<Heap>, ExecutionError> {
+        match self {
+            Value::Heap(h) => Ok(h),
+            _ => Err(ExecutionError::new(format!("expected heap, got `{self:?}`")))
+        }
+    }
+
+    pub fn into_deque(self) -> Result<Rc<VecDeque<Value>>, ExecutionError> {
+        match self {
+            Value::Deque(d) => Ok(d),
+            _ => Err(ExecutionError::new(format!("expected deque, got `{self:?}`")))
+        }
+    }
+
+    pub fn into_ref(self) -> Result<Ref, ExecutionError> {
+        match self {
+            Value::Ref(r) => Ok(r),
+            _ => Err(ExecutionError::new(format!("expected ref, got `{self:?}`")))
+        }
+    }
+
+    /// Converts a `[x y]` array to a point.
+    pub fn into_point(self) -> Result<(isize, isize), ExecutionError> {
+        match self.into_integer_array()?.as_slice() {
+            [x, y] => Ok((*x, *y)),
+            other => Err(ExecutionError::new(format!("expected point `[x y]`, got array of {} items", other.len()))),
+        }
+    }
+
+    pub fn from_point((x, y): (isize, isize)) -> Value {
+        Value::Array(vec![Value::Integer(x), Value::Integer(y)])
+    }
+
+    pub fn into_block(self) -> Result<Rc<Node>, ExecutionError> {
         match self {
             Value::Block(n) => Ok(n),
             _ => Err(ExecutionError::new(format!("expected block, got `{self:?}`")))
         }
     }
+
+    /// The printed representation on a single line, cut short with `...` if it's longer than
+    /// `max_len` characters.
+    /// Useful for values like `$input`, which are far too long to show in full.
+    pub fn summary(&self, max_len: usize) -> String {
+        let full = self.to_string().replace('\n', "\\n");
+        if full.chars().count() <= max_len {
+            full
+        } else {
+            let mut short = full.chars().take(max_len.saturating_sub(3)).collect::<String>();
+            short.push_str("...");
+            short
+        }
+    }
+}
+
+// Consistent with `Eq`, so values can be used as keys. Blocks are never used as keys in practice,
+// so they all hash the same rather than hashing their whole syntax tree
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Value::Char(c) => c.hash(state),
+            Value::Integer(i) => i.hash(state),
+            Value::Boolean(b) => b.hash(state),
+            Value::Array(items) => items.hash(state),
+            Value::Unbound(name) => name.hash(state),
+            Value::Block(_) => (),
+            Value::Regex(regex) => regex.pattern().hash(state),
+            Value::Grid(grid) => grid.hash(state),
+            Value::Heap(heap) => heap.hash(state),
+            Value::Deque(deque) => deque.hash(state),
+            Value::Ref(r) => r.hash(state),
+        }
+    }
 }
 
 // Representation when printed
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         // Special case: print strings as strings
-        if let Ok(s) = self.clone().into_string() && s.len() > 0 {
+        if let Ok(s) = self.clone().into_string() && !s.is_empty() {
             return write!(f, "{s}");
         }
 
@@ -109,26 +199,194 @@ impl Display for Value {
 
             Value::Unbound(b) => write!(f, "(unbound binding: {b})"),
             Value::Block(_) => write!(f, "(block)"),
+            Value::Regex(r) => write!(f, "(regex: {})", r.pattern()),
+            Value::Grid(g) => write!(f, "{g}"),
+            Value::Heap(h) => {
+                let entries = h.to_sorted_vec().into_iter()
+                    .map(|(item, priority)| Value::Array(vec![item, priority]))
+                    .collect();
+                write!(f, "(heap: {})", Value::Array(entries))
+            },
+            Value::Deque(d) => write!(f, "(deque: {})", Value::Array(d.iter().cloned().collect())),
+            Value::Ref(r) => write!(f, "{r}"),
         }
     }
 }
 
-struct BindingFrame {
-    bindings: HashMap<String, Value>,
+pub struct BindingFrame {
+    pub bindings: HashMap<String, Value>,
+
+    /// Where each binding was made by an assignment action. Bindings made some other way, like
+    /// [Interpreter::set_top_level_binding], don't have one.
+    pub origins: HashMap<String, BindingOrigin>,
 }
 
 impl BindingFrame {
     pub fn new() -> Self {
         BindingFrame {
             bindings: HashMap::new(),
+            origins: HashMap::new(),
         }
     }
 }
 
+/// Where a binding was made.
+#[derive(Debug, Clone)]
+pub struct BindingOrigin {
+    /// The location of the binding atom which was assigned to, like the `$x` in `5 $x :`, or of the
+    /// assignment action if the binding wasn't written directly before it.
+    pub loc: Loc,
+
+    /// Whether it was made with `:const`, so can't be reassigned or shadowed.
+    pub is_const: bool,
+}
+
+/// The ways of assigning to a binding, each with their own assignment action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assignment {
+    /// `:`, which makes a new binding, if the name isn't bound already.
+    Bind,
+
+    /// `:=`, which changes the value of the nearest existing binding.
+    Reassign,
+
+    /// `:shadow`, which makes a new binding in the current scope, even if the name is bound in an
+    /// outer one.
+    Shadow,
+
+    /// `:const`, which makes a new binding like `:`, which then can't be reassigned or shadowed.
+    Const,
+}
+
+impl Default for BindingFrame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The cache of a memoized user action, defined with `::memo`. Results are keyed on the values the
+/// action consumes, so anything else it depends on, like bindings from outer scopes, must not
+/// change between calls. Reading deeper into the stack is an error.
+#[derive(Debug, Clone)]
+pub struct Memo {
+    pub inputs: usize,
+    pub outputs: usize,
+    cache: HashMap<Vec<Value>, Vec<Value>>,
+
+    /// Calls answered from the cache.
+    pub hits: usize,
+
+    /// Calls which had to execute the action.
+    pub misses: usize,
+}
+
+impl Memo {
+    pub fn new(inputs: usize, outputs: usize) -> Self {
+        Memo { inputs, outputs, cache: HashMap::new(), hits: 0, misses: 0 }
+    }
+
+    /// The number of cached results.
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    /// Forgets every cached result. The hit and miss counts are kept.
+    pub fn clear(&mut self) {
+        self.cache.clear();
+    }
+}
+
+/// A block which is currently being executed, either as the body of a user action or by a builtin
+/// like `#` or `map`.
+///
+/// These are only tracked while a [Hook] is installed.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// The name of the user action, or the builtin which executed the block.
+    pub name: String,
+    pub is_action: bool,
+
+    /// The atom which started executing the block.
+    pub call_loc: Loc,
+}
+
+impl Display for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_action {
+            write!(f, "{}", self.name)
+        } else {
+            write!(f, "{{ ... }} {}", self.name)
+        }
+    }
+}
+
+/// Observes execution from within [Interpreter::execute], for tools like debuggers.
+///
+/// Hooks may pause execution for as long as they like, and can stop it by returning an error.
+pub trait Hook {
+    /// Called before each atom is executed.
+    fn before_atom(&mut self, _interpreter: &Interpreter, _node: &Node) -> Result<(), ExecutionError> {
+        Ok(())
+    }
+
+    /// Called after each atom has executed successfully.
+    fn after_atom(&mut self, _interpreter: &Interpreter, _node: &Node) -> Result<(), ExecutionError> {
+        Ok(())
+    }
+
+    /// Called before the body of a block is executed, by a user action call or a builtin like `#`.
+    /// The block is already on the [call stack](Interpreter::call_stack).
+    fn before_block(&mut self, _interpreter: &Interpreter, _body: &Node) -> Result<(), ExecutionError> {
+        Ok(())
+    }
+}
+
+/// The action atom which caused something to execute. Given to [NativeAction]s so that they can
+/// execute blocks on its behalf.
+#[derive(Clone, Copy)]
+pub struct ActionCall<'a> {
+    pub name: &'a str,
+    pub loc: &'a Loc,
+
+    /// The location of the binding atom directly before the action, if there is one, like the `$x`
+    /// in `5 $x :`. Assignment actions record it as where the binding was made.
+    pub target: Option<&'a Loc>,
+}
+
 pub struct Interpreter {
     binding_frames: Vec<BindingFrame>,
     stack: Vec<Value>,
-    user_actions: HashMap<String, Node>,
+    user_actions: HashMap<String, Rc<Node>>,
+
+    /// Caches for user actions defined with `::memo`.
+    memos: HashMap<String, Memo>,
+
+    /// Builtins, and any other actions registered by [Interpreter::register_action]. These take
+    /// priority over user actions.
+    native_actions: HashMap<String, Rc<dyn NativeAction>>,
+
+    /// The lowest height the stack has reached since a checked block started executing.
+    /// See [Interpreter::execute_block_checked].
+    low_water: usize,
+
+    hooks: Vec<Box<dyn Hook>>,
+
+    /// Blocks being executed, innermost last. Only tracked while there are hooks, and not unwound
+    /// by errors, so that hooks can see where the error happened.
+    call_stack: Vec<Frame>,
+
+    /// Where printing actions write to.
+    output: Box<dyn Write>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
@@ -137,6 +395,14 @@ impl Interpreter {
             binding_frames: vec![BindingFrame::new()],
             stack: vec![],
             user_actions: HashMap::new(),
+            memos: HashMap::new(),
+            native_actions: BUILTINS.iter()
+                .map(|b| (b.name.to_owned(), Rc::new(b) as Rc<dyn NativeAction>))
+                .collect(),
+            low_water: 0,
+            hooks: vec![],
+            call_stack: vec![],
+            output: Box::new(stdout()),
         }
     }
 
@@ -144,358 +410,368 @@ impl Interpreter {
         self.binding_frames.first_mut().unwrap().bindings.insert(name.to_owned(), value);
     }
 
+    /// Redirects the output of printing actions, which is stdout by default.
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    /// Installs a hook, which will be called for everything executed from now on.
+    pub fn add_hook(&mut self, hook: Box<dyn Hook>) {
+        self.hooks.push(hook);
+    }
+
+    /// The value stack, with the top last.
+    pub fn stack(&self) -> &[Value] {
+        &self.stack
+    }
+
+    /// Binding frames, innermost last. There is one for the top-level, and then one for each
+    /// [Frame] in the [call stack](Interpreter::call_stack).
+    pub fn binding_frames(&self) -> &[BindingFrame] {
+        &self.binding_frames
+    }
+
+    /// Blocks being executed, innermost last. Empty at the top-level, or if there are no hooks.
+    pub fn call_stack(&self) -> &[Frame] {
+        &self.call_stack
+    }
+
+    /// The body of the user action with the given name, if one has been defined.
+    pub fn user_action(&self, name: &str) -> Option<&Node> {
+        self.user_actions.get(name).map(|body| body.as_ref())
+    }
+
+    /// Every user action which has been defined, and its body, in no particular order.
+    pub fn user_actions(&self) -> impl Iterator<Item = (&str, &Node)> {
+        self.user_actions.iter().map(|(name, body)| (name.as_str(), body.as_ref()))
+    }
+
+    /// The cache of the memoized user action with the given name, if it is one.
+    pub fn memo(&self, name: &str) -> Option<&Memo> {
+        self.memos.get(name)
+    }
+
     pub fn execute(&mut self, node: &Node) -> Result<(), ExecutionError> {
         match &node.kind {
-            NodeKind::Atom(atom) => match atom {
-                Atom::LiteralInteger(i) => self.push(Value::Integer(*i)),
-                Atom::LiteralChar(c) => self.push(Value::Char(*c)),
-                Atom::Action(a) => self.execute_action(a).map_err(|e| e.add_loc(&node.loc))?,
-                Atom::Binding(b) => self.push_binding(b),
-            }
+            NodeKind::Atom(atom) => self.execute_atom(node, atom, None, None)?,
 
             NodeKind::Sequence(ns) => {
-                for n in ns {
-                    self.execute(&n)?;
+                for (i, n) in ns.iter().enumerate() {
+                    match &n.kind {
+                        NodeKind::Atom(atom) => self.execute_atom(n, atom, i.checked_sub(1).map(|p| &ns[p]), ns.get(i + 1))?,
+                        _ => self.execute(n)?,
+                    }
                 }
             }
 
             NodeKind::Block(node) => {
-                self.stack.push(Value::Block(*node.clone()));
+                self.stack.push(Value::Block(node.clone()));
             },
+
+            // Annotations are only used for static checking
+            NodeKind::StackEffect(_) => (),
         }
 
         Ok(())
     }
 
-    fn execute_block(&mut self, node: &Node) -> Result<(), ExecutionError> {
+    /// Executes an atom. `previous` and `next` are the nodes either side of it in its sequence, if
+    /// there are any.
+    fn execute_atom(&mut self, node: &Node, atom: &Atom, previous: Option<&Node>, next: Option<&Node>) -> Result<(), ExecutionError> {
+        if !self.hooks.is_empty() {
+            self.run_hooks(|hook, interpreter| hook.before_atom(interpreter, node))
+                .map_err(|e| e.add_loc(&node.loc))?;
+        }
+
+        match atom {
+            Atom::LiteralInteger(i) => self.push(Value::Integer(*i)),
+            Atom::LiteralChar(c) => self.push(Value::Char(*c)),
+            Atom::LiteralString(s) => self.push(Value::from_string(s)),
+            Atom::Action(a) => {
+                let target = match previous {
+                    Some(Node { kind: NodeKind::Atom(Atom::Binding(_)), loc, .. }) => Some(loc),
+                    _ => None,
+                };
+                let call = ActionCall { name: a, loc: &node.loc, target };
+                match node.builtin {
+                    Some(builtin) => (builtin.run)(self, call),
+                    None => self.execute_action(call),
+                }.map_err(|e| e.add_loc(&node.loc))?
+            },
+
+            // A binding which is about to be assigned is pushed by name even if it's already bound,
+            // so that the assignment can decide whether that's allowed
+            Atom::Binding(b) if matches!(next, Some(Node { kind: NodeKind::Atom(Atom::Action(a)), .. }) if is_assignment_action(a)) =>
+                self.push(Value::Unbound(b.clone())),
+            Atom::Binding(b) => self.push_binding(b),
+        }
+
+        if !self.hooks.is_empty() {
+            self.run_hooks(|hook, interpreter| hook.after_atom(interpreter, node))
+                .map_err(|e| e.add_loc(&node.loc))?;
+        }
+
+        Ok(())
+    }
+
+    fn run_hooks(&mut self, f: impl Fn(&mut dyn Hook, &Interpreter) -> Result<(), ExecutionError>) -> Result<(), ExecutionError> {
+        // Take the hooks out while they run, so that they can be given the whole interpreter
+        let mut hooks = std::mem::take(&mut self.hooks);
+        let result = hooks.iter_mut().try_for_each(|hook| f(hook.as_mut(), self));
+        self.hooks = hooks;
+        result
+    }
+
+    /// Executes the body of a block, in a new binding frame. `call` is the action which is executing
+    /// it, such as a user action call or a builtin like `#`.
+    pub fn execute_block(&mut self, node: &Node, call: ActionCall<'_>) -> Result<(), ExecutionError> {
+        let tracked = !self.hooks.is_empty();
+        if tracked {
+            self.call_stack.push(Frame {
+                name: call.name.to_owned(),
+                is_action: !self.native_actions.contains_key(call.name),
+                call_loc: call.loc.clone(),
+            });
+            self.run_hooks(|hook, interpreter| hook.before_block(interpreter, node))?;
+        }
+
         self.binding_frames.push(BindingFrame::new());
         self.execute(node)?;
         self.binding_frames.pop();
 
+        if tracked {
+            self.call_stack.pop();
+        }
+
         Ok(())
     }
 
-    fn execute_action(&mut self, name: &str) -> Result<(), ExecutionError> {
-        match name {
-            // Core machinery
-            ":" => {
-                let target = self.pop()?;
-                let Value::Unbound(name) = target else {
-                    return Err(ExecutionError::new(format!("bind target `{target}` is not a binding; has it already been assigned?")))
-                };
-
-                let value = self.pop()?;
-                self.binding_frames.last_mut().unwrap().bindings.insert(name, value);
-            },
-            "::" => {
-                let target = self.pop()?;
-                let Value::Unbound(name) = target else {
-                    return Err(ExecutionError::new(format!("bind target `{target}` is not a binding; has it already been assigned?")))
-                };
+    /// Executes a block, and checks that it consumed and left the expected number of values.
+    /// `description` says what the block is, for the error message.
+    ///
+    /// Blocks are allowed to dig deeper into the stack than `inputs` so long as they put back what
+    /// they took, so only the overall change in stack height is compared.
+    pub fn execute_block_checked(&mut self, node: &Node, inputs: usize, outputs: usize, description: &str, call: ActionCall<'_>) -> Result<(), ExecutionError> {
+        self.check_block_effect(node, inputs, outputs, || description.to_owned(), call)
+    }
 
-                // Drop $ off binding name
-                let name = name.strip_prefix('$').unwrap();
+    /// Like [Interpreter::execute_block_checked], but only describes the block if there's an error.
+    fn check_block_effect(&mut self, node: &Node, inputs: usize, outputs: usize, description: impl FnOnce() -> String, call: ActionCall<'_>) -> Result<(), ExecutionError> {
+        let start = self.stack.len();
+        let low_water = self.execute_block_measured(node, call)?;
+
+        let end = self.stack.len();
+        if end as isize - start as isize != outputs as isize - inputs as isize {
+            let consumed = start - low_water;
+            let left = end - low_water;
+            let description = description();
+            return Err(ExecutionError::new(format!(
+                "{description} should consume {inputs} value(s) and leave {outputs}, but it consumed {consumed} and left {left}"
+            )).add_loc(&node.loc))
+        }
 
-                let block = self.pop()?.into_block()?;
+        Ok(())
+    }
 
-                if self.user_actions.contains_key(name) {
-                    return Err(ExecutionError::new(format!("already defined an action named `{name}`")))
-                }
+    /// Executes the body of a block, returning the lowest height the stack reached while it ran.
+    fn execute_block_measured(&mut self, node: &Node, call: ActionCall<'_>) -> Result<usize, ExecutionError> {
+        let outer_low_water = self.low_water;
+        self.low_water = self.stack.len();
 
-                self.user_actions.insert(name.to_owned(), block);
-            },
-            "#" => {
-                let block = self.pop()?.into_block()?;
-                self.execute_block(&block)?;
-            },
-            "true" => self.push(Value::Boolean(true)),
-            "false" => self.push(Value::Boolean(false)),
-            "=" => {
-                let b = self.pop()?;
-                let a = self.pop()?;
+        let result = self.execute_block(node, call);
+        let low_water = self.low_water;
+        self.low_water = min(outer_low_water, low_water);
+        result.map(|()| low_water)
+    }
 
-                self.push(Value::Boolean(a == b));
-            },
-            "?" => {
-                let if_truthy = self.pop()?;
-                let if_falsey = self.pop()?;
-                let cond = self.pop()?.into_boolean()?;
-
-                if cond {
-                    self.push(if_truthy);
-                } else {
-                    self.push(if_falsey);
-                }
-            },
-            "|" => {
-                let b = self.pop()?.into_boolean()?;
-                let a = self.pop()?.into_boolean()?;
+    fn execute_action(&mut self, call: ActionCall<'_>) -> Result<(), ExecutionError> {
+        let name = call.name;
 
-                self.push(Value::Boolean(a || b));
-            },
-            "&" => {
-                let b = self.pop()?.into_boolean()?;
-                let a = self.pop()?.into_boolean()?;
+        if let Some(action) = self.native_actions.get(name) {
+            return action.clone().execute(self, call);
+        }
 
-                self.push(Value::Boolean(a && b));
-            },
-            "!" => {
-                let x = self.pop()?.into_boolean()?;
-                self.push(Value::Boolean(!x));
-            },
-            "while" => {
-                let cond = self.pop()?.into_block()?;
-                let action = self.pop()?.into_block()?;
-
-                loop {
-                    self.execute_block(&cond)?;
-                    let b = self.pop()?.into_boolean()?;
-
-                    if b {
-                        self.execute_block(&action)?;
-                    } else {
-                        break
-                    }
-                }
+        if let Some(body) = self.user_actions.get(name) {
+            let body = body.clone();
+            if !self.memos.is_empty() && self.memos.contains_key(name) {
+                return self.execute_memoized(&body, call);
             }
+            return match body.stack_effect() {
+                Some(effect) => {
+                    let (inputs, outputs) = (effect.inputs.len(), effect.outputs.len());
+                    self.check_block_effect(&body, inputs, outputs, || format!("action `{name}`"), call)
+                },
+                None => self.execute_block(&body, call),
+            }
+        }
 
-            // Basic arithmetic
-            "+" => {
-                let b = self.pop()?.into_integer()?;
-                let a = self.pop()?.into_integer()?;
-                self.push(Value::Integer(a + b))
-            },
-            "-" => {
-                let b = self.pop()?.into_integer()?;
-                let a = self.pop()?.into_integer()?;
-                self.push(Value::Integer(a - b))
-            },
-            "*" => {
-                let b = self.pop()?.into_integer()?;
-                let a = self.pop()?.into_integer()?;
-                self.push(Value::Integer(a * b))
-            },
-            "/" => {
-                let b = self.pop()?.into_integer()?;
-                let a = self.pop()?.into_integer()?;
-                self.push(Value::Integer(a / b))
-            },
-
-            // Numeric comparison
-            ">" => {
-                let b = self.pop()?.into_integer()?;
-                let a = self.pop()?.into_integer()?;
-                self.push(Value::Boolean(a > b))
-            },
-            "<" => {
-                let b = self.pop()?.into_integer()?;
-                let a = self.pop()?.into_integer()?;
-                self.push(Value::Boolean(a < b))
-            },
+        // Oh no!
+        Err(ExecutionError::new(format!("unknown action `{name}`")))
+    }
 
-            // Unary arithmetic
-            "neg" => {
-                let i = self.pop()?.into_integer()?;
-                self.push(Value::Integer(-i))
-            },
-            "abs" => {
-                let i = self.pop()?.into_integer()?;
-                self.push(Value::Integer(i.abs()))
-            },
+    /// Executes a memoized user action, or reuses its results from an earlier call with the same
+    /// inputs. Nothing the action would have done besides its results, like printing, happens
+    /// again on a cache hit.
+    fn execute_memoized(&mut self, body: &Node, call: ActionCall<'_>) -> Result<(), ExecutionError> {
+        let name = call.name;
+        let memo = &self.memos[name];
+        let (inputs, outputs) = (memo.inputs, memo.outputs);
+        if self.stack.len() < inputs {
+            return Err(ExecutionError::new(format!(
+                "memoized action `{name}` consumes {inputs} value(s), but the stack only has {}", self.stack.len()
+            )));
+        }
 
-            // Stack unpack
-            "." | ".." | "..." | "...." | "....." | "......" => {
-                let a = self.pop()?.into_array()?;
-                let expected_count = name.len();
+        let key = self.stack[self.stack.len() - inputs..].to_vec();
+        if let Some(results) = memo.cache.get(&key) {
+            let results = results.clone();
+            self.stack.truncate(self.stack.len() - inputs);
+            self.low_water = min(self.low_water, self.stack.len());
+            self.stack.extend(results);
+            self.memos.get_mut(name).unwrap().hits += 1;
+            return Ok(());
+        }
 
-                if expected_count != a.len() {
-                    return Err(ExecutionError::new(format!("unpack action `{name}` expected {expected_count} items but got {}", a.len())))
-                }
+        // Unlike other checked blocks, this can't dig beneath its inputs, even if it puts back what it
+        // took, as then its results would depend on values which aren't part of the key
+        let start = self.stack.len();
+        let low_water = self.execute_block_measured(body, call)?;
+        let end = self.stack.len();
+        if low_water < start - inputs || end - low_water != outputs {
+            return Err(ExecutionError::new(format!(
+                "memoized action `{name}` should consume {inputs} value(s) and leave {outputs}, but it consumed {} and left {}",
+                start - low_water, end - low_water,
+            )).add_loc(&body.loc))
+        }
 
-                for item in a {
-                    self.push(item);
-                }
-            },
+        let results = self.stack[self.stack.len() - outputs..].to_vec();
+        let memo = self.memos.get_mut(name).unwrap();
+        memo.misses += 1;
+        memo.cache.insert(key, results);
+        Ok(())
+    }
 
-            // Array operations
-            "[]" => {
-                self.push(Value::Array(vec![]))
-            },
-            "@" => {
-                let index = self.pop()?.into_integer()?;
-                let mut arr = self.pop()?.into_array()?;
+    /// Adds a native action, which can then be called like any other. Fails if there's already a
+    /// builtin, native or user action with the same name, or the stack effect is invalid.
+    pub fn register_action(&mut self, action: impl NativeAction + 'static) -> Result<(), ExecutionError> {
+        let name = action.name();
+        if self.native_actions.contains_key(name) || self.user_actions.contains_key(name) {
+            return Err(ExecutionError::new(format!("already defined an action named `{name}`")))
+        }
+        if let Err(e) = StackEffect::parse(action.effect()) {
+            return Err(ExecutionError::new(format!("invalid stack effect for native action `{name}`: {e}")))
+        }
 
-                if index < 0 || index >= arr.len() as isize {
-                    return Err(ExecutionError::new(format!("index out of range `{index}`")))
-                }
+        self.native_actions.insert(name.to_owned(), Rc::new(action));
+        Ok(())
+    }
 
-                self.push(arr.remove(index as usize));
-            },
-            "length" => {
-                let arr = self.pop()?.into_array()?;
-                self.push(Value::Integer(arr.len() as isize));
-            }
-            "append" => {
-                let v = self.pop()?;
-                let mut arr = self.pop()?.into_array()?;
-                arr.push(v);
+    /// Registers a closure as a native action. See [Interpreter::register_action].
+    pub fn register_fn(
+        &mut self,
+        name: &str,
+        effect: &str,
+        doc: &str,
+        run: impl Fn(&mut Interpreter, ActionCall<'_>) -> Result<(), ExecutionError> + 'static,
+    ) -> Result<(), ExecutionError> {
+        self.register_action(NativeFn { name: name.to_owned(), effect: effect.to_owned(), doc: doc.to_owned(), run })
+    }
 
-                self.push(Value::Array(arr));
-            },
-            "range" => {
-                let end = self.pop()?.into_integer()?;
-                let start = self.pop()?.into_integer()?;
+    /// The builtin or registered native action with the given name, if there is one.
+    pub fn native_action(&self, name: &str) -> Option<&dyn NativeAction> {
+        self.native_actions.get(name).map(|a| a.as_ref())
+    }
 
-                self.push(Value::Array(
-                    (start..=end).map(Value::Integer).collect()
-                ));
-            },
-            "map" => {
-                let op = self.pop()?.into_block()?;
-                let arr = self.pop()?.into_array()?;
-
-                let mut new_arr = vec![];
-                for item in arr {
-                    self.push(item);
-                    self.execute_block(&op)?;
-                    new_arr.push(self.pop()?);
-                }
+    /// Assigns a binding in the innermost binding frame, whether or not it's already bound.
+    pub fn bind(&mut self, name: &str, value: Value) {
+        let frame = self.binding_frames.last_mut().unwrap();
+        frame.bindings.insert(name.to_owned(), value);
+        frame.origins.remove(name);
+    }
 
-                self.push(Value::Array(new_arr));
-            },
-            "++" => {
-                let b = self.pop()?.into_array()?;
-                let a = self.pop()?.into_array()?;
+    /// Assigns a binding like an assignment action would, failing if that kind of assignment isn't
+    /// allowed. See [Assignment]. `loc` is where the binding is being made, which errors about it
+    /// later will point to.
+    pub fn assign(&mut self, name: &str, value: Value, assignment: Assignment, loc: &Loc) -> Result<(), ExecutionError> {
+        let existing = self.binding_frames.iter().rposition(|frame| frame.bindings.contains_key(name));
+        let origin = existing.and_then(|i| self.binding_frames[i].origins.get(name));
+
+        // Points at the original binding too, if we know where it was made
+        let error = |message: String| {
+            let error = ExecutionError::new(message);
+            match origin {
+                Some(origin) => error.with_note(format!("`{name}` was bound here"), &origin.loc),
+                None => error,
+            }
+        };
+
+        match assignment {
+            Assignment::Reassign | Assignment::Shadow if origin.is_some_and(|o| o.is_const) => {
+                let verb = if assignment == Assignment::Reassign { "reassign" } else { "shadow" };
+                return Err(error(format!("can't {verb} `{name}`, because it's a constant")));
+            },
+            Assignment::Bind | Assignment::Const if existing.is_some() =>
+                return Err(error(format!("`{name}` is already bound; use `:=` to reassign it, or `:shadow` to hide it with a new binding"))),
+            Assignment::Reassign => {
+                let Some(i) = existing else {
+                    return Err(error(format!("can't reassign `{name}`, because it isn't bound; use `:` to bind it")));
+                };
 
-                self.push(Value::Array([a, b].concat()))
+                // Where it was bound stays the same
+                self.binding_frames[i].bindings.insert(name.to_owned(), value);
+                return Ok(());
             },
-            "fold" => {
-                let mut acc = self.pop()?;
-                let op = self.pop()?.into_block()?; // called with array item on top, then acc
-                let arr = self.pop()?.into_array()?;
-
-                for item in arr {
-                    self.push(acc);
-                    self.push(item);
-                    self.execute_block(&op)?;
-                    acc = self.pop()?;
-                }
+            _ => (),
+        }
 
-                self.push(acc);
-            },
-            "sort" => {
-                let mut arr = self.pop()?.into_integer_array()?;
-                arr.sort();
+        let frame = self.binding_frames.last_mut().unwrap();
+        frame.bindings.insert(name.to_owned(), value);
+        frame.origins.insert(name.to_owned(), BindingOrigin { loc: loc.clone(), is_const: assignment == Assignment::Const });
+        Ok(())
+    }
 
-                self.push(Value::Array(
-                    arr.into_iter().map(|i| Value::Integer(i)).collect()
-                ))
-            },
-            "shift" => {
-                let mut arr = self.pop()?.into_array()?;
-                let first = arr.remove(0);
+    /// Defines a user action. Fails if there's already a user action with the same name.
+    pub fn define_action(&mut self, name: &str, body: impl Into<Rc<Node>>) -> Result<(), ExecutionError> {
+        if self.user_actions.contains_key(name) {
+            return Err(ExecutionError::new(format!("already defined an action named `{name}`")))
+        }
 
-                self.push(Value::Array(arr));
-                self.push(first);
-            },
-            "break" => {
-                let pred = self.pop()?.into_block()?;
-                let arr = self.pop()?.into_array()?;
-
-                // Create a new array whenever an item matches the predicate
-                // but KEEP the item which satisfied the predicate
-                // (That's why we're called `break` and not `split`, though I don't think it's a
-                //  great name...)
-                let mut result = vec![vec![]];
-                for item in arr {
-                    // Invoke predicate
-                    self.push(item.clone());
-                    self.execute_block(&pred)?;
-                    let is_delimiter = self.pop()?.into_boolean()?;
-
-                    if is_delimiter {
-                        // Delimiter: add new array containing just this
-                        // (Wrapped in an array so you can `map` over the broken array and treat
-                        //  all items in the same way)
-                        result.push(vec![item]);
-
-                        // ...then start new list for non-delimiters
-                        result.push(vec![]);
-                    } else {
-                        // Non-delimiter: just keep adding onto the last bit
-                        result.last_mut().unwrap().push(item);
-                    }
-                }
+        self.user_actions.insert(name.to_owned(), body.into());
+        Ok(())
+    }
 
-                // Push result
-                self.push(
-                    Value::Array(result.into_iter().map(Value::Array).collect())
-                );
-            },
-            "reverse" => {
-                let mut arr = self.pop()?.into_array()?;
-                arr.reverse();
-                self.push(Value::Array(arr));
-            }
+    /// Defines a user action whose results are cached by the values it consumes. The action must
+    /// consume `inputs` values and leave `outputs`. See [Memo].
+    pub fn define_memoized_action(&mut self, name: &str, body: impl Into<Rc<Node>>, inputs: usize, outputs: usize) -> Result<(), ExecutionError> {
+        self.define_action(name, body)?;
+        self.memos.insert(name.to_owned(), Memo::new(inputs, outputs));
+        Ok(())
+    }
 
-            // String operations
-            // TODO: can be implemented as more general array operations now
-            "lines" => {
-                let s = self.pop()?.into_string()?;
-                
-                self.push(Value::Array(
-                    s.split("\n")
-                        .map(Value::from_string)
-                        .collect()
-                ));
-            },
-            "wsplit" => {
-                let s = self.pop()?.into_string()?;
-                
-                self.push(Value::Array(
-                    s.split_ascii_whitespace()
-                        .map(Value::from_string)
-                        .collect()
-                ));
-            },
-            "int" => {
-                let s = self.pop()?.into_string()?;
-                match s.parse() {
-                    Ok(i) => self.push(Value::Integer(i)),
-                    Err(_) => return Err(ExecutionError::new(format!("not convertible to integer: `{s}`"))),
-                }
+    /// Forgets the cached results of a memoized user action. Fails if it isn't one.
+    pub fn clear_memo(&mut self, name: &str) -> Result<(), ExecutionError> {
+        match self.memos.get_mut(name) {
+            Some(memo) => {
+                memo.clear();
+                Ok(())
             },
-            
-            // Character operations
-            "digit?" => {
-                let c = self.pop()?.into_char()?;
-                self.push(Value::Boolean(c.is_digit(10)));
-            }
-
-            // I/O
-            "print" => print!("{}", self.pop()?),
-            "println" => println!("{}", self.pop()?),
-            "debug" => self.print_stack_debug(),
-
-            // User actions
-            _ if self.user_actions.contains_key(name) => {
-                let body = self.user_actions.get(name).unwrap().clone();
-                self.execute_block(&body)?;
-            }
-            
-            // Oh no!
-            _ => return Err(ExecutionError::new(format!("unknown action `{name}`"))),
+            None if self.user_actions.contains_key(name) =>
+                Err(ExecutionError::new(format!("action `{name}` isn't memoized; define it with `::memo` instead of `::`"))),
+            None => Err(ExecutionError::new(format!("unknown action `{name}`"))),
         }
+    }
 
-        Ok(())
+    /// Where printing actions write to. See [Interpreter::set_output].
+    pub fn output(&mut self) -> &mut dyn Write {
+        &mut self.output
     }
 
-    pub fn print_stack_debug(&self) {
-        println!("\n=== TOP ===");
+    pub fn print_stack_debug(&mut self) -> std::io::Result<()> {
+        writeln!(self.output, "\n=== TOP ===")?;
         for item in self.stack.iter().rev() {
-            println!("{item}");
+            writeln!(self.output, "{item}")?;
         }
-        println!("===========");
+        writeln!(self.output, "===========")
     }
 
     fn push_binding(&mut self, name: &str) {
@@ -511,13 +787,18 @@ impl Interpreter {
         self.push(Value::Unbound(name.to_owned()))
     }
 
-    fn push(&mut self, value: Value) {
+    /// Pushes a value onto the top of the stack.
+    pub fn push(&mut self, value: Value) {
         self.stack.push(value)
     }
 
-    fn pop(&mut self) -> Result<Value, ExecutionError> {
+    /// Pops the value from the top of the stack, or fails if it's empty.
+    pub fn pop(&mut self) -> Result<Value, ExecutionError> {
         match self.stack.pop() {
-            Some(v) => Ok(v),
+            Some(v) => {
+                self.low_water = min(self.low_water, self.stack.len());
+                Ok(v)
+            },
             None => Err(ExecutionError::new("attempted to pop from empty stack")),
         }
     }
@@ -530,11 +811,32 @@ impl Interpreter {
 pub struct ExecutionError {
     message: String,
     loc: Option<Loc>,
+
+    /// Other places relevant to the error, each with a message saying why.
+    notes: Vec<(String, Loc)>,
 }
 
 impl ExecutionError {
     pub fn new(message: impl Into<String>) -> Self {
-        Self { message: message.into(), loc: None }
+        Self { message: message.into(), loc: None, notes: vec![] }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn loc(&self) -> Option<&Loc> {
+        self.loc.as_ref()
+    }
+
+    pub fn notes(&self) -> &[(String, Loc)] {
+        &self.notes
+    }
+
+    /// Adds a note pointing at another place relevant to the error.
+    pub fn with_note(mut self, message: impl Into<String>, loc: &Loc) -> Self {
+        self.notes.push((message.into(), loc.clone()));
+        self
     }
 
     pub fn add_loc(self, loc: &Loc) -> Self {
@@ -556,7 +858,141 @@ impl Display for ExecutionError {
 
         write!(f, ": {}", self.message)?;
 
+        for (message, loc) in &self.notes {
+            write!(f, "\n  note: {message}, at `{}` (position {:?} in {})", loc.contents(), loc.pos, loc.source.name)?;
+        }
+
         Ok(())
     }
 }
 impl Error for ExecutionError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::{code_to_node, load_stdlib};
+
+    use super::*;
+
+    fn run(code: &str) -> Result<Vec<Value>, ExecutionError> {
+        let mut interpreter = Interpreter::new();
+        interpreter.execute(&load_stdlib().unwrap())?;
+        interpreter.execute(&code_to_node(code, "test").unwrap())?;
+        Ok(interpreter.stack().to_vec())
+    }
+
+    /// Runs code which should fail, and returns the error message and the code at its location.
+    fn error(code: &str) -> (String, String) {
+        let error = run(code).unwrap_err();
+        (error.message().to_owned(), error.loc().unwrap().contents())
+    }
+
+    #[test]
+    fn combinator_blocks_are_checked() {
+        assert_eq!(error("[ 1 , 2 ] { dup } map"), (
+            "block passed to `map` should consume 1 value(s) and leave 1, but it consumed 1 and left 2".to_owned(),
+            "dup".to_owned(),
+        ));
+        assert_eq!(error("[ 1 , 2 ] { + 1 } 0 fold"), (
+            "block passed to `fold` should consume 2 value(s) and leave 1, but it consumed 2 and left 2".to_owned(),
+            "+ 1".to_owned(),
+        ));
+        assert_eq!(error("{ } { 1 2 } while"), (
+            "condition block passed to `while` should consume 0 value(s) and leave 1, but it consumed 0 and left 2".to_owned(),
+            "1 2".to_owned(),
+        ));
+        assert_eq!(error("0 { 1 } { true } while"), (
+            "action block passed to `while` should consume 0 value(s) and leave 0, but it consumed 0 and left 1".to_owned(),
+            "1".to_owned(),
+        ));
+        assert_eq!(error("[ 1 , 2 ] { drop } break"), (
+            "predicate block passed to `break` should consume 1 value(s) and leave 1, but it consumed 1 and left 0".to_owned(),
+            "drop".to_owned(),
+        ));
+
+        // Blocks can use values beneath their inputs, so long as they put them back
+        assert_eq!(run("5 [ 1 , 2 ] { swap dup pull2 + } map").unwrap(), vec![Value::Integer(5), Value::Array(vec![Value::Integer(6), Value::Integer(7)])]);
+    }
+
+    #[test]
+    fn annotated_blocks_and_actions_are_checked() {
+        assert_eq!(error("1 { ( a -- b ) drop } #"), (
+            "annotated block should consume 1 value(s) and leave 1, but it consumed 1 and left 0".to_owned(),
+            "( a -- b ) drop".to_owned(),
+        ));
+        assert_eq!(error("{ ( a -- ) } $f ::  1 f"), (
+            "action `f` should consume 1 value(s) and leave 0, but it consumed 0 and left 0".to_owned(),
+            "( a -- )".to_owned(),
+        ));
+        assert_eq!(run("{ ( a -- b ) 1 + } $f ::  1 f").unwrap(), vec![Value::Integer(2)]);
+    }
+
+    #[test]
+    fn annotated_choice_branches_must_agree() {
+        assert_eq!(error("1 true { ( a -- ) drop } { ( a -- a ) } ? #"), (
+            "unbalanced `?` branches: falsey branch is annotated `( a -- )`, but truthy branch is annotated `( a -- a )`".to_owned(),
+            "?".to_owned(),
+        ));
+        assert_eq!(run("1 true { ( a -- ) drop } { ( b -- ) drop } ? #").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn memo_hit_counts_consumed_inputs_in_checked_blocks() {
+        // The second call to `add` is a cache hit, inside a block which digs beneath its inputs
+        let memoized = run("{ ( a b -- c ) + } $add ::memo  0 1 add drop  0 [ 1 , 2 ] { add } map").unwrap_err();
+        let plain = run("{ ( a b -- c ) + } $add ::  0 1 add drop  0 [ 1 , 2 ] { add } map").unwrap_err();
+        assert_eq!(memoized.message, plain.message);
+        assert!(memoized.message.contains("consumed 2 and left 1"), "{memoized}");
+    }
+
+    #[test]
+    fn memo_hit_leaves_cached_results() {
+        let stack = run("{ ( a b -- c ) + } $add ::memo  1 2 add  1 2 add").unwrap();
+        assert_eq!(stack, vec![Value::Integer(3), Value::Integer(3)]);
+    }
+
+    #[test]
+    fn memoized_actions_cant_read_beneath_their_inputs() {
+        // Without the check, the second call would be a hit and leave 1, rather than 20
+        let error = run("{ ( a -- ) swap drop } $f ::memo  10 1 f  20 1 f").unwrap_err();
+        assert!(error.message.contains("memoized action `f` should consume 1 value(s) and leave 0, but it consumed 2 and left 1"), "{error}");
+        assert!(run("{ ( a -- ) swap drop } $f ::  10 1 f  20 1 f").is_ok());
+
+        // Inferred effects count everything the body reads
+        let stack = run("{ 0 = { } { swap } ? # } $f ::memo  1 2 0 f  3 2 0 f").unwrap();
+        assert_eq!(stack, [2, 1, 2, 3].map(Value::Integer));
+    }
+
+    #[test]
+    fn binding_forms() {
+        let ints = |items: &[isize]| items.iter().copied().map(Value::Integer).collect::<Vec<_>>();
+
+        // `:=` changes the binding in whichever scope it was made
+        assert_eq!(run("1 $x :  { 2 $x := } #  $x").unwrap(), ints(&[2]));
+
+        // `:shadow` hides it until the block ends
+        assert_eq!(run("1 $x :  { 2 $x :shadow  $x } #  $x").unwrap(), ints(&[2, 1]));
+
+        // Constants can be read like anything else
+        assert_eq!(run("1 $x :const  $x $x +").unwrap(), ints(&[2]));
+
+        assert_eq!(error("1 $x :  2 $x :").0, "`$x` is already bound; use `:=` to reassign it, or `:shadow` to hide it with a new binding");
+        assert_eq!(error("1 $x :  { 2 $x : } #").0, "`$x` is already bound; use `:=` to reassign it, or `:shadow` to hide it with a new binding");
+        assert_eq!(error("2 $x :=").0, "can't reassign `$x`, because it isn't bound; use `:` to bind it");
+        assert_eq!(error("1 $x :const  { 2 $x :shadow } #").0, "can't shadow `$x`, because it's a constant");
+        assert_eq!(error("1 2 :").0, "bind target `2` is not a binding");
+    }
+
+    #[test]
+    fn binding_errors_point_to_the_original_binding() {
+        let error = run("1 $x :const\n2 $x :=").unwrap_err();
+        assert_eq!(error.message(), "can't reassign `$x`, because it's a constant");
+        assert_eq!(error.loc().unwrap().contents(), ":=");
+        let (note, loc) = &error.notes()[0];
+        assert_eq!(note, "`$x` was bound here");
+        assert_eq!((loc.contents(), loc.pos), ("$x".to_owned(), 2));
+
+        // Reassigning keeps where it was first bound
+        let error = run("1 $x :  2 $x :=  3 $x :").unwrap_err();
+        assert_eq!(error.notes()[0].1.pos, 2);
+    }
+}
@@ -0,0 +1,196 @@
+use std::{cmp::min, error::Error, rc::Rc};
+
+use crate::{cst::{definition_ending_at, parse_cst, CstNode}, loc::LocSource, parser::{parse, Node, NodeKind}, token::{tokenize, tokenize_with_trivia, Atom, Token, TokenKind}};
+
+const INDENT: &str = "    ";
+
+/// Formats source code, keeping comments and the original line breaks, but normalising:
+///   - Indentation, based on how deeply nested in blocks and `[ ... ]` array sugar each line is
+///   - Spacing, so there's exactly one space between tokens on the same line
+///   - Blank lines, so there's at most one in a row, and always one after an action definition
+///     (unless it's a one-liner followed by another one-liner)
+///
+/// Returns an error if the formatted code would parse differently to the original, which would be
+/// a bug in the formatter.
+pub fn format_source(source: &LocSource) -> Result<String, Box<dyn Error>> {
+    let cst = parse_cst(tokenize_with_trivia(source)?)?;
+
+    let mut formatter = Formatter::new();
+    formatter.format_nodes(&cst, 0);
+    let formatted = formatter.finish();
+
+    // Safety net - make sure we haven't changed what the code means
+    let formatted_source = LocSource::new(source.name.clone(), Rc::new(formatted.clone()));
+    let original = parse(tokenize(source)?, source)?;
+    let reformatted = parse(tokenize(&formatted_source)?, &formatted_source)?;
+    if !same_tree(&original, &reformatted) {
+        return Err(format!("formatting {} would change how it parses", source.name).into())
+    }
+
+    Ok(formatted)
+}
+
+struct Formatter {
+    output: String,
+
+    /// The number of line breaks seen since the last token was written.
+    pending_newlines: usize,
+
+    /// How many `[` array sugar brackets are currently open within the current block.
+    bracket_depth: usize,
+
+    /// Set after an action definition, to ensure that a blank line is written after it.
+    blank_line_due: bool,
+}
+
+impl Formatter {
+    fn new() -> Self {
+        Formatter {
+            output: String::new(),
+            pending_newlines: 0,
+            bracket_depth: 0,
+            blank_line_due: false,
+        }
+    }
+
+    fn finish(mut self) -> String {
+        // End with a newline, unless there's nothing at all
+        if !self.output.is_empty() {
+            self.output.push('\n');
+        }
+        self.output
+    }
+
+    /// Writes a token to the output, starting a new line first if there were line breaks before it.
+    fn write(&mut self, text: &str, depth: usize) {
+        if self.blank_line_due && self.pending_newlines > 0 {
+            self.pending_newlines = 2;
+        }
+        self.blank_line_due = false;
+
+        if self.output.is_empty() {
+            // Start of the file - drop any leading blank lines
+        } else if self.pending_newlines > 0 {
+            for _ in 0..min(self.pending_newlines, 2) {
+                self.output.push('\n');
+            }
+            for _ in 0..(depth + self.bracket_depth) {
+                self.output.push_str(INDENT);
+            }
+        } else {
+            self.output.push(' ');
+        }
+
+        self.output.push_str(text);
+        self.pending_newlines = 0;
+    }
+
+    fn write_token(&mut self, token: &Token, depth: usize) {
+        match &token.kind {
+            TokenKind::Whitespace(w) => self.pending_newlines += w.matches('\n').count(),
+            TokenKind::Comment(c) => self.write(c.trim_end(), depth),
+
+            TokenKind::Atom(Atom::Action(a)) if a == "[" => {
+                self.write(a, depth);
+                self.bracket_depth += 1;
+            },
+            TokenKind::Atom(Atom::Action(a)) if a == "]" => {
+                // Decrease first, so that a `]` starting a line lines up with its `[`
+                self.bracket_depth = self.bracket_depth.saturating_sub(1);
+                self.write(a, depth);
+            },
+
+            _ => self.write(&token.loc.contents(), depth),
+        }
+    }
+
+    fn format_nodes(&mut self, nodes: &[CstNode], depth: usize) {
+        for (i, node) in nodes.iter().enumerate() {
+            match node {
+                CstNode::Token(token) => {
+                    self.write_token(token, depth);
+
+                    if let Some(definition) = definition_ending_at(nodes, i) {
+                        // Keep runs of one-line definitions together, but otherwise separate
+                        // definitions from whatever comes next
+                        let next_multiline = next_definition(nodes, i + 1).map(|block| block.is_multiline());
+                        if definition.block.is_multiline() || next_multiline != Some(false) {
+                            self.blank_line_due = true;
+                        }
+                    }
+                },
+
+                CstNode::Block { open, children, close } => {
+                    self.write_token(open, depth);
+
+                    // Array sugar can't span blocks, so start afresh inside
+                    let outer_bracket_depth = self.bracket_depth;
+                    self.bracket_depth = 0;
+                    self.format_nodes(children, depth + 1);
+                    self.bracket_depth = outer_bracket_depth;
+
+                    self.write_token(close, depth);
+                },
+
+                CstNode::StackEffect { open, children, close } => {
+                    self.write_token(open, depth);
+                    for child in children {
+                        self.write_token(child, depth + 1);
+                    }
+                    self.write_token(close, depth);
+                },
+            }
+        }
+    }
+}
+
+/// If the next significant nodes from `start` onwards are a definition, returns its block.
+fn next_definition(nodes: &[CstNode], start: usize) -> Option<&CstNode> {
+    let significant = nodes[start..].iter()
+        .enumerate()
+        .filter(|(_, n)| !matches!(n, CstNode::Token(t) if t.is_trivia()))
+        .take(3)
+        .collect::<Vec<_>>();
+
+    let &[_, _, (offset, _)] = significant.as_slice() else { return None };
+    definition_ending_at(nodes, start + offset).map(|d| d.block)
+}
+
+/// Whether two nodes are the same, ignoring their locations.
+fn same_tree(a: &Node, b: &Node) -> bool {
+    match (&a.kind, &b.kind) {
+        (NodeKind::Atom(a), NodeKind::Atom(b)) => a == b,
+        (NodeKind::Sequence(a), NodeKind::Sequence(b)) =>
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same_tree(a, b)),
+        (NodeKind::Block(a), NodeKind::Block(b)) => same_tree(a, b),
+        (NodeKind::StackEffect(a), NodeKind::StackEffect(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(code: &str) -> String {
+        format_source(&LocSource::new("test".to_owned(), Rc::new(code.to_owned()))).unwrap()
+    }
+
+    #[test]
+    fn keeps_non_ascii_comments() {
+        assert_eq!(format("// éé\n12 println\n"), "// éé\n12 println\n");
+        assert_eq!(format("// café\n1 println\n"), "// café\n1 println\n");
+    }
+
+    #[test]
+    fn formats_programs_without_tokens() {
+        assert_eq!(format(""), "");
+        assert_eq!(format("// café\n"), "// café\n");
+    }
+
+    #[test]
+    fn keeps_non_ascii_tokens() {
+        assert_eq!(format("\"héllo wörld\" println\n"), "\"héllo wörld\" println\n");
+        assert_eq!(format("{   'é'    println } $grüß ::\n  grüß\n"), "{ 'é' println } $grüß ::\n\ngrüß\n");
+    }
+}
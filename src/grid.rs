@@ -0,0 +1,257 @@
+//! A 2D grid of values, as used by the `grid-*` builtins.
+//!
+//! Points are `[x y]`, with `x` the column and `y` the row, both counting from zero at the top
+//! left - the same way round as a grid made from lines of text.
+
+use std::{collections::VecDeque, fmt::Display};
+
+use crate::eval::{ExecutionError, Value};
+
+pub type Point = (isize, isize);
+
+/// Offsets to the neighbours sharing an edge with a point, clockwise from up.
+pub const ORTHOGONAL: [Point; 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+
+/// Offsets to all neighbours of a point, including diagonals, clockwise from up.
+pub const ALL_DIRECTIONS: [Point; 8] = [(0, -1), (1, -1), (1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1)];
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Grid {
+    width: usize,
+    height: usize,
+
+    /// Cells in reading order, row by row.
+    cells: Vec<Value>,
+}
+
+impl Grid {
+    /// Parses a grid of characters from lines of text. Every line must be the same length, but a
+    /// trailing newline is ignored.
+    pub fn parse(text: &str) -> Result<Grid, ExecutionError> {
+        let lines = text.strip_suffix('\n').unwrap_or(text).split('\n').collect::<Vec<_>>();
+        let width = lines[0].chars().count();
+
+        let mut cells = vec![];
+        for (y, line) in lines.iter().enumerate() {
+            let len = line.chars().count();
+            if len != width {
+                return Err(ExecutionError::new(format!("grid rows must all be the same length, but row 0 has {width} characters and row {y} has {len}")));
+            }
+            cells.extend(line.chars().map(Value::Char));
+        }
+
+        Ok(Grid { width, height: lines.len(), cells })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn in_bounds(&self, (x, y): Point) -> bool {
+        x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height
+    }
+
+    fn index(&self, point: Point) -> Option<usize> {
+        self.in_bounds(point).then(|| point.1 as usize * self.width + point.0 as usize)
+    }
+
+    pub fn get(&self, point: Point) -> Option<&Value> {
+        self.index(point).map(|i| &self.cells[i])
+    }
+
+    /// Replaces the value at a point. Returns false if it's out of bounds.
+    pub fn set(&mut self, point: Point, value: Value) -> bool {
+        match self.index(point) {
+            Some(i) => {
+                self.cells[i] = value;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// The in-bounds points at the given offsets from a point.
+    pub fn neighbours(&self, (x, y): Point, offsets: &[Point]) -> Vec<Point> {
+        offsets.iter()
+            .map(|(dx, dy)| (x + dx, y + dy))
+            .filter(|p| self.in_bounds(*p))
+            .collect()
+    }
+
+    /// Every point, in reading order.
+    pub fn points(&self) -> impl Iterator<Item = Point> + '_ {
+        (0..self.height).flat_map(|y| (0..self.width).map(move |x| (x as isize, y as isize)))
+    }
+
+    /// Points whose value equals `value`, in reading order.
+    pub fn find_all(&self, value: &Value) -> Vec<Point> {
+        self.points().filter(|p| self.get(*p) == Some(value)).collect()
+    }
+
+    pub fn row(&self, y: usize) -> Option<Vec<Value>> {
+        (y < self.height).then(|| self.cells[y * self.width..(y + 1) * self.width].to_vec())
+    }
+
+    pub fn column(&self, x: usize) -> Option<Vec<Value>> {
+        (x < self.width).then(|| (0..self.height).map(|y| self.cells[y * self.width + x].clone()).collect())
+    }
+
+    /// Values along a line from `start`, stepping by `direction`, until leaving the grid.
+    pub fn ray(&self, start: Point, direction: Point) -> Vec<Value> {
+        let mut values = vec![];
+        let mut point = start;
+        while let Some(value) = self.get(point) {
+            values.push(value.clone());
+            point = (point.0 + direction.0, point.1 + direction.1);
+        }
+        values
+    }
+
+    /// Every diagonal running down and to the right, from the bottom left corner to the top right,
+    /// followed by every diagonal running down and to the left, from the top left to the bottom
+    /// right.
+    pub fn diagonals(&self) -> Vec<Vec<Value>> {
+        let (width, height) = (self.width as isize, self.height as isize);
+        let down_right = (0..height).rev().map(|y| (0, y))
+            .chain((1..width).map(|x| (x, 0)))
+            .map(|start| self.ray(start, (1, 1)));
+        let down_left = (0..width).map(|x| (x, 0))
+            .chain((1..height).map(|y| (width - 1, y)))
+            .map(|start| self.ray(start, (-1, 1)));
+
+        down_right.chain(down_left).filter(|d| !d.is_empty()).collect()
+    }
+
+    /// The connected region of points with the same value as `start`, moving between orthogonal
+    /// neighbours. Points are in the order they were reached.
+    pub fn flood_fill(&self, start: Point) -> Vec<Point> {
+        let mut seen = vec![false; self.cells.len()];
+        self.flood_fill_marking(start, &mut seen)
+    }
+
+    /// Splits the grid into connected regions of equal values, ordered by the first point of each
+    /// in reading order.
+    pub fn regions(&self) -> Vec<Vec<Point>> {
+        let mut seen = vec![false; self.cells.len()];
+        self.points()
+            .filter_map(|p| {
+                let region = self.flood_fill_marking(p, &mut seen);
+                (!region.is_empty()).then_some(region)
+            })
+            .collect()
+    }
+
+    /// Flood fills from `start`, skipping and marking points in `seen`.
+    fn flood_fill_marking(&self, start: Point, seen: &mut [bool]) -> Vec<Point> {
+        let Some(start_index) = self.index(start) else { return vec![] };
+        if seen[start_index] {
+            return vec![];
+        }
+        seen[start_index] = true;
+
+        let value = &self.cells[start_index];
+        let mut region = vec![];
+        let mut queue = VecDeque::from([start]);
+        while let Some(point) = queue.pop_front() {
+            region.push(point);
+            for neighbour in self.neighbours(point, &ORTHOGONAL) {
+                let i = self.index(neighbour).unwrap();
+                if !seen[i] && &self.cells[i] == value {
+                    seen[i] = true;
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+        region
+    }
+
+    /// The rows, as arrays.
+    pub fn rows(&self) -> Vec<Vec<Value>> {
+        (0..self.height).map(|y| self.row(y).unwrap()).collect()
+    }
+}
+
+// Printed as text, one row per line
+impl Display for Grid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (y, row) in self.rows().into_iter().enumerate() {
+            if y > 0 {
+                writeln!(f)?;
+            }
+            for cell in row {
+                write!(f, "{cell}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(s: &str) -> Vec<Value> {
+        s.chars().map(Value::Char).collect()
+    }
+
+    #[test]
+    fn parsing() {
+        let grid = Grid::parse("abc\ndef\n").unwrap();
+        assert_eq!((grid.width(), grid.height()), (3, 2));
+        assert_eq!(grid.get((2, 1)), Some(&Value::Char('f')));
+        assert_eq!(grid.get((3, 0)), None);
+        assert_eq!(grid.get((0, -1)), None);
+        assert_eq!(grid.to_string(), "abc\ndef");
+
+        assert_eq!(
+            Grid::parse("abc\nde").unwrap_err().message(),
+            "grid rows must all be the same length, but row 0 has 3 characters and row 1 has 2",
+        );
+    }
+
+    #[test]
+    fn setting() {
+        let mut grid = Grid::parse("ab\ncd").unwrap();
+        assert!(grid.set((1, 0), Value::Char('x')));
+        assert!(!grid.set((2, 0), Value::Char('x')));
+        assert_eq!(grid.to_string(), "ax\ncd");
+    }
+
+    #[test]
+    fn neighbours() {
+        let grid = Grid::parse("abc\ndef\nghi").unwrap();
+        assert_eq!(grid.neighbours((1, 1), &ORTHOGONAL), vec![(1, 0), (2, 1), (1, 2), (0, 1)]);
+        assert_eq!(grid.neighbours((0, 0), &ORTHOGONAL), vec![(1, 0), (0, 1)]);
+        assert_eq!(grid.neighbours((0, 0), &ALL_DIRECTIONS), vec![(1, 0), (1, 1), (0, 1)]);
+    }
+
+    #[test]
+    fn lines() {
+        let grid = Grid::parse("abc\ndef").unwrap();
+        assert_eq!(grid.row(1), Some(chars("def")));
+        assert_eq!(grid.row(2), None);
+        assert_eq!(grid.column(2), Some(chars("cf")));
+        assert_eq!(grid.column(3), None);
+        assert_eq!(grid.ray((0, 0), (1, 1)), chars("ae"));
+        assert_eq!(grid.ray((5, 5), (1, 1)), vec![]);
+        assert_eq!(grid.diagonals(), ["d", "ae", "bf", "c", "a", "bd", "ce", "f"].map(chars));
+    }
+
+    #[test]
+    fn regions() {
+        let grid = Grid::parse("aab\nabb\ncca").unwrap();
+        assert_eq!(grid.find_all(&Value::Char('a')), vec![(0, 0), (1, 0), (0, 1), (2, 2)]);
+        assert_eq!(grid.flood_fill((0, 0)), vec![(0, 0), (1, 0), (0, 1)]);
+        assert_eq!(grid.flood_fill((3, 0)), vec![]);
+        assert_eq!(grid.regions(), vec![
+            vec![(0, 0), (1, 0), (0, 1)],
+            vec![(2, 0), (2, 1), (1, 1)],
+            vec![(0, 2), (1, 2)],
+            vec![(2, 2)],
+        ]);
+    }
+}
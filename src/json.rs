@@ -0,0 +1,528 @@
+use std::{error::Error, fmt::Display};
+
+/// How deeply arrays and objects may be nested. Parsing recurses, so without a limit a malicious
+/// document could overflow the stack.
+const MAX_DEPTH: usize = 128;
+
+/// A JSON value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Boolean(bool),
+
+    /// A number without a fractional part or exponent. Parsing fails if it doesn't fit in an
+    /// `isize`, rather than losing precision as a float.
+    Integer(isize),
+
+    /// Any other number.
+    Float(f64),
+
+    String(String),
+    Array(Vec<Json>),
+
+    /// Keys are kept in their original order.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Builds an object from key-value pairs.
+    pub fn object<'a>(pairs: impl IntoIterator<Item = (&'a str, Json)>) -> Json {
+        Json::Object(pairs.into_iter().map(|(k, v)| (k.to_owned(), v)).collect())
+    }
+
+    /// Looks up a key, if this is an object.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(pairs) => pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Looks up a path of keys through nested objects.
+    pub fn get_path(&self, path: &[&str]) -> Option<&Json> {
+        path.iter().try_fold(self, |json, key| json.get(key))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<isize> {
+        match self {
+            Json::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    /// Gets any number as a float.
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Json::Integer(i) => Some(*i as f64),
+            Json::Float(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_boolean(&self) -> Option<bool> {
+        match self {
+            Json::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// Parses a complete JSON document.
+    pub fn parse(input: &str) -> Result<Json, JsonError> {
+        let mut parser = JsonParser { chars: input.chars().collect(), pos: 0, depth: 0 };
+
+        let value = parser.parse_value()?;
+        parser.skip_whitespace();
+        if parser.pos < parser.chars.len() {
+            return Err(parser.error("unexpected trailing characters"));
+        }
+
+        Ok(value)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Self { Json::Boolean(b) }
+}
+
+impl From<isize> for Json {
+    fn from(i: isize) -> Self { Json::Integer(i) }
+}
+
+impl From<usize> for Json {
+    fn from(i: usize) -> Self { Json::Integer(i as isize) }
+}
+
+impl From<f64> for Json {
+    fn from(n: f64) -> Self { Json::Float(n) }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Self { Json::String(s.to_owned()) }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Self { Json::String(s) }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(items: Vec<Json>) -> Self { Json::Array(items) }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(o: Option<T>) -> Self {
+        match o {
+            Some(v) => v.into(),
+            None => Json::Null,
+        }
+    }
+}
+
+// Serializes compactly
+impl Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Boolean(b) => write!(f, "{b}"),
+            Json::Integer(i) => write!(f, "{i}"),
+            Json::Float(n) if n.is_finite() => write!(f, "{n}"),
+            Json::Float(_) => write!(f, "null"), // JSON can't represent these
+            Json::String(s) => write_json_string(f, s),
+
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, "]")
+            },
+
+            Json::Object(pairs) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_json_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+fn write_json_string(f: &mut std::fmt::Formatter<'_>, s: &str) -> std::fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Error encountered while parsing JSON, with the position it happened at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonError {
+    pub message: String,
+
+    /// Character offset into the input.
+    pub pos: usize,
+
+    /// 1-indexed line and column.
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid JSON at line {}, column {}: {}", self.line, self.col, self.message)
+    }
+}
+impl Error for JsonError {}
+
+struct JsonParser {
+    chars: Vec<char>,
+    pos: usize,
+
+    /// Number of arrays and objects we're currently inside.
+    depth: usize,
+}
+
+impl JsonParser {
+    fn error(&self, message: impl Into<String>) -> JsonError {
+        let before = &self.chars[..self.pos.min(self.chars.len())];
+        let line = before.iter().filter(|c| **c == '\n').count() + 1;
+        let col = before.iter().rev().take_while(|c| **c != '\n').count() + 1;
+        JsonError { message: message.into(), pos: self.pos, line, col }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() && c.is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), JsonError> {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{c}`")))
+        }
+    }
+
+    fn expect_word(&mut self, word: &str, value: Json) -> Result<Json, JsonError> {
+        for c in word.chars() {
+            if self.peek() != Some(c) {
+                return Err(self.error(format!("expected `{word}`")));
+            }
+            self.pos += 1;
+        }
+        Ok(value)
+    }
+
+    fn parse_value(&mut self) -> Result<Json, JsonError> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some('n') => self.expect_word("null", Json::Null),
+            Some('t') => self.expect_word("true", Json::Boolean(true)),
+            Some('f') => self.expect_word("false", Json::Boolean(false)),
+            Some('"') => Ok(Json::String(self.parse_string()?)),
+            Some('[') => self.nested(Self::parse_array),
+            Some('{') => self.nested(Self::parse_object),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.parse_number(),
+            Some(c) => Err(self.error(format!("unexpected character `{c}`"))),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    /// Parses an array or object, failing if that would nest too deeply.
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Json, JsonError>) -> Result<Json, JsonError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error(format!("nested more than {MAX_DEPTH} levels deep")));
+        }
+
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn parse_array(&mut self) -> Result<Json, JsonError> {
+        self.expect('[')?;
+        let mut items = vec![];
+
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(Json::Array(items));
+        }
+
+        loop {
+            items.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                },
+                _ => return Err(self.error("expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<Json, JsonError> {
+        self.expect('{')?;
+        let mut pairs = vec![];
+
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(Json::Object(pairs));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("expected string key"));
+            }
+            let key = self.parse_string()?;
+
+            self.skip_whitespace();
+            self.expect(':')?;
+            let value = self.parse_value()?;
+            pairs.push((key, value));
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(Json::Object(pairs));
+                },
+                _ => return Err(self.error("expected `,` or `}`")),
+            }
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, JsonError> {
+        self.expect('"')?;
+        let mut s = String::new();
+
+        loop {
+            match self.peek() {
+                None => return Err(self.error("unterminated string")),
+                Some('"') => {
+                    self.pos += 1;
+                    return Ok(s);
+                },
+                Some('\\') => {
+                    self.pos += 1;
+                    let escaped = match self.peek() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            self.pos += 1;
+                            let mut code = self.parse_hex4()?;
+
+                            // Combine surrogate pairs. A high surrogate without a low one after it is
+                            // replaced, and whatever escape followed it is kept.
+                            if (0xD800..0xDC00).contains(&code) && self.chars[self.pos..].starts_with(&['\\', 'u']) {
+                                self.pos += 2;
+                                let low = self.parse_hex4()?;
+                                if (0xDC00..0xE000).contains(&low) {
+                                    code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                                } else {
+                                    s.push(char::REPLACEMENT_CHARACTER);
+                                    code = low;
+                                }
+                            }
+
+                            s.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                            continue;
+                        },
+                        _ => return Err(self.error("invalid escape sequence")),
+                    };
+                    s.push(escaped);
+                    self.pos += 1;
+                },
+                Some(c) if (c as u32) < 0x20 => return Err(self.error("control character in string")),
+                Some(c) => {
+                    s.push(c);
+                    self.pos += 1;
+                },
+            }
+        }
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, JsonError> {
+        let digits = self.chars.get(self.pos..self.pos + 4)
+            .ok_or_else(|| self.error("truncated unicode escape"))?
+            .iter()
+            .collect::<String>();
+        // `from_str_radix` would also accept a sign
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(self.error("invalid unicode escape"));
+        }
+        let code = u32::from_str_radix(&digits, 16).unwrap();
+        self.pos += 4;
+        Ok(code)
+    }
+
+    fn parse_number(&mut self) -> Result<Json, JsonError> {
+        let start = self.pos;
+        if self.peek() == Some('-') {
+            self.pos += 1;
+        }
+
+        let mut is_integer = true;
+        while let Some(c) = self.peek() {
+            match c {
+                '0'..='9' => (),
+                '.' | 'e' | 'E' | '+' | '-' => is_integer = false,
+                _ => break,
+            }
+            self.pos += 1;
+        }
+
+        let text = self.chars[start..self.pos].iter().collect::<String>();
+        if is_integer && text.trim_start_matches('-').chars().next().is_some() {
+            return match text.parse() {
+                Ok(i) => Ok(Json::Integer(i)),
+                Err(_) => {
+                    self.pos = start;
+                    Err(self.error(format!("integer `{text}` is out of range")))
+                },
+            };
+        }
+        match text.parse() {
+            Ok(n) => Ok(Json::Float(n)),
+            Err(_) => {
+                self.pos = start;
+                Err(self.error(format!("invalid number `{text}`")))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integers_out_of_range_are_errors() {
+        let error = Json::parse("[1, 99999999999999999999]").unwrap_err();
+        assert_eq!(error.message, "integer `99999999999999999999` is out of range");
+        assert_eq!((error.line, error.col), (1, 5));
+
+        assert_eq!(Json::parse("-9223372036854775808"), Ok(Json::Integer(isize::MIN)));
+        assert!(Json::parse("-9223372036854775809").is_err());
+    }
+
+    fn error(input: &str) -> (String, usize, usize) {
+        let error = Json::parse(input).unwrap_err();
+        (error.message, error.line, error.col)
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(
+            Json::parse(" {\"b\": [1, -2.5e1, true, null, []], \"a\": {\"\": \"x\"}} "),
+            Ok(Json::object([
+                ("b", Json::Array(vec![Json::Integer(1), Json::Float(-25.0), Json::Boolean(true), Json::Null, Json::Array(vec![])])),
+                ("a", Json::object([("", "x".into())])),
+            ])),
+        );
+        assert_eq!(Json::parse("-0"), Ok(Json::Integer(0)));
+        assert_eq!(Json::parse("1E2"), Ok(Json::Float(100.0)));
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(Json::parse(r#""a\"\\\/\b\f\n\r\t""#), Ok("a\"\\/\u{8}\u{c}\n\r\t".into()));
+        assert_eq!(Json::parse(r#""\u00e9\u00C9é""#), Ok("éÉé".into()));
+
+        // Surrogate pairs are combined, and lone surrogates replaced
+        assert_eq!(Json::parse(r#""\ud83d\ude00""#), Ok("😀".into()));
+        assert_eq!(Json::parse(r#""\ud83dx\ude00""#), Ok("\u{fffd}x\u{fffd}".into()));
+        assert_eq!(Json::parse(r#""\ud83dA""#), Ok("\u{fffd}A".into()));
+        assert_eq!(Json::parse(r#""\ud83d\u0041""#), Ok("\u{fffd}A".into()));
+
+        assert_eq!(error(r#""\u+123""#), ("invalid unicode escape".to_owned(), 1, 4));
+        assert_eq!(error(r#""\u12zz""#), ("invalid unicode escape".to_owned(), 1, 4));
+        assert_eq!(error(r#""\u1"#), ("truncated unicode escape".to_owned(), 1, 4));
+        assert_eq!(error(r#""\x""#), ("invalid escape sequence".to_owned(), 1, 3));
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let json = Json::object([
+            ("z", Json::Array(vec![1isize.into(), 0.5.into(), Json::Null])),
+            ("a\n\"é\u{1}", Json::object([("k", false.into())])),
+        ]);
+        assert_eq!(json.to_string(), r#"{"z":[1,0.5,null],"a\n\"é\u0001":{"k":false}}"#);
+        assert_eq!(Json::parse(&json.to_string()), Ok(json));
+    }
+
+    #[test]
+    fn error_positions() {
+        assert_eq!(error(""), ("unexpected end of input".to_owned(), 1, 1));
+        assert_eq!(error("[1,\n  2 3]"), ("expected `,` or `]`".to_owned(), 2, 5));
+        assert_eq!(error("[1,]"), ("unexpected character `]`".to_owned(), 1, 4));
+        assert_eq!(error("{\"a\" 1}"), ("expected `:`".to_owned(), 1, 6));
+        assert_eq!(error("{a: 1}"), ("expected string key".to_owned(), 1, 2));
+        assert_eq!(error("\"é\nx\""), ("control character in string".to_owned(), 1, 3));
+        assert_eq!(error("\"abc"), ("unterminated string".to_owned(), 1, 5));
+        assert_eq!(error("[tru]"), ("expected `true`".to_owned(), 1, 5));
+        assert_eq!(error("1 2"), ("unexpected trailing characters".to_owned(), 1, 3));
+        assert_eq!(error("[1-2]"), ("invalid number `1-2`".to_owned(), 1, 2));
+        assert_eq!(error("-"), ("invalid number `-`".to_owned(), 1, 1));
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let nested = |depth| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(Json::parse(&nested(MAX_DEPTH)).is_ok());
+        assert_eq!(error(&nested(MAX_DEPTH + 1)), ("nested more than 128 levels deep".to_owned(), 1, 129));
+
+        // Deep enough to overflow the stack without the limit
+        let deep = format!("{{\"a\": {}", "{\"a\": [".repeat(20000));
+        assert_eq!(error(&deep).0, "nested more than 128 levels deep");
+    }
+}
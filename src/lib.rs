@@ -0,0 +1,93 @@
+
+//! An interpreter for a small stack-based language, used to solve Advent of Code 2024, along with
+//! tooling for it like a checker, formatter, debugger and language server.
+//!
+//! To embed the interpreter, parse some code with [code_to_node] and run it with an [Interpreter]
+//! which has loaded the stdlib, then inspect the values it left on the [stack](Interpreter::stack):
+//!
+//! ```no_run
+//! use advent_of_code_2024::{code_to_node, load_stdlib, Interpreter, Value};
+//!
+//! let mut interpreter = Interpreter::new();
+//! interpreter.execute(&load_stdlib()?)?;
+//! interpreter.execute(&code_to_node("[ 1 , 2 , 3 ] sum", "example")?)?;
+//! assert_eq!(interpreter.stack(), &[Value::Integer(6)]);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::{error::Error, fs::read_to_string, rc::Rc};
+
+pub mod token;
+pub mod parser;
+pub mod eval;
+pub mod loc;
+pub mod builtins;
+pub mod check;
+pub mod lint;
+pub mod cst;
+pub mod fmt;
+pub mod doc;
+pub mod json;
+pub mod containers;
+pub mod grid;
+pub mod regex;
+pub mod scan;
+pub mod search;
+pub mod rpc;
+pub mod lsp;
+pub mod debugger;
+pub mod dap;
+pub mod trace;
+pub mod profile;
+pub mod coverage;
+pub mod bench;
+pub mod capi;
+
+pub use eval::{ExecutionError, Hook, Interpreter, Value};
+pub use loc::{Loc, LocSource};
+pub use parser::{Node, NodeKind, SyntaxError};
+
+/// The source code of the standard library.
+pub const STDLIB: &str = include_str!("../lib/stdlib.stk");
+
+/// Tokenizes and parses code. `name` identifies the code in [Loc]s, and is usually a file path.
+///
+/// Errors are [SyntaxError]s.
+pub fn code_to_node(code: &str, name: &str) -> Result<Node, Box<dyn Error>> {
+    let source = LocSource::new(name.to_owned(), Rc::new(code.to_owned()));
+    let tokens = token::tokenize(&source)?;
+    let root = parser::parse(tokens, &source)?;
+
+    Ok(root)
+}
+
+/// Parses the [standard library](STDLIB), ready to be executed by an [Interpreter].
+pub fn load_stdlib() -> Result<Node, Box<dyn Error>> {
+    code_to_node(STDLIB, "(stdlib)")
+}
+
+/// The [LocSource] which [Loc]s within the stdlib refer to.
+pub fn stdlib_source() -> LocSource {
+    LocSource::new("(stdlib)".to_owned(), Rc::new(STDLIB.to_owned()))
+}
+
+/// Create an interpreter with the stdlib loaded, and `$input` set from an input file if given.
+pub fn prepare_interpreter(input_path: Option<&str>) -> Result<Interpreter, Box<dyn Error>> {
+    prepare_interpreter_with_hooks(input_path, vec![])
+}
+
+/// Like [prepare_interpreter], but installs hooks before loading the stdlib.
+pub fn prepare_interpreter_with_hooks(input_path: Option<&str>, hooks: Vec<Box<dyn Hook>>) -> Result<Interpreter, Box<dyn Error>> {
+    let input = input_path.map(read_to_string).transpose()?;
+
+    let mut interpreter = Interpreter::new();
+    for hook in hooks {
+        interpreter.add_hook(hook);
+    }
+    if let Some(input) = input {
+        interpreter.set_top_level_binding("$input", Value::from_string(&input));
+    }
+    interpreter.execute(&load_stdlib()?)?; // Load stdlib
+
+    Ok(interpreter)
+}
@@ -0,0 +1,391 @@
+use std::{collections::{HashMap, HashSet}, fmt::Display};
+
+use crate::{builtins::builtin, loc::{Loc, LocSource}, parser::{is_assignment_action, is_definition_action, Node, NodeKind}, token::{tokenize_with_trivia, Atom, TokenKind}};
+
+/// A possible mistake found by the [Linter].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    /// A stable identifier for the kind of warning, which can be used to suppress it.
+    pub code: &'static str,
+    pub message: String,
+    pub loc: Loc,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (line, col) = self.loc.line_col();
+        write!(f, "{}:{line}:{col}: {} [{}]", self.loc.source.name, self.message, self.code)
+    }
+}
+
+/// A binding assigned with `:` which is never read.
+pub const UNUSED_BINDING: &str = "unused-binding";
+
+/// A binding which is read, but never assigned anywhere.
+pub const UNASSIGNED_BINDING: &str = "unassigned-binding";
+
+/// A user action with the same name as a builtin, which will never be called because the builtin
+/// takes priority.
+pub const SHADOWED_BUILTIN: &str = "shadowed-builtin";
+
+/// A user action with the same name as one defined earlier, like those in the stdlib, which will
+/// fail at runtime.
+pub const REDEFINED_ACTION: &str = "redefined-action";
+
+/// A user action which always calls itself, so can never return.
+pub const UNCONDITIONAL_RECURSION: &str = "unconditional-recursion";
+
+/// A call to an action which isn't a builtin and isn't defined anywhere.
+pub const UNKNOWN_ACTION: &str = "unknown-action";
+
+/// A `?` choosing between two blocks, which is then not executed with `#`.
+pub const UNEXECUTED_CHOICE: &str = "unexecuted-choice";
+
+/// How a `$binding` atom is being used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BindingUse {
+    Read,
+    Assign,
+
+    /// Assigns a binding which was made somewhere else, with `:=`.
+    Reassign,
+
+    Define,
+
+    /// Names an existing action, like the `$name` in `$name memo-clear`.
+    ActionName,
+}
+
+/// Finds common mistakes in programs, without running them.
+///
+/// Any warning can be suppressed with a comment like `// allow: unused-binding` on the same line
+/// as the warning or the line before it. Multiple codes can be separated with commas.
+pub struct Linter {
+    /// Every action defined with `::` anywhere in the program, and where it was first defined.
+    actions: HashMap<String, Loc>,
+
+    /// Every binding assigned with `:` anywhere in the program, or set up by the interpreter.
+    assigned: HashSet<String>,
+
+    /// Every binding read anywhere in the program.
+    read: HashSet<String>,
+
+    warnings: Vec<Warning>,
+}
+
+impl Linter {
+    /// Creates a new linter. `external_bindings` are those which are assigned by the interpreter
+    /// rather than the program, like `$input`.
+    pub fn new(external_bindings: &[&str]) -> Self {
+        Linter {
+            actions: HashMap::new(),
+            assigned: external_bindings.iter().map(|b| b.to_string()).collect(),
+            read: HashSet::new(),
+            warnings: vec![],
+        }
+    }
+
+    /// Records the actions and bindings defined and used by a program. This must be called for all
+    /// parts of a program before any of them are linted.
+    pub fn add_program(&mut self, node: &Node) {
+        for definition in node.definitions() {
+            self.actions.entry(definition.name.to_owned()).or_insert(definition.loc.clone());
+        }
+
+        for_each_binding(node, &mut |name, usage| {
+            match usage {
+                BindingUse::Read => self.read.insert(name.to_owned()),
+                BindingUse::Assign | BindingUse::Reassign => self.assigned.insert(name.to_owned()),
+                BindingUse::Define | BindingUse::ActionName => false,
+            };
+        });
+    }
+
+    /// Lints a program which has previously been passed to [Linter::add_program].
+    pub fn lint_program(&mut self, node: &Node) {
+        self.lint_unused_bindings(node, true);
+        self.lint_sequences(node);
+
+        for definition in node.definitions() {
+            if builtin(definition.name).is_some() {
+                self.warn(SHADOWED_BUILTIN, definition.loc, format!(
+                    "action `{}` has the same name as a builtin, so will never be called", definition.name
+                ));
+            } else if let Some(first) = self.actions.get(definition.name) && first != definition.loc {
+                let (line, col) = first.line_col();
+                let message = format!(
+                    "action `{}` was already defined at {}:{line}:{col}, so defining it again will fail",
+                    definition.name, first.source.name,
+                );
+                self.warn(REDEFINED_ACTION, definition.loc, message);
+            }
+
+            self.lint_recursion(definition.name, definition.body);
+        }
+    }
+
+    /// Consumes the linter and returns the warnings it found, in source order, excluding any which
+    /// have been suppressed.
+    pub fn into_warnings(mut self) -> Vec<Warning> {
+        let mut allowed = HashMap::new();
+        self.warnings.retain(|w| {
+            let allowed = allowed.entry(w.loc.source.name.clone()).or_insert_with(|| allow_comments(&w.loc.source));
+            !is_suppressed(w, allowed)
+        });
+        self.warnings.sort_by_key(|w| (w.loc.source.name.clone(), w.loc.pos));
+        self.warnings.dedup();
+        self.warnings
+    }
+
+    fn warn(&mut self, code: &'static str, loc: &Loc, message: impl Into<String>) {
+        self.warnings.push(Warning { code, message: message.into(), loc: loc.clone() });
+    }
+
+    /// Looks for bindings assigned within a scope (either a block, or the top-level) which are
+    /// never read within it.
+    ///
+    /// Bindings are dynamically scoped, so a top-level binding could be read by any action. Those
+    /// are considered used if they're read anywhere in the program.
+    fn lint_unused_bindings(&mut self, scope: &Node, top_level: bool) {
+        let NodeKind::Sequence(items) = &scope.kind else { return };
+
+        let mut read_in_scope = HashSet::new();
+        for_each_binding(scope, &mut |name, usage| {
+            if usage == BindingUse::Read {
+                read_in_scope.insert(name.to_owned());
+            }
+        });
+
+        for (item, next) in items.iter().zip(items.iter().skip(1)) {
+            if let Some((name, BindingUse::Assign)) = binding_use(item, Some(next)) {
+                // Bindings starting with an underscore are deliberately unused, e.g. `$_ :`
+                let used = read_in_scope.contains(name) || (top_level && self.read.contains(name));
+                if !used && !name.starts_with("$_") {
+                    self.warn(UNUSED_BINDING, &item.loc, format!("binding `{name}` is assigned but never read"));
+                }
+            }
+        }
+
+        for item in items {
+            if let NodeKind::Block(body) = &item.kind {
+                self.lint_unused_bindings(body, false);
+            }
+        }
+    }
+
+    /// Lints everything which can be found by looking at individual items of a sequence, along
+    /// with their neighbours.
+    fn lint_sequences(&mut self, node: &Node) {
+        match &node.kind {
+            NodeKind::Sequence(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    let next = items.get(i + 1);
+
+                    match &item.kind {
+                        NodeKind::Atom(Atom::Binding(_)) => {
+                            if let Some((name, BindingUse::Read)) = binding_use(item, next) && !self.assigned.contains(name) {
+                                self.warn(UNASSIGNED_BINDING, &item.loc, format!(
+                                    "binding `{name}` is never assigned, so will always be unbound"
+                                ));
+                            }
+                        },
+
+                        NodeKind::Atom(Atom::Action(name)) => {
+                            if builtin(name).is_none() && !self.actions.contains_key(name) {
+                                self.warn(UNKNOWN_ACTION, &item.loc, format!("unknown action `{name}`"));
+                            }
+
+                            // Look for `{ ... } { ... } ?` without a following `#`
+                            let choosing_blocks = name == "?" && i >= 2
+                                && items[i - 2..i].iter().all(|n| matches!(n.kind, NodeKind::Block(_)));
+                            let executed = matches!(next, Some(Node { kind: NodeKind::Atom(Atom::Action(a)), .. }) if a == "#");
+                            if choosing_blocks && !executed {
+                                self.warn(UNEXECUTED_CHOICE, &item.loc,
+                                    "`?` chooses between two blocks, but the result isn't executed with `#`");
+                            }
+                        },
+
+                        _ => self.lint_sequences(item),
+                    }
+                }
+            },
+            NodeKind::Block(body) => self.lint_sequences(body),
+            NodeKind::Atom(_) | NodeKind::StackEffect(_) => (),
+        }
+    }
+
+    /// Looks for an action calling itself on every path through its body.
+    fn lint_recursion(&mut self, name: &str, body: &Node) {
+        let NodeKind::Sequence(items) = &body.kind else { return };
+
+        for (i, item) in items.iter().enumerate() {
+            let next = items.get(i + 1);
+            match &item.kind {
+                NodeKind::Atom(Atom::Action(action)) if action == name => {
+                    let message = if next.is_some() {
+                        format!("`{name}` calls itself unconditionally, so the code after this is unreachable")
+                    } else {
+                        format!("`{name}` calls itself unconditionally, so can never return")
+                    };
+                    self.warn(UNCONDITIONAL_RECURSION, &item.loc, message);
+                    return;
+                },
+
+                // A block which is executed immediately is always run too
+                NodeKind::Block(inner) if matches!(next, Some(Node { kind: NodeKind::Atom(Atom::Action(a)), .. }) if a == "#") =>
+                    self.lint_recursion(name, inner),
+
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Works out how a node is using a binding, if it's a binding atom, based on the node which comes
+/// after it.
+fn binding_use<'a>(node: &'a Node, next: Option<&Node>) -> Option<(&'a str, BindingUse)> {
+    let NodeKind::Atom(Atom::Binding(name)) = &node.kind else { return None };
+
+    let usage = match next {
+        Some(Node { kind: NodeKind::Atom(Atom::Action(a)), .. }) if a == ":=" => BindingUse::Reassign,
+        Some(Node { kind: NodeKind::Atom(Atom::Action(a)), .. }) if is_assignment_action(a) => BindingUse::Assign,
+        Some(Node { kind: NodeKind::Atom(Atom::Action(a)), .. }) if is_definition_action(a) => BindingUse::Define,
+        Some(Node { kind: NodeKind::Atom(Atom::Action(a)), .. }) if a == "memo-clear" => BindingUse::ActionName,
+        _ => BindingUse::Read,
+    };
+    Some((name, usage))
+}
+
+/// Calls a function for every binding atom within a node, at any depth.
+fn for_each_binding(node: &Node, f: &mut impl FnMut(&str, BindingUse)) {
+    match &node.kind {
+        NodeKind::Sequence(items) => {
+            for (i, item) in items.iter().enumerate() {
+                if let Some((name, usage)) = binding_use(item, items.get(i + 1)) {
+                    f(name, usage);
+                } else {
+                    for_each_binding(item, f);
+                }
+            }
+        },
+        NodeKind::Block(body) => for_each_binding(body, f),
+        NodeKind::Atom(_) | NodeKind::StackEffect(_) => (),
+    }
+}
+
+/// The warning codes listed by each `// allow: ...` comment in a source, by the line it's on.
+fn allow_comments(source: &LocSource) -> HashMap<usize, Vec<String>> {
+    // Sources which can't be tokenized can't have been linted either
+    let tokens = tokenize_with_trivia(source).unwrap_or_default();
+
+    let mut allowed = HashMap::new();
+    for token in tokens {
+        if let TokenKind::Comment(comment) = &token.kind
+            && let Some(codes) = comment.trim_start_matches('/').trim().strip_prefix("allow:") {
+            allowed.entry(token.loc.line_col().0)
+                .or_insert_with(Vec::new)
+                .extend(codes.split(',').map(|c| c.trim().to_owned()));
+        }
+    }
+    allowed
+}
+
+/// Whether a warning has been suppressed by an `// allow: ...` comment on its line, or the line
+/// before it.
+fn is_suppressed(warning: &Warning, allowed: &HashMap<usize, Vec<String>>) -> bool {
+    let (line, _) = warning.loc.line_col();
+    [line - 1, line].iter()
+        .filter_map(|l| allowed.get(l))
+        .flatten()
+        .any(|code| code == warning.code)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{code_to_node, load_stdlib};
+
+    use super::*;
+
+    /// Lints a program along with the stdlib, and returns the warnings as they'd be printed.
+    fn lint(code: &str) -> Vec<String> {
+        let stdlib = load_stdlib().unwrap();
+        let program = code_to_node(code, "test").unwrap();
+
+        let mut linter = Linter::new(&["$input"]);
+        linter.add_program(&stdlib);
+        linter.add_program(&program);
+        linter.lint_program(&program);
+        linter.into_warnings().iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn unused_bindings() {
+        assert_eq!(lint("{ 1 $y : } $f ::  f"), vec!["test:1:5: binding `$y` is assigned but never read [unused-binding]"]);
+        assert_eq!(lint("{ 1 $y :  $y } $f ::  f println"), Vec::<String>::new());
+
+        // Top-level bindings can be read from any action, and underscores mark them as unused
+        assert_eq!(lint("{ $x 1 + } $f ::  1 $x :  f println  2 $_ :"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn unassigned_bindings() {
+        assert_eq!(lint("$y println"), vec!["test:1:1: binding `$y` is never assigned, so will always be unbound [unassigned-binding]"]);
+        assert_eq!(lint("$input println"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn shadowed_builtins_and_redefined_actions() {
+        assert_eq!(lint("{ } $print ::"), vec![
+            "test:1:5: action `print` has the same name as a builtin, so will never be called [shadowed-builtin]",
+        ]);
+        assert_eq!(lint("{ } $g ::\n{ } $g ::"), vec![
+            "test:2:5: action `g` was already defined at test:1:5, so defining it again will fail [redefined-action]",
+        ]);
+        assert_eq!(lint("{ } $nothing ::"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn unconditional_recursion() {
+        assert_eq!(lint("{ 1 f } $f ::"), vec![
+            "test:1:5: `f` calls itself unconditionally, so can never return [unconditional-recursion]",
+        ]);
+        assert_eq!(lint("{ f 1 } $f ::"), vec![
+            "test:1:3: `f` calls itself unconditionally, so the code after this is unreachable [unconditional-recursion]",
+        ]);
+        // Including through blocks which are executed straight away
+        assert_eq!(lint("{ { f } # } $f ::"), vec![
+            "test:1:5: `f` calls itself unconditionally, so can never return [unconditional-recursion]",
+        ]);
+        assert_eq!(lint("{ dup 0 > { 1 - f } { } ? # } $f ::"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn unknown_actions() {
+        assert_eq!(lint("1 frobnicate"), vec!["test:1:3: unknown action `frobnicate` [unknown-action]"]);
+        assert_eq!(lint("{ } $frobnicate ::  frobnicate"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn unexecuted_choices() {
+        assert_eq!(lint("true { 1 } { 2 } ? println"), vec![
+            "test:1:18: `?` chooses between two blocks, but the result isn't executed with `#` [unexecuted-choice]",
+        ]);
+        assert_eq!(lint("true { 1 } { 2 } ? # println"), Vec::<String>::new());
+        assert_eq!(lint("true 1 2 ? println"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn allow_comments_suppress_warnings() {
+        assert_eq!(lint("1 $x :"), vec!["test:1:3: binding `$x` is assigned but never read [unused-binding]"]);
+        assert_eq!(lint("1 $x : // allow: unused-binding"), Vec::<String>::new());
+        assert_eq!(lint("// allow: unknown-action, unused-binding\n1 $x :"), Vec::<String>::new());
+        assert_eq!(lint("// allow: unknown-action\n1 $x :").len(), 1);
+    }
+
+    #[test]
+    fn allow_in_strings_doesnt_suppress() {
+        assert_eq!(lint("1 $x : \"// allow: unused-binding, \" println"), vec![
+            "test:1:3: binding `$x` is assigned but never read [unused-binding]",
+        ]);
+    }
+}
@@ -26,12 +26,15 @@ impl Loc {
         let b_range = b.range();
         let start = min(a_range.start, b_range.start);
         let end = max(a_range.end, b_range.end);
-        Loc::new(source, start, end - start + 1)
+        Loc::new(source, start, end - start)
     }
     
     /// The contents of the source range highlighted by ths [Loc].
     pub fn contents(&self) -> String {
-        self.source.contents[self.range()].to_owned()
+        let range = self.range();
+        let start = self.source.byte_offset(range.start);
+        let end = self.source.byte_offset(range.end);
+        self.source.contents[start..end].to_owned()
     }
 
     /// The source range of characters covered by this [Loc].
@@ -39,6 +42,13 @@ impl Loc {
         self.pos..(self.pos + self.len)
     }
 
+    /// The 1-indexed line and column which this [Loc] starts at.
+    pub fn line_col(&self) -> (usize, usize) {
+        let line_starts = &self.source.line_starts;
+        let line = line_starts.partition_point(|start| *start <= self.pos);
+        (line, self.pos - line_starts[line - 1] + 1)
+    }
+
     /// Temporary method to generate a meaningless [Loc].
     /// Should exist when everything else is done!
     pub fn stub() -> Self {
@@ -54,10 +64,58 @@ impl Loc {
 pub struct LocSource {
     pub name: String,
     pub contents: Rc<String>,
+
+    /// The character index at which each line starts, so that line numbers can be found quickly.
+    line_starts: Rc<Vec<usize>>,
+
+    /// The byte index at which each line starts, to find the bytes covered by a [Loc].
+    line_byte_starts: Rc<Vec<usize>>,
 }
 
 impl LocSource {
     pub fn new(name: String, contents: Rc<String>) -> Self {
-        Self { name, contents }
+        let line_starts = std::iter::once(0)
+            .chain(contents.chars().enumerate().filter(|(_, c)| *c == '\n').map(|(i, _)| i + 1))
+            .collect();
+        let line_byte_starts = std::iter::once(0)
+            .chain(contents.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self { name, contents, line_starts: Rc::new(line_starts), line_byte_starts: Rc::new(line_byte_starts) }
+    }
+
+    /// The byte index of a character index, which may be the end of the contents.
+    fn byte_offset(&self, char_offset: usize) -> usize {
+        let line = self.line_starts.partition_point(|start| *start <= char_offset) - 1;
+        let line_start = self.line_byte_starts[line];
+        self.contents[line_start..].char_indices()
+            .nth(char_offset - self.line_starts[line])
+            .map_or(self.contents.len(), |(i, _)| line_start + i)
+    }
+
+    /// Whether a file name given by the user refers to this source. It can be either the full name,
+    /// or the end of one after a `/`, so `code.stk` matches `aoc/day1/code.stk`.
+    pub fn matches_file(&self, file: &str) -> bool {
+        self.name == file || self.name.ends_with(&format!("/{file}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contents_counts_characters() {
+        let source = LocSource::new("test".to_owned(), Rc::new("é\nwörld ok".to_owned()));
+        assert_eq!(Loc::new(source.clone(), 2, 5).contents(), "wörld");
+        assert_eq!(Loc::new(source.clone(), 8, 2).contents(), "ok");
+        assert_eq!(Loc::new(source, 2, 5).line_col(), (2, 1));
+    }
+
+    #[test]
+    fn spanning_covers_exactly_both() {
+        let source = LocSource::new("test".to_owned(), Rc::new("ab cd ef".to_owned()));
+        let (a, b) = (Loc::new(source.clone(), 0, 2), Loc::new(source, 6, 2));
+        assert_eq!(Loc::new_spanning(&a, &b).contents(), "ab cd ef");
+        assert_eq!(Loc::new_spanning(&b, &a).contents(), "ab cd ef");
     }
 }
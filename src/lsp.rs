@@ -0,0 +1,414 @@
+use std::{collections::BTreeMap, error::Error, io::{BufRead, Write}, rc::Rc};
+
+use crate::{check::Checker, doc::{builtin_docs, source_docs, ActionDoc}, json::Json, lint::Linter, loc::{Loc, LocSource}, parser::{is_assignment_action, is_definition_action, parse, Node, SyntaxError}, rpc::{read_message, write_message}, token::{tokenize, Atom, Token, TokenKind}};
+
+const TEXT_DOCUMENT_SYNC_FULL: isize = 1;
+const SEVERITY_ERROR: isize = 1;
+const SEVERITY_WARNING: isize = 2;
+const COMPLETION_KIND_FUNCTION: isize = 3;
+const METHOD_NOT_FOUND: isize = -32601;
+
+/// A Language Server Protocol server, which analyses open documents alongside the stdlib.
+///
+/// Documents are always sent in full, and analysed from scratch whenever they change.
+pub struct LanguageServer<W: Write> {
+    output: W,
+    stdlib: Node,
+
+    /// Documentation for builtins and the stdlib.
+    library_docs: Vec<ActionDoc>,
+
+    /// Open documents, by URI.
+    documents: BTreeMap<String, LocSource>,
+}
+
+/// Something which can be defined and referenced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Symbol {
+    /// A user action, without the `$`.
+    Action(String),
+
+    /// A binding, with the `$`.
+    Binding(String),
+}
+
+impl<W: Write> LanguageServer<W> {
+    pub fn new(output: W, stdlib: Node, library_docs: Vec<ActionDoc>) -> Self {
+        LanguageServer { output, stdlib, library_docs, documents: BTreeMap::new() }
+    }
+
+    /// Handles messages until the client sends `exit`, or the input ends.
+    pub fn serve(&mut self, input: &mut impl BufRead) -> Result<(), Box<dyn Error>> {
+        while let Some(message) = read_message(input)? {
+            // Ignore anything which isn't a request or notification, like responses
+            let Some(method) = message.get("method").and_then(Json::as_str) else { continue };
+            if method == "exit" {
+                break;
+            }
+
+            let params = message.get("params").cloned().unwrap_or(Json::Null);
+            let result = self.handle(method, &params)?;
+
+            // Only requests have an ID, and need a response
+            if let Some(id) = message.get("id") {
+                let response = match result {
+                    Some(result) => Json::object([
+                        ("jsonrpc", "2.0".into()),
+                        ("id", id.clone()),
+                        ("result", result),
+                    ]),
+                    None => Json::object([
+                        ("jsonrpc", "2.0".into()),
+                        ("id", id.clone()),
+                        ("error", Json::object([
+                            ("code", METHOD_NOT_FOUND.into()),
+                            ("message", format!("unsupported method `{method}`").into()),
+                        ])),
+                    ]),
+                };
+                write_message(&mut self.output, &response)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handles a single request or notification, returning its result, or `None` if the method
+    /// isn't supported.
+    fn handle(&mut self, method: &str, params: &Json) -> Result<Option<Json>, Box<dyn Error>> {
+        let uri = params.get_path(&["textDocument", "uri"]).and_then(Json::as_str).unwrap_or_default().to_owned();
+
+        let result = match method {
+            "initialize" => Json::object([
+                ("capabilities", Json::object([
+                    ("textDocumentSync", TEXT_DOCUMENT_SYNC_FULL.into()),
+                    ("definitionProvider", true.into()),
+                    ("referencesProvider", true.into()),
+                    ("hoverProvider", true.into()),
+                    ("completionProvider", Json::object([])),
+                ])),
+                ("serverInfo", Json::object([("name", "stk-lsp".into())])),
+            ]),
+            "initialized" | "shutdown" | "$/cancelRequest" | "$/setTrace" => Json::Null,
+
+            "textDocument/didOpen" => {
+                let text = params.get_path(&["textDocument", "text"]).and_then(Json::as_str).unwrap_or_default();
+                self.open_document(&uri, text)?;
+                Json::Null
+            },
+            "textDocument/didChange" => {
+                // With full sync, the last change is the entire new text
+                let change = params.get("contentChanges").and_then(Json::as_array).and_then(|c| c.last());
+                if let Some(text) = change.and_then(|c| c.get("text")).and_then(Json::as_str) {
+                    self.open_document(&uri, text)?;
+                }
+                Json::Null
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                self.publish_diagnostics(&uri, vec![])?;
+                Json::Null
+            },
+
+            "textDocument/definition" => self.definition(&uri, params).into(),
+            "textDocument/references" => self.references(&uri, params).into(),
+            "textDocument/hover" => self.hover(&uri, params).into(),
+            "textDocument/completion" => self.completion(&uri),
+
+            _ => return Ok(None),
+        };
+
+        Ok(Some(result))
+    }
+
+    fn open_document(&mut self, uri: &str, text: &str) -> Result<(), Box<dyn Error>> {
+        let name = uri.strip_prefix("file://").unwrap_or(uri).to_owned();
+        let source = LocSource::new(name, Rc::new(text.to_owned()));
+
+        let diagnostics = self.diagnostics(&source);
+        self.documents.insert(uri.to_owned(), source);
+        self.publish_diagnostics(uri, diagnostics)
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str, diagnostics: Vec<Json>) -> Result<(), Box<dyn Error>> {
+        let notification = Json::object([
+            ("jsonrpc", "2.0".into()),
+            ("method", "textDocument/publishDiagnostics".into()),
+            ("params", Json::object([
+                ("uri", uri.into()),
+                ("diagnostics", diagnostics.into()),
+            ])),
+        ]);
+        write_message(&mut self.output, &notification)
+    }
+
+    /// Finds syntax errors in a document, or if there are none, the results of the checker and
+    /// the linter.
+    fn diagnostics(&self, source: &LocSource) -> Vec<Json> {
+        let root = match tokenize(source).and_then(|tokens| parse(tokens, source)) {
+            Ok(root) => root,
+            Err(e) => {
+                let (loc, message) = match e.downcast_ref::<SyntaxError>() {
+                    Some(e) => (e.loc.clone(), e.message.clone()),
+                    None => (Loc::new(source.clone(), 0, 0), e.to_string()),
+                };
+                return vec![diagnostic(&loc, SEVERITY_ERROR, None, &message)];
+            },
+        };
+
+        let mut diagnostics = vec![];
+
+        let mut checker = Checker::new();
+        checker.add_definitions(&self.stdlib);
+        checker.add_definitions(&root);
+        checker.check_actions();
+        checker.check_program(&root);
+        for d in checker.into_diagnostics() {
+            if d.loc.source == *source {
+                diagnostics.push(diagnostic(&d.loc, SEVERITY_ERROR, None, &d.message));
+            }
+        }
+
+        let mut linter = Linter::new(&["$input"]);
+        linter.add_program(&self.stdlib);
+        linter.add_program(&root);
+        linter.lint_program(&root);
+        for w in linter.into_warnings() {
+            if w.loc.source == *source {
+                diagnostics.push(diagnostic(&w.loc, SEVERITY_WARNING, Some(w.code), &w.message));
+            }
+        }
+
+        diagnostics
+    }
+
+    /// Finds the symbol at the position given in a request's parameters.
+    fn symbol_at(&self, uri: &str, params: &Json) -> Option<(Symbol, Loc, usize)> {
+        let source = self.documents.get(uri)?;
+        let offset = offset_at(&source.contents, params.get("position")?)?;
+        let tokens = tokenize(source).ok()?;
+
+        // Prefer a token under the cursor, but also accept one which the cursor is just after
+        let index = tokens.iter().position(|t| t.loc.range().contains(&offset))
+            .or_else(|| tokens.iter().position(|t| t.loc.range().end == offset))?;
+        let (symbol, _) = classify(&tokens, index)?;
+        Some((symbol, tokens[index].loc.clone(), offset))
+    }
+
+    /// Finds every occurrence of a symbol, in the given document first and then any others.
+    /// Each is paired with whether it's a definition or assignment.
+    fn occurrences<'a>(&'a self, uri: &'a str, symbol: &Symbol) -> Vec<(&'a str, Loc, bool)> {
+        // Bindings are dynamically scoped so could in theory be shared between files, but that
+        // would be very confusing, so only look in the same one
+        let uris = match symbol {
+            Symbol::Action(_) => {
+                let mut uris = vec![uri];
+                uris.extend(self.documents.keys().map(|u| u.as_str()).filter(|u| *u != uri));
+                uris
+            },
+            Symbol::Binding(_) => vec![uri],
+        };
+
+        let mut occurrences = vec![];
+        for uri in uris {
+            let Some(source) = self.documents.get(uri) else { continue };
+            let Ok(tokens) = tokenize(source) else { continue };
+
+            for i in 0..tokens.len() {
+                if let Some((s, is_definition)) = classify(&tokens, i) && s == *symbol {
+                    occurrences.push((uri, tokens[i].loc.clone(), is_definition));
+                }
+            }
+        }
+        occurrences
+    }
+
+    /// For an action, goes to where it's defined with `::`.
+    ///
+    /// For a binding, goes to the closest assignment before the cursor, since that's most likely
+    /// to be the one in scope - or failing that, the first.
+    fn definition(&self, uri: &str, params: &Json) -> Option<Json> {
+        let (symbol, _, offset) = self.symbol_at(uri, params)?;
+        let definitions = self.occurrences(uri, &symbol)
+            .into_iter()
+            .filter(|(_, _, is_definition)| *is_definition)
+            .collect::<Vec<_>>();
+
+        let (uri, loc, _) = match symbol {
+            Symbol::Action(_) => definitions.first()?,
+            Symbol::Binding(_) => definitions.iter()
+                .rfind(|(_, loc, _)| loc.pos <= offset)
+                .or(definitions.first())?,
+        };
+        Some(location(uri, loc))
+    }
+
+    fn references(&self, uri: &str, params: &Json) -> Option<Json> {
+        let (symbol, _, _) = self.symbol_at(uri, params)?;
+        let include_declaration = params.get_path(&["context", "includeDeclaration"])
+            .and_then(Json::as_boolean)
+            .unwrap_or(true);
+
+        let references = self.occurrences(uri, &symbol)
+            .into_iter()
+            .filter(|(_, _, is_definition)| include_declaration || !is_definition)
+            .map(|(uri, loc, _)| location(uri, &loc))
+            .collect::<Vec<_>>();
+        Some(references.into())
+    }
+
+    /// Shows the documentation and stack effect of an action.
+    fn hover(&self, uri: &str, params: &Json) -> Option<Json> {
+        let (Symbol::Action(name), loc, _) = self.symbol_at(uri, params)? else { return None };
+        let docs = self.docs(uri);
+        let doc = docs.iter().find(|d| d.name == name)?;
+
+        let mut parts = vec![];
+        match &doc.effect {
+            Some(effect) => parts.push(format!("```\n{} {effect}\n```", doc.name)),
+            None => parts.push(format!("```\n{}\n```", doc.name)),
+        }
+        if let Some(usage) = &doc.usage {
+            parts.push(format!("`{usage}`"));
+        }
+        if !doc.description.is_empty() {
+            parts.push(doc.description.clone());
+        }
+        parts.push(format!("*{}*", doc.location().unwrap_or(doc.origin.clone())));
+
+        Some(Json::object([
+            ("contents", Json::object([
+                ("kind", "markdown".into()),
+                ("value", parts.join("\n\n").into()),
+            ])),
+            ("range", range(&loc)),
+        ]))
+    }
+
+    /// Offers every action which could be called from a document.
+    fn completion(&self, uri: &str) -> Json {
+        let mut items = vec![];
+        let mut seen = vec![];
+        for doc in self.docs(uri) {
+            if seen.contains(&doc.name) {
+                continue;
+            }
+
+            let detail = match &doc.effect {
+                Some(effect) => format!("{effect}    [{}]", doc.origin),
+                None => format!("[{}]", doc.origin),
+            };
+            items.push(Json::object([
+                ("label", doc.name.as_str().into()),
+                ("kind", COMPLETION_KIND_FUNCTION.into()),
+                ("detail", detail.into()),
+                ("documentation", doc.description.as_str().into()),
+            ]));
+            seen.push(doc.name);
+        }
+
+        items.into()
+    }
+
+    /// Documentation for all actions available to a document, with its own actions first.
+    fn docs(&self, uri: &str) -> Vec<ActionDoc> {
+        let mut docs = vec![];
+        if let Some(source) = self.documents.get(uri) {
+            docs.extend(source_docs(source).unwrap_or_default());
+        }
+        docs.extend(self.library_docs.iter().cloned());
+        docs
+    }
+}
+
+/// Runs a language server over stdin and stdout.
+pub fn serve_stdio(stdlib: Node, stdlib_source: &LocSource) -> Result<(), Box<dyn Error>> {
+    let mut library_docs = builtin_docs();
+    library_docs.extend(source_docs(stdlib_source)?);
+
+    let mut server = LanguageServer::new(std::io::stdout(), stdlib, library_docs);
+    server.serve(&mut std::io::stdin().lock())
+}
+
+/// Works out which symbol the token at `index` refers to, and whether it's a definition of it.
+///
+/// `$name ::` (or `::memo`) defines the action `name`, `$name memo-clear` refers to it, and
+/// `$name :` (or `:shadow` or `:const`) assigns the binding `$name`. Reassigning it with `:=`
+/// counts as a use rather than a definition.
+fn classify(tokens: &[Token], index: usize) -> Option<(Symbol, bool)> {
+    let next_action = match tokens.get(index + 1) {
+        Some(Token { kind: TokenKind::Atom(Atom::Action(a)), .. }) => Some(a.as_str()),
+        _ => None,
+    };
+
+    match &tokens[index].kind {
+        TokenKind::Atom(Atom::Action(name)) => Some((Symbol::Action(name.clone()), false)),
+        TokenKind::Atom(Atom::Binding(name)) if next_action.is_some_and(is_definition_action) =>
+            Some((Symbol::Action(name.strip_prefix('$').unwrap().to_owned()), true)),
+        TokenKind::Atom(Atom::Binding(name)) if next_action == Some("memo-clear") =>
+            Some((Symbol::Action(name.strip_prefix('$').unwrap().to_owned()), false)),
+        TokenKind::Atom(Atom::Binding(name)) =>
+            Some((Symbol::Binding(name.clone()), next_action.is_some_and(|a| is_assignment_action(a) && a != ":="))),
+        _ => None,
+    }
+}
+
+fn diagnostic(loc: &Loc, severity: isize, code: Option<&str>, message: &str) -> Json {
+    let mut pairs = vec![
+        ("range", range(loc)),
+        ("severity", severity.into()),
+        ("source", "stk".into()),
+        ("message", message.into()),
+    ];
+    if let Some(code) = code {
+        pairs.push(("code", code.into()));
+    }
+    Json::object(pairs)
+}
+
+fn location(uri: &str, loc: &Loc) -> Json {
+    Json::object([("uri", uri.into()), ("range", range(loc))])
+}
+
+fn range(loc: &Loc) -> Json {
+    Json::object([
+        ("start", position(&loc.source.contents, loc.pos)),
+        ("end", position(&loc.source.contents, loc.pos + loc.len)),
+    ])
+}
+
+// LSP positions are a 0-indexed line and character within it. Characters are meant to be counted in
+// UTF-16 code units, but we count `char`s, which only differs outside of the Basic Multilingual
+// Plane - unlikely to come up in source code.
+
+/// The position of a character offset.
+fn position(contents: &str, offset: usize) -> Json {
+    let mut line: usize = 0;
+    let mut character: usize = 0;
+    for c in contents.chars().take(offset) {
+        if c == '\n' {
+            line += 1;
+            character = 0;
+        } else {
+            character += 1;
+        }
+    }
+
+    Json::object([("line", line.into()), ("character", character.into())])
+}
+
+/// The character offset of a position.
+fn offset_at(contents: &str, position: &Json) -> Option<usize> {
+    let line = position.get("line")?.as_integer()? as usize;
+    let character = position.get("character")?.as_integer()? as usize;
+
+    let mut offset = 0;
+    for (i, text) in contents.split('\n').enumerate() {
+        let length = text.chars().count();
+        if i == line {
+            return Some(offset + character.min(length));
+        }
+        offset += length + 1;
+    }
+    None
+}
@@ -1,24 +1,29 @@
-#![feature(let_chains)]
+use std::{cell::RefCell, env::args, error::Error, fs::{read_to_string, write, File}, io::{stderr, stdin, stdout, BufWriter, Write}, process::exit, rc::Rc};
 
-use std::{env::args, error::Error, fs::read_to_string, io::{stdin, stdout, Write}, path::Path, process::abort, rc::Rc};
-
-use eval::{Interpreter, Value};
-use loc::LocSource;
-use parser::{parse, Node};
-use token::tokenize;
-
-mod token;
-mod parser;
-mod eval;
-mod loc;
-
-pub fn code_to_node(code: &str, name: &str) -> Result<Node, Box<dyn Error>> {
-    let source = LocSource::new(name.to_owned(), Rc::new(code.to_owned()));
-    let tokens = tokenize(&source)?;
-    let root = parse(tokens)?;
-
-    Ok(root)
-}
+use advent_of_code_2024::{
+    bench::Benchmark,
+    capi,
+    check::Checker,
+    code_to_node,
+    coverage::{Coverage, CoverageRecorder},
+    dap,
+    debugger::{print_backtrace, print_source_context, Debugger},
+    doc::{builtin_docs, render_html, render_markdown, source_docs},
+    fmt::format_source,
+    json::Json,
+    lint::Linter,
+    load_stdlib,
+    lsp,
+    prepare_interpreter,
+    prepare_interpreter_with_hooks,
+    profile::{Profile, Profiler},
+    stdlib_source,
+    trace::{LineRange, TraceFilter, Tracer},
+    Hook,
+    Interpreter,
+    LocSource,
+    Node,
+};
 
 fn main() -> Result<(), Box<dyn Error>> {
     // If no (additional) args passed, start a repl
@@ -26,22 +31,201 @@ fn main() -> Result<(), Box<dyn Error>> {
         return repl();
     }
 
-    let code_path = args().nth(1).expect("no code path passed");
-    let input_path = args().nth(2);
+    match args().nth(1).as_deref() {
+        Some("check") => check(args().skip(2).collect()),
+        Some("lint") => lint(args().skip(2).collect()),
+        Some("fmt") => fmt(args().skip(2).collect()),
+        Some("doc") => doc(args().skip(2).collect()),
+        Some("lsp") => lsp::serve_stdio(load_stdlib()?, &stdlib_source()),
+        Some("debug") => debug(args().skip(2).collect()),
+        Some("dap") => dap::serve_stdio(prepare_interpreter),
+        Some("bench") => bench(args().skip(2).collect()),
+        Some("c-header") => {
+            print!("{}", capi::C_HEADER);
+            Ok(())
+        },
+        _ => run(),
+    }
+}
+
+/// Run a code file, optionally with an input file.
+///
+/// Tracing is enabled by `--trace`, or any of the options which configure it:
+///   - `--trace-action=NAME` only traces within the definition of a user action
+///   - `--trace-lines=FILE:FROM-TO` only traces a range of lines (the file defaults to the code file)
+///   - `--trace-output=PATH` writes the trace to a file, rather than stderr
+///
+/// `--profile` prints the time spent in each action to stderr once the code finishes, and
+/// `--profile-folded=PATH` writes the same timings as folded stacks, for flamegraph tools.
+///
+/// `--coverage` prints a listing of the code file and stdlib to stderr once the code finishes,
+/// showing how many times each line was executed. `--coverage-output=PATH` writes the listing to a
+/// file instead, and `--coverage-lcov=PATH` writes an LCOV tracefile.
+fn run() -> Result<(), Box<dyn Error>> {
+    let (options, paths): (Vec<_>, Vec<_>) = args().skip(1).partition(|a| a.starts_with("--"));
+    let code_path = paths.first().expect("no code path passed");
+    let input_path = paths.get(1);
+
+    let mut trace = false;
+    let mut trace_filter = TraceFilter::default();
+    let mut trace_output = None;
+    let mut profile_report = false;
+    let mut profile_folded = None;
+    let mut coverage_listing = false;
+    let mut coverage_output = None;
+    let mut coverage_lcov = None;
+    for option in &options {
+        match option.split_once('=') {
+            None if option == "--trace" => trace = true,
+            Some(("--trace-action", name)) => trace_filter.actions.push(name.to_owned()),
+            Some(("--trace-lines", range)) => trace_filter.ranges.push(LineRange::parse(range, code_path)?),
+            Some(("--trace-output", path)) => trace_output = Some(path),
+            None if option == "--profile" => profile_report = true,
+            Some(("--profile-folded", path)) => profile_folded = Some(path),
+            None if option == "--coverage" => coverage_listing = true,
+            Some(("--coverage-output", path)) => coverage_output = Some(path),
+            Some(("--coverage-lcov", path)) => coverage_lcov = Some(path),
+            _ => return Err(format!("unknown option `{option}`").into()),
+        }
+    }
+    trace |= trace_output.is_some() || !trace_filter.actions.is_empty() || !trace_filter.ranges.is_empty();
 
     // Load input code
-    let code = read_to_string(&code_path)?;
-    let root = code_to_node(&code, &code_path)?;
+    let code = read_to_string(code_path)?;
+    let root = code_to_node(&code, code_path)?;
 
-    let input = input_path.map(|p| read_to_string(p)).transpose()?;
+    // Record coverage while the stdlib loads too, so that its definitions count as executed
+    let coverage = (coverage_listing || coverage_output.is_some() || coverage_lcov.is_some())
+        .then(|| Rc::new(RefCell::new(Coverage::default())));
+    let mut hooks: Vec<Box<dyn Hook>> = vec![];
+    if let Some(coverage) = &coverage {
+        hooks.push(Box::new(CoverageRecorder::new(coverage.clone())));
+    }
 
-    let mut interpreter = Interpreter::new();
-    if let Some(input) = input {
-        interpreter.set_top_level_binding("$input", Value::from_string(&input));
+    let mut interpreter = prepare_interpreter_with_hooks(input_path.map(|p| p.as_str()), hooks)?;
+    if trace {
+        let output: Box<dyn Write> = match trace_output {
+            Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+            None => Box::new(BufWriter::new(stderr())),
+        };
+        interpreter.add_hook(Box::new(Tracer::new(output, trace_filter)));
     }
-    interpreter.execute(&load_stdlib()?)?; // Load stdlib
+    let profile = (profile_report || profile_folded.is_some()).then(|| {
+        let profile = Rc::new(RefCell::new(Profile::default()));
+        interpreter.add_hook(Box::new(Profiler::new(profile.clone())));
+        profile
+    });
     interpreter.execute(&root)?;
 
+    if let Some(profile) = profile {
+        let profile = profile.borrow();
+        if profile_report {
+            profile.write_report(&mut stderr())?;
+        }
+        if let Some(path) = profile_folded {
+            profile.write_folded(&mut BufWriter::new(File::create(path)?))?;
+        }
+    }
+
+    if let Some(coverage) = coverage {
+        let coverage = coverage.borrow();
+        let stdlib = load_stdlib()?;
+        let reports = [coverage.report(&root), coverage.report(&stdlib)];
+
+        if coverage_listing || coverage_output.is_some() {
+            let mut output: Box<dyn Write> = match coverage_output {
+                Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+                None => Box::new(stderr()),
+            };
+            for report in &reports {
+                report.write_listing(&mut output)?;
+            }
+        }
+        if let Some(path) = coverage_lcov {
+            let mut output = BufWriter::new(File::create(path)?);
+            for report in &reports {
+                report.write_lcov(&mut output)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Run a code file under an interactive debugger, optionally with an input file.
+fn debug(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let code_path = args.first().expect("no code path passed");
+    let input_path = args.get(1);
+
+    let code = read_to_string(code_path)?;
+    let root = code_to_node(&code, code_path)?;
+
+    // Install the debugger after the stdlib is loaded, so we don't start off stepping through it
+    let mut interpreter = prepare_interpreter(input_path.map(|p| p.as_str()))?;
+    interpreter.add_hook(Box::new(Debugger::new(code_path)));
+
+    if let Err(e) = interpreter.execute(&root) {
+        println!("Execution error: {e}");
+        if let Some(loc) = e.loc() {
+            print_source_context(loc, 1);
+            print_backtrace(&interpreter, loc);
+        }
+        for (message, loc) in e.notes() {
+            println!("Note: {message}");
+            print_source_context(loc, 1);
+        }
+        exit(1);
+    }
+
+    Ok(())
+}
+
+/// Time running a code file, optionally with an input file, several times.
+///
+/// `--runs=N` sets how many runs are measured (default 10), after `--warmup=N` unmeasured runs
+/// (default 2). `--save=PATH` saves the results as a baseline, and `--compare=PATH` compares against
+/// a saved baseline, failing if any phase's median time got slower by more than `--threshold=PERCENT`
+/// (default 5).
+fn bench(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let (options, paths): (Vec<_>, Vec<_>) = args.iter().partition(|a| a.starts_with("--"));
+    let code_path = paths.first().expect("no code path passed");
+    let input_path = paths.get(1);
+
+    let mut runs = 10;
+    let mut warmup = 2;
+    let mut save = None;
+    let mut compare = None;
+    let mut threshold = 5.0;
+    for option in options {
+        match option.split_once('=') {
+            Some(("--runs", n)) => runs = n.parse()?,
+            Some(("--warmup", n)) => warmup = n.parse()?,
+            Some(("--save", path)) => save = Some(path),
+            Some(("--compare", path)) => compare = Some(path),
+            Some(("--threshold", percent)) => threshold = percent.parse()?,
+            _ => return Err(format!("unknown option `{option}`").into()),
+        }
+    }
+
+    println!("Benchmarking {code_path}: {warmup} warm-up run(s), {runs} measured run(s)");
+    let benchmark = Benchmark::run(code_path, input_path.map(|p| p.as_str()), warmup, runs)?;
+    benchmark.write_report(&mut stdout())?;
+
+    if let Some(path) = save {
+        write(path, benchmark.to_json().to_string())?;
+        println!("Saved baseline to {path}");
+    }
+
+    if let Some(path) = compare {
+        let baseline = Benchmark::from_json(&Json::parse(&read_to_string(path)?)?)?;
+        println!();
+        let regressions = benchmark.compare(&baseline, threshold, &mut stdout())?;
+        if !regressions.is_empty() {
+            println!("Slower than baseline by more than {threshold}%: {}", regressions.join(", "));
+            exit(1);
+        }
+    }
+
     Ok(())
 }
 
@@ -49,6 +233,9 @@ fn repl() -> Result<(), Box<dyn Error>> {
     let mut interpreter = Interpreter::new();
     interpreter.execute(&load_stdlib()?)?;
 
+    let mut docs = builtin_docs();
+    docs.extend(source_docs(&stdlib_source())?);
+
     loop {
         print!("> ");
         stdout().flush()?;
@@ -56,19 +243,27 @@ fn repl() -> Result<(), Box<dyn Error>> {
         let mut line = String::new();
         stdin().read_line(&mut line)?;
 
-        let node;
-        match code_to_node(&line, "(repl)") {
-            Ok(n) => node = n,
+        // `help <action>` shows documentation rather than executing anything
+        if let Some(name) = line.trim().strip_prefix("help ") {
+            match docs.iter().find(|d| d.name == name.trim()) {
+                Some(doc) => println!("{doc}"),
+                None => println!("No documentation for `{}`", name.trim()),
+            }
+            continue;
+        }
+
+        let node = match code_to_node(&line, "(repl)") {
+            Ok(n) => n,
             Err(e) => {
                 println!("Parse error: {e}");
                 continue;
             }
-        }
+        };
 
         match interpreter.execute(&node) {
             Ok(_) => {
-                interpreter.print_stack_debug();
-                println!("");
+                interpreter.print_stack_debug()?;
+                println!();
             },
             Err(e) => {
                 println!("Execution error: {e}");
@@ -78,6 +273,114 @@ fn repl() -> Result<(), Box<dyn Error>> {
     }
 }
 
-fn load_stdlib() -> Result<Node, Box<dyn Error>> {
-    code_to_node(include_str!("../lib/stdlib.stk"), "(stdlib)")
+/// Statically check the stack effects of the given code files, alongside the stdlib.
+fn check(code_paths: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let stdlib = load_stdlib()?;
+    let roots = load_code_files(&code_paths)?;
+
+    let mut checker = Checker::new();
+    checker.add_definitions(&stdlib);
+    for root in &roots {
+        checker.add_definitions(root);
+    }
+
+    checker.check_actions();
+    checker.check_program(&stdlib);
+    for root in &roots {
+        checker.check_program(root);
+    }
+
+    let diagnostics = checker.into_diagnostics();
+    for diagnostic in &diagnostics {
+        println!("{diagnostic}");
+    }
+
+    if !diagnostics.is_empty() {
+        exit(1);
+    }
+    Ok(())
+}
+
+/// Lint the given code files, alongside the stdlib.
+fn lint(code_paths: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let stdlib = load_stdlib()?;
+    let roots = load_code_files(&code_paths)?;
+
+    let mut linter = Linter::new(&["$input"]);
+    linter.add_program(&stdlib);
+    for root in &roots {
+        linter.add_program(root);
+    }
+
+    linter.lint_program(&stdlib);
+    for root in &roots {
+        linter.lint_program(root);
+    }
+
+    let warnings = linter.into_warnings();
+    for warning in &warnings {
+        println!("{warning}");
+    }
+
+    if !warnings.is_empty() {
+        exit(1);
+    }
+    Ok(())
+}
+
+/// Format the given code files in-place.
+/// With `--check`, instead list the files which aren't formatted, without changing them.
+fn fmt(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let check_only = args.iter().any(|a| a == "--check");
+
+    let mut any_unformatted = false;
+    for code_path in args.iter().filter(|a| *a != "--check") {
+        let code = read_to_string(code_path)?;
+        let source = LocSource::new(code_path.to_owned(), Rc::new(code.clone()));
+        let formatted = format_source(&source)?;
+
+        if formatted != code {
+            any_unformatted = true;
+            if check_only {
+                println!("{code_path}");
+            } else {
+                write(code_path, formatted)?;
+            }
+        }
+    }
+
+    if check_only && any_unformatted {
+        exit(1);
+    }
+    Ok(())
+}
+
+/// Print a reference of all builtins, stdlib actions, and actions in the given code files.
+/// Markdown by default, or HTML with `--html`.
+fn doc(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let html = args.iter().any(|a| a == "--html");
+
+    let mut docs = builtin_docs();
+    docs.extend(source_docs(&stdlib_source())?);
+    for code_path in args.iter().filter(|a| *a != "--html") {
+        let code = read_to_string(code_path)?;
+        docs.extend(source_docs(&LocSource::new(code_path.to_owned(), Rc::new(code)))?);
+    }
+
+    if html {
+        print!("{}", render_html(&docs));
+    } else {
+        print!("{}", render_markdown(&docs));
+    }
+    Ok(())
+}
+
+fn load_code_files(code_paths: &[String]) -> Result<Vec<Node>, Box<dyn Error>> {
+    let mut roots = vec![];
+    for code_path in code_paths {
+        let code = read_to_string(code_path)?;
+        roots.push(code_to_node(&code, code_path)?);
+    }
+
+    Ok(roots)
 }
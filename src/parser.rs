@@ -1,40 +1,182 @@
-use std::error::Error;
+use std::{error::Error, fmt::Display, rc::Rc};
 
-use crate::{loc::Loc, token::{Atom, Token, TokenKind}};
+use crate::{builtins::{builtin, Builtin}, loc::{Loc, LocSource}, token::{Atom, Token, TokenKind}};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum NodeKind {
     Atom(Atom),
     Sequence(Vec<Node>),
-    Block(Box<Node>),
+    Block(Rc<Node>),
+    StackEffect(StackEffect),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Node {
     pub kind: NodeKind,
     pub loc: Loc,
+
+    /// If this is an action atom naming a builtin, that builtin. It's looked up once here rather
+    /// than every time the atom is executed, which is fine as builtins can't be redefined.
+    pub builtin: Option<&'static Builtin>,
 }
 
 impl Node {
     pub fn new(kind: NodeKind, loc: Loc) -> Self {
-        Self { kind, loc }
+        let builtin = match &kind {
+            NodeKind::Atom(Atom::Action(name)) => builtin(name),
+            _ => None,
+        };
+        Self { kind, loc, builtin }
+    }
+
+    /// If this node is the body of a block which begins with a stack effect annotation, returns
+    /// that annotation.
+    pub fn stack_effect(&self) -> Option<&StackEffect> {
+        let NodeKind::Sequence(items) = &self.kind else { return None };
+        match items.first() {
+            Some(Node { kind: NodeKind::StackEffect(effect), .. }) => Some(effect),
+            _ => None,
+        }
+    }
+
+    /// Finds every user action definition within this node, at any depth.
+    pub fn definitions(&self) -> Vec<Definition<'_>> {
+        let mut definitions = vec![];
+        self.find_definitions(&mut definitions);
+        definitions
+    }
+
+    fn find_definitions<'a>(&'a self, definitions: &mut Vec<Definition<'a>>) {
+        match &self.kind {
+            NodeKind::Sequence(items) => {
+                for window in items.windows(3) {
+                    if let [
+                        Node { kind: NodeKind::Block(body), .. },
+                        Node { kind: NodeKind::Atom(Atom::Binding(name)), loc, .. },
+                        Node { kind: NodeKind::Atom(Atom::Action(action)), .. },
+                    ] = window && is_definition_action(action) {
+                        let name = name.strip_prefix('$').unwrap();
+                        definitions.push(Definition { name, body, loc });
+                    }
+                }
+
+                for item in items {
+                    item.find_definitions(definitions);
+                }
+            }
+            NodeKind::Block(body) => body.find_definitions(definitions),
+            NodeKind::Atom(_) | NodeKind::StackEffect(_) => (),
+        }
+    }
+}
+
+/// Whether an action assigns a value to the binding before it: `:` makes a new binding, `:=`
+/// reassigns an existing one, `:shadow` hides an outer binding with a new one, and `:const` makes a
+/// binding which can't be changed.
+pub fn is_assignment_action(action: &str) -> bool {
+    matches!(action, ":" | ":=" | ":shadow" | ":const")
+}
+
+/// Whether an action defines a user action from the block and binding before it: `::`, or
+/// `::memo` for a memoized one.
+pub fn is_definition_action(action: &str) -> bool {
+    action == "::" || action == "::memo"
+}
+
+/// A user action definition, written `{ ... } $name ::` or `{ ... } $name ::memo`.
+pub struct Definition<'a> {
+    /// The name of the action, without the `$`.
+    pub name: &'a str,
+    pub body: &'a Node,
+
+    /// The location of the `$name` binding.
+    pub loc: &'a Loc,
+}
+
+/// A declared stack effect, written like `( a b -- c )` at the start of a block.
+/// 
+/// The names are purely descriptive - only the number of items on each side matters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackEffect {
+    pub inputs: Vec<String>,
+    pub outputs: Vec<String>,
+}
+
+impl StackEffect {
+    pub fn new(inputs: Vec<String>, outputs: Vec<String>) -> Self {
+        Self { inputs, outputs }
+    }
+
+    /// Builds a [StackEffect] from the names written inside its parentheses, which must be split
+    /// into inputs and outputs by exactly one `--`.
+    pub fn from_names(names: Vec<String>) -> Result<Self, Box<dyn Error>> {
+        let mut parts = names.split(|n| n == "--");
+        let (Some(inputs), Some(outputs), None) = (parts.next(), parts.next(), parts.next()) else {
+            return Err("stack effect annotation must contain exactly one `--`".into())
+        };
+
+        Ok(StackEffect::new(inputs.to_vec(), outputs.to_vec()))
+    }
+
+    /// Parses the inside of a stack effect annotation from a string, like `"a b -- c"`.
+    pub fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        Self::from_names(s.split_whitespace().map(|n| n.to_owned()).collect())
     }
 }
 
-pub fn parse(mut tokens: Vec<Token>) -> Result<Node, Box<dyn Error>> {
+impl Display for StackEffect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "(")?;
+        for name in &self.inputs {
+            write!(f, " {name}")?;
+        }
+        write!(f, " --")?;
+        for name in &self.outputs {
+            write!(f, " {name}")?;
+        }
+        write!(f, " )")
+    }
+}
+
+/// An error in the syntax of some source code, pointing at where it was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub message: String,
+    pub loc: Loc,
+}
+
+impl SyntaxError {
+    pub fn new(message: impl Into<String>, loc: &Loc) -> Self {
+        SyntaxError { message: message.into(), loc: loc.clone() }
+    }
+}
+
+impl Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (line, col) = self.loc.line_col();
+        write!(f, "{}:{line}:{col}: {}", self.loc.source.name, self.message)
+    }
+}
+impl Error for SyntaxError {}
+
+/// Parses the tokens of some source. An empty program, with no tokens, has an empty [Loc] at the
+/// start of `source`.
+pub fn parse(mut tokens: Vec<Token>, source: &LocSource) -> Result<Node, Box<dyn Error>> {
     // Reverse tokens so we get a stack which we can pop from
     tokens.reverse();
 
-    let node = parse_sequence(&mut tokens, false)?;
+    let node = parse_sequence(&mut tokens, source, None)?;
 
-    if !tokens.is_empty() {
-        return Err(format!("unable to parse from: {:?}", tokens.pop().unwrap()).into())
+    if let Some(token) = tokens.pop() {
+        return Err(SyntaxError::new(format!("unable to parse from: {:?}", token.kind), &token.loc).into())
     }
 
     Ok(node)
 }
 
-fn parse_sequence(tokens: &mut Vec<Token>, in_block: bool) -> Result<Node, Box<dyn Error>> {
+/// Parses a sequence until the tokens run out, or until the closing brace if this is the body of a
+/// block opened at `block_open`.
+fn parse_sequence(tokens: &mut Vec<Token>, source: &LocSource, block_open: Option<&Loc>) -> Result<Node, Box<dyn Error>> {
     let mut items = vec![];
 
     while let Some(Token { kind, loc }) = tokens.pop() {
@@ -42,39 +184,78 @@ fn parse_sequence(tokens: &mut Vec<Token>, in_block: bool) -> Result<Node, Box<d
             TokenKind::Atom(atom) => items.push(Node::new(NodeKind::Atom(atom), loc)),
 
             TokenKind::LBrace => {
-                let body = parse_sequence(tokens, true)?;
-                items.push(Node::new(NodeKind::Block(Box::new(body)), loc))
+                let body = parse_sequence(tokens, source, Some(&loc))?;
+                items.push(Node::new(NodeKind::Block(Rc::new(body)), loc))
             }
 
+            TokenKind::LParen => {
+                // Only permitted as the very first thing in a block, so that it's clear what the
+                // annotation applies to
+                if block_open.is_none() || !items.is_empty() {
+                    return Err(SyntaxError::new("stack effect annotations must be at the start of a block", &loc).into())
+                }
+
+                let (effect, end_loc) = parse_stack_effect(tokens, &loc)?;
+                items.push(Node::new(NodeKind::StackEffect(effect), Loc::new_spanning(&loc, &end_loc)))
+            }
+
+            TokenKind::RParen => {
+                return Err(SyntaxError::new("unexpected `)` outside of a stack effect annotation", &loc).into())
+            }
+
+            // Not expected from `tokenize`, but harmless
+            TokenKind::Comment(_) | TokenKind::Whitespace(_) => (),
+
             TokenKind::RBrace => {
-                if in_block {
+                if block_open.is_some() {
                     // `items` will be empty for an empty block - if so, point at the brace
-                    let span_loc =
-                        if items.is_empty() {
-                            loc
-                        } else {
-                            loc_spanning(&items)
-                        };
+                    let span_loc = loc_spanning(&items).unwrap_or(loc);
                     return Ok(Node::new(NodeKind::Sequence(items), span_loc))
                 } else {
-                    return Err("unexpected end of block while not inside a block".into())
+                    return Err(SyntaxError::new("unexpected end of block while not inside a block", &loc).into())
                 }
             }
         }
     }
 
-    if in_block {
-        return Err("ran out of tokens while inside block".into())
+    if let Some(open) = block_open {
+        return Err(SyntaxError::new("ran out of tokens while inside block", open).into())
     }
 
-    // I don't think it's possible for `items` to be empty here
-    let loc = loc_spanning(&items);
-    return Ok(Node::new(NodeKind::Sequence(items), loc)) 
+    // `items` will be empty if the source has no tokens, or only comments
+    let loc = loc_spanning(&items).unwrap_or_else(|| Loc::new(source.clone(), 0, 0));
+    Ok(Node::new(NodeKind::Sequence(items), loc))
+}
+
+/// Parses the names of a stack effect annotation, after its opening `(` at `open` has been consumed.
+/// Also returns the [Loc] of the closing `)`.
+fn parse_stack_effect(tokens: &mut Vec<Token>, open: &Loc) -> Result<(StackEffect, Loc), Box<dyn Error>> {
+    let mut names = vec![];
+
+    loop {
+        match tokens.pop() {
+            Some(Token { kind: TokenKind::RParen, loc }) => {
+                let effect = StackEffect::from_names(names)
+                    .map_err(|e| SyntaxError::new(e.to_string(), &Loc::new_spanning(open, &loc)))?;
+                return Ok((effect, loc))
+            },
+
+            Some(Token { kind: TokenKind::Atom(Atom::Action(name) | Atom::Binding(name)), .. }) =>
+                names.push(name),
+
+            Some(token) if token.is_trivia() => (),
+
+            Some(token) =>
+                return Err(SyntaxError::new(format!("unexpected token in stack effect annotation: {:?}", token.kind), &token.loc).into()),
+
+            None =>
+                return Err(SyntaxError::new("ran out of tokens while inside stack effect annotation", open).into()),
+        }
+    }
 }
 
-fn loc_spanning(nodes: &[Node]) -> Loc {
-    nodes.iter().fold(
-        nodes.first().unwrap().loc.clone(),
-        |acc, el| Loc::new_spanning(&acc, &el.loc)
-    )
+/// The [Loc] covering every node, or `None` if there aren't any.
+fn loc_spanning(nodes: &[Node]) -> Option<Loc> {
+    let first = nodes.first()?.loc.clone();
+    Some(nodes.iter().fold(first, |acc, el| Loc::new_spanning(&acc, &el.loc)))
 }
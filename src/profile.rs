@@ -0,0 +1,296 @@
+use std::{cell::RefCell, collections::HashMap, io::{self, Write}, rc::Rc, time::{Duration, Instant}};
+
+use crate::{eval::{ExecutionError, Hook, Interpreter, Memo}, loc::Loc, parser::{Node, NodeKind}, token::Atom};
+
+/// Timings for one builtin or user action.
+#[derive(Debug, Clone)]
+pub struct ProfileEntry {
+    pub name: String,
+
+    /// Where a user action was defined, or `None` for builtins and other native actions.
+    pub definition: Option<Loc>,
+
+    pub calls: usize,
+
+    /// Total time spent in calls, including anything they called. Recursive calls are only counted
+    /// once, by the outermost call.
+    pub inclusive: Duration,
+
+    /// Time spent in calls, excluding other actions which they called.
+    pub exclusive: Duration,
+
+    /// Cache hits and misses, if this is a memoized user action.
+    pub memo: Option<(usize, usize)>,
+
+    /// How many calls are currently executing, to avoid counting recursion twice.
+    active: usize,
+}
+
+/// A unique path through the actions which have been called, for folded stack output.
+struct CallTreeNode {
+    entry: usize,
+    parent: Option<usize>,
+    children: HashMap<usize, usize>,
+    exclusive: Duration,
+}
+
+/// An action call which is currently executing.
+struct ActiveCall {
+    entry: usize,
+    tree_node: usize,
+    start: Instant,
+
+    /// Inclusive time of the actions called so far, to subtract from this call's exclusive time.
+    children: Duration,
+}
+
+/// Call counts and timings for each action executed, collected by a [Profiler].
+#[derive(Default)]
+pub struct Profile {
+    /// Entries in the order that they were first called.
+    entries: Vec<ProfileEntry>,
+    indices: HashMap<String, usize>,
+
+    /// The roots of the call tree, which are actions called from the top-level.
+    roots: HashMap<usize, usize>,
+    tree: Vec<CallTreeNode>,
+
+    /// Action calls currently executing, innermost last.
+    calls: Vec<ActiveCall>,
+}
+
+impl Profile {
+    /// All entries, sorted by exclusive time with the most expensive first.
+    pub fn entries(&self) -> Vec<&ProfileEntry> {
+        let mut entries = self.entries.iter().collect::<Vec<_>>();
+        entries.sort_by(|a, b| b.exclusive.cmp(&a.exclusive).then(a.name.cmp(&b.name)));
+        entries
+    }
+
+    /// Writes a table of [entries](Profile::entries), most expensive first.
+    pub fn write_report(&self, output: &mut impl Write) -> io::Result<()> {
+        writeln!(output, "{:>10} {:>12} {:>12}  action", "calls", "total ms", "self ms")?;
+        for entry in self.entries() {
+            let definition = match &entry.definition {
+                Some(loc) => {
+                    let (line, col) = loc.line_col();
+                    format!("{}:{line}:{col}", loc.source.name)
+                },
+                None => "builtin".to_owned(),
+            };
+            let memo = match entry.memo {
+                Some((hits, misses)) => format!(" [memo: {hits} hits, {misses} misses]"),
+                None => String::new(),
+            };
+            writeln!(
+                output, "{:>10} {:>12.3} {:>12.3}  {} ({definition}){memo}",
+                entry.calls, millis(entry.inclusive), millis(entry.exclusive), entry.name,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Writes exclusive time in microseconds for each distinct call stack, in the "folded" format
+    /// used by flamegraph tools like `inferno` and `flamegraph.pl`:
+    ///
+    /// ```text
+    /// interpret;fold;map;# 1234
+    /// ```
+    pub fn write_folded(&self, output: &mut impl Write) -> io::Result<()> {
+        let mut lines = vec![];
+        for (index, node) in self.tree.iter().enumerate() {
+            let micros = node.exclusive.as_micros();
+            if micros == 0 {
+                continue;
+            }
+
+            let mut names = vec![];
+            let mut current = Some(index);
+            while let Some(i) = current {
+                names.push(self.entries[self.tree[i].entry].name.as_str());
+                current = self.tree[i].parent;
+            }
+            names.reverse();
+            lines.push(format!("{} {micros}", names.join(";")));
+        }
+
+        lines.sort();
+        for line in lines {
+            writeln!(output, "{line}")?;
+        }
+        Ok(())
+    }
+
+    fn enter(&mut self, interpreter: &Interpreter, name: &str) {
+        let entry = match self.indices.get(name) {
+            Some(entry) => *entry,
+            None => {
+                // Native actions take priority over user actions with the same name
+                let definition = match interpreter.native_action(name) {
+                    Some(_) => None,
+                    None => interpreter.user_action(name).map(|body| body.loc.clone()),
+                };
+                self.entries.push(ProfileEntry {
+                    name: name.to_owned(),
+                    definition,
+                    calls: 0,
+                    inclusive: Duration::ZERO,
+                    exclusive: Duration::ZERO,
+                    memo: None,
+                    active: 0,
+                });
+                self.indices.insert(name.to_owned(), self.entries.len() - 1);
+                self.entries.len() - 1
+            },
+        };
+        self.entries[entry].calls += 1;
+        self.entries[entry].active += 1;
+
+        let parent = self.calls.last().map(|c| c.tree_node);
+        let siblings = match parent {
+            Some(parent) => &self.tree[parent].children,
+            None => &self.roots,
+        };
+        let tree_node = match siblings.get(&entry) {
+            Some(node) => *node,
+            None => {
+                let node = self.tree.len();
+                self.tree.push(CallTreeNode { entry, parent, children: HashMap::new(), exclusive: Duration::ZERO });
+                match parent {
+                    Some(parent) => self.tree[parent].children.insert(entry, node),
+                    None => self.roots.insert(entry, node),
+                };
+                node
+            },
+        };
+
+        self.calls.push(ActiveCall { entry, tree_node, start: Instant::now(), children: Duration::ZERO });
+    }
+
+    /// Copies the cache statistics of a memoized user action into its entry.
+    fn record_memo(&mut self, name: &str, memo: &Memo) {
+        if let Some(entry) = self.indices.get(name) {
+            self.entries[*entry].memo = Some((memo.hits, memo.misses));
+        }
+    }
+
+    fn exit(&mut self) {
+        let Some(call) = self.calls.pop() else { return };
+        let inclusive = call.start.elapsed();
+        let exclusive = inclusive.saturating_sub(call.children);
+
+        let entry = &mut self.entries[call.entry];
+        entry.active -= 1;
+        if entry.active == 0 {
+            entry.inclusive += inclusive;
+        }
+        entry.exclusive += exclusive;
+        self.tree[call.tree_node].exclusive += exclusive;
+
+        if let Some(parent) = self.calls.last_mut() {
+            parent.children += inclusive;
+        }
+    }
+}
+
+/// A [Hook] which times every action call, recording the results into a shared [Profile].
+pub struct Profiler {
+    profile: Rc<RefCell<Profile>>,
+}
+
+impl Profiler {
+    pub fn new(profile: Rc<RefCell<Profile>>) -> Self {
+        Profiler { profile }
+    }
+}
+
+impl Hook for Profiler {
+    fn before_atom(&mut self, interpreter: &Interpreter, node: &Node) -> Result<(), ExecutionError> {
+        if let NodeKind::Atom(Atom::Action(name)) = &node.kind {
+            self.profile.borrow_mut().enter(interpreter, name);
+        }
+        Ok(())
+    }
+
+    fn after_atom(&mut self, interpreter: &Interpreter, node: &Node) -> Result<(), ExecutionError> {
+        if let NodeKind::Atom(Atom::Action(name)) = &node.kind {
+            let mut profile = self.profile.borrow_mut();
+            profile.exit();
+            if interpreter.native_action(name).is_none() && let Some(memo) = interpreter.memo(name) {
+                profile.record_memo(name, memo);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::code_to_node;
+
+    use super::*;
+
+    /// Runs code with a profiler, and returns whether it succeeded and the profile.
+    fn profile(code: &str) -> (bool, Profile) {
+        let profile = Rc::new(RefCell::new(Profile::default()));
+        let mut interpreter = Interpreter::new();
+        interpreter.add_hook(Box::new(Profiler::new(profile.clone())));
+        let succeeded = interpreter.execute(&code_to_node(code, "code.stk").unwrap()).is_ok();
+
+        drop(interpreter);
+        (succeeded, Rc::into_inner(profile).unwrap().into_inner())
+    }
+
+    /// Each entry's name, call count, and the line and column where it was defined.
+    fn calls(profile: &Profile) -> Vec<(&str, usize, Option<String>)> {
+        let mut calls = profile.entries().into_iter()
+            .map(|e| (e.name.as_str(), e.calls, e.definition.as_ref().map(|loc| {
+                let (line, col) = loc.line_col();
+                format!("{line}:{col}")
+            })))
+            .collect::<Vec<_>>();
+        calls.sort();
+        calls
+    }
+
+    #[test]
+    fn counts_calls_to_each_action() {
+        let (succeeded, profile) = profile("{ ( n -- n ) 1 + } $inc ::\n5 inc inc");
+        assert!(succeeded);
+        assert_eq!(calls(&profile), vec![("+", 2, None), ("::", 1, None), ("inc", 2, Some("1:3".to_owned()))]);
+        assert!(profile.entries().iter().all(|e| e.exclusive <= e.inclusive && e.memo.is_none()));
+
+        // Every call stack is one which actually happened
+        let mut folded = vec![];
+        profile.write_folded(&mut folded).unwrap();
+        for line in String::from_utf8(folded).unwrap().lines() {
+            let (stack, micros) = line.rsplit_once(' ').unwrap();
+            assert!(["::", "inc", "inc;+"].contains(&stack), "{line}");
+            assert!(micros.parse::<u128>().unwrap() > 0, "{line}");
+        }
+
+        let mut report = vec![];
+        profile.write_report(&mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.starts_with("     calls     total ms      self ms  action\n"), "{report}");
+        assert!(report.contains(" inc (code.stk:1:3)\n"), "{report}");
+    }
+
+    #[test]
+    fn records_memo_statistics() {
+        let (_, profile) = profile("{ ( n -- n ) 1 + } $inc ::memo\n5 inc 5 inc 6 inc");
+        let inc = profile.entries().into_iter().find(|e| e.name == "inc").unwrap();
+        assert_eq!((inc.calls, inc.memo), (3, Some((1, 2))));
+    }
+
+    #[test]
+    fn keeps_calls_made_before_an_error() {
+        let (succeeded, profile) = profile("{ ( n -- n ) 1 + } $inc ::\n5 inc +");
+        assert!(!succeeded);
+        assert_eq!(calls(&profile), vec![("+", 2, None), ("::", 1, None), ("inc", 1, Some("1:3".to_owned()))]);
+    }
+}
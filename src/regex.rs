@@ -0,0 +1,744 @@
+//! A small regular expression engine, used by the `re-*` builtins.
+//!
+//! Patterns are compiled to a program for a Pike VM, which runs in time linear in the length of
+//! the text, and picks the same match a backtracking engine would: the leftmost one, preferring
+//! earlier alternatives and following greediness.
+//!
+//! Supported syntax:
+//!   - Literals, and `.` for any character except a newline
+//!   - Classes like `[a-z_]` and `[^,]`, and the escapes `\d`, `\w`, `\s` and their negations
+//!   - Groups `( ... )`, non-capturing groups `(?: ... )` and alternation `|`
+//!   - Quantifiers `*`, `+`, `?`, `{n}`, `{n,}` and `{n,m}`, each made lazy by a following `?`. Repeating
+//!     a repetition, like `a**`, needs a group: `(?:a*)*`
+//!   - Anchors `^` and `$` for the start and end of the text, and `\b` and `\B` for word boundaries
+//!
+//! Text is matched as characters rather than bytes, so offsets are character indices.
+
+use std::{error::Error, fmt::Display};
+
+/// A compiled regular expression.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    pattern: String,
+    program: Vec<Inst>,
+
+    /// Number of capture groups, including the implicit group 0 for the whole match.
+    groups: usize,
+}
+
+/// The start and end offsets of each capture group in a match, if the group took part in it.
+/// Group 0 is the whole match, so it's always present.
+pub type Captures = Vec<Option<(usize, usize)>>;
+
+impl Regex {
+    /// Compiles a pattern.
+    pub fn new(pattern: &str) -> Result<Regex, RegexError> {
+        let mut parser = RegexParser { chars: pattern.chars().collect(), pos: 0, groups: 1 };
+        let ast = parser.parse_alternation()?;
+        if parser.pos < parser.chars.len() {
+            // The only thing which stops an alternation early is an unmatched `)`
+            return Err(parser.error("unmatched `)`"));
+        }
+
+        let mut program = vec![Inst::Save(0)];
+        compile(&ast, &mut program);
+        program.push(Inst::Save(1));
+        program.push(Inst::Match);
+
+        Ok(Regex { pattern: pattern.to_owned(), program, groups: parser.groups })
+    }
+
+    /// The pattern this was compiled from.
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Number of capture groups, including group 0 for the whole match.
+    pub fn group_count(&self) -> usize {
+        self.groups
+    }
+
+    /// Whether the pattern matches anywhere in the text.
+    pub fn is_match(&self, text: &[char]) -> bool {
+        self.find_at(text, 0).is_some()
+    }
+
+    /// Finds the leftmost match which starts at or after `start`.
+    pub fn find_at(&self, text: &[char], start: usize) -> Option<Captures> {
+        PikeVm::new(self, text).run(start)
+    }
+
+    /// Finds every non-overlapping match, from left to right.
+    pub fn find_all(&self, text: &[char]) -> Vec<Captures> {
+        let mut matches = vec![];
+        let mut start = 0;
+        while start <= text.len() && let Some(captures) = self.find_at(text, start) {
+            let (match_start, match_end) = captures[0].unwrap();
+
+            // Step past empty matches, so they aren't found again
+            start = if match_end == match_start { match_end + 1 } else { match_end };
+            matches.push(captures);
+        }
+        matches
+    }
+
+    /// Replaces every match. In the replacement, `$0` to `$9` insert the text of that group, which
+    /// is empty if it didn't take part in the match, and `$$` inserts a literal `$`.
+    pub fn replace_all(&self, text: &[char], replacement: &[char]) -> Vec<char> {
+        let mut result = vec![];
+        let mut copied_up_to = 0;
+
+        for captures in self.find_all(text) {
+            let (match_start, match_end) = captures[0].unwrap();
+            result.extend_from_slice(&text[copied_up_to..match_start]);
+            copied_up_to = match_end;
+
+            let mut i = 0;
+            while i < replacement.len() {
+                match (replacement[i], replacement.get(i + 1)) {
+                    ('$', Some('$')) => {
+                        result.push('$');
+                        i += 2;
+                    },
+                    ('$', Some(d)) if let Some(group) = d.to_digit(10) && (group as usize) < self.groups => {
+                        if let Some((start, end)) = captures[group as usize] {
+                            result.extend_from_slice(&text[start..end]);
+                        }
+                        i += 2;
+                    },
+                    (c, _) => {
+                        result.push(c);
+                        i += 1;
+                    },
+                }
+            }
+        }
+
+        result.extend_from_slice(&text[copied_up_to..]);
+        result
+    }
+}
+
+// Compiled regexes are equal if they came from the same pattern
+impl PartialEq for Regex {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern
+    }
+}
+impl Eq for Regex {}
+
+/// Error encountered while compiling a pattern, with the position it happened at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegexError {
+    pub message: String,
+
+    /// Character offset into the pattern.
+    pub pos: usize,
+}
+
+impl Display for RegexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid regex at position {}: {}", self.pos, self.message)
+    }
+}
+impl Error for RegexError {}
+
+#[derive(Debug, Clone)]
+enum Ast {
+    Empty,
+    Literal(char),
+    Class(CharClass),
+    Assert(Assertion),
+
+    /// A group, with its capture index if it's capturing.
+    Group(Box<Ast>, Option<usize>),
+
+    Concat(Vec<Ast>),
+    Alternate(Vec<Ast>),
+    Repeat { ast: Box<Ast>, min: usize, max: Option<usize>, greedy: bool },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Assertion {
+    Start,
+    End,
+    WordBoundary,
+    NotWordBoundary,
+}
+
+/// A set of characters, as inclusive ranges.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CharClass {
+    ranges: Vec<(char, char)>,
+
+    /// Sets whose complements are included, from escapes like `\D` inside a class.
+    excluded: Vec<Vec<(char, char)>>,
+
+    negated: bool,
+}
+
+const DIGIT: &[(char, char)] = &[('0', '9')];
+const WORD: &[(char, char)] = &[('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')];
+const SPACE: &[(char, char)] = &[('\t', '\r'), (' ', ' ')];
+
+impl CharClass {
+    fn new(ranges: &[(char, char)], negated: bool) -> CharClass {
+        CharClass { ranges: ranges.to_vec(), excluded: vec![], negated }
+    }
+
+    fn matches(&self, c: char) -> bool {
+        let in_ranges = |ranges: &[(char, char)]| ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&c));
+        let included = in_ranges(&self.ranges) || self.excluded.iter().any(|ranges| !in_ranges(ranges));
+        included != self.negated
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+struct RegexParser {
+    chars: Vec<char>,
+    pos: usize,
+
+    /// Number of capture groups seen so far, including group 0.
+    groups: usize,
+}
+
+impl RegexParser {
+    fn error(&self, message: impl Into<String>) -> RegexError {
+        RegexError { message: message.into(), pos: self.pos }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_alternation(&mut self) -> Result<Ast, RegexError> {
+        let mut alternatives = vec![self.parse_concat()?];
+        while self.eat('|') {
+            alternatives.push(self.parse_concat()?);
+        }
+
+        Ok(match alternatives.len() {
+            1 => alternatives.pop().unwrap(),
+            _ => Ast::Alternate(alternatives),
+        })
+    }
+
+    fn parse_concat(&mut self) -> Result<Ast, RegexError> {
+        let mut items = vec![];
+        while let Some(c) = self.peek() && c != '|' && c != ')' {
+            let atom = self.parse_atom()?;
+            items.push(self.parse_quantifiers(atom)?);
+        }
+
+        Ok(match items.len() {
+            0 => Ast::Empty,
+            1 => items.pop().unwrap(),
+            _ => Ast::Concat(items),
+        })
+    }
+
+    fn parse_atom(&mut self) -> Result<Ast, RegexError> {
+        let start = self.pos;
+        let c = self.peek().unwrap();
+        self.pos += 1;
+
+        match c {
+            '.' => Ok(Ast::Class(CharClass::new(&[('\n', '\n')], true))),
+            '^' => Ok(Ast::Assert(Assertion::Start)),
+            '$' => Ok(Ast::Assert(Assertion::End)),
+            '[' => self.parse_class(),
+            '\\' => self.parse_escape(),
+
+            '(' => {
+                let index = if self.chars[self.pos..].starts_with(&['?', ':']) {
+                    self.pos += 2;
+                    None
+                } else {
+                    self.groups += 1;
+                    Some(self.groups - 1)
+                };
+
+                let inner = self.parse_alternation()?;
+                if !self.eat(')') {
+                    self.pos = start;
+                    return Err(self.error("unclosed group"));
+                }
+                Ok(Ast::Group(Box::new(inner), index))
+            },
+
+            '*' | '+' | '?' => {
+                self.pos = start;
+                Err(self.error(format!("nothing to repeat before `{c}`")))
+            },
+
+            c => Ok(Ast::Literal(c)),
+        }
+    }
+
+    fn parse_escape(&mut self) -> Result<Ast, RegexError> {
+        let Some(c) = self.peek() else {
+            return Err(self.error("pattern ends with `\\`"));
+        };
+        self.pos += 1;
+
+        Ok(match c {
+            'b' => Ast::Assert(Assertion::WordBoundary),
+            'B' => Ast::Assert(Assertion::NotWordBoundary),
+            _ => match self.escaped_class(c) {
+                Some(class) => Ast::Class(class),
+                None => Ast::Literal(self.escaped_char(c)?),
+            },
+        })
+    }
+
+    /// The class for an escape like `\d`, if it's one.
+    fn escaped_class(&self, c: char) -> Option<CharClass> {
+        let ranges = match c.to_ascii_lowercase() {
+            'd' => DIGIT,
+            'w' => WORD,
+            's' => SPACE,
+            _ => return None,
+        };
+        Some(CharClass::new(ranges, c.is_ascii_uppercase()))
+    }
+
+    /// The literal character for an escape which isn't a class.
+    fn escaped_char(&self, c: char) -> Result<char, RegexError> {
+        match c {
+            'n' => Ok('\n'),
+            'r' => Ok('\r'),
+            't' => Ok('\t'),
+            c if c.is_ascii_alphanumeric() => {
+                Err(RegexError { message: format!("unknown escape `\\{c}`"), pos: self.pos - 2 })
+            },
+            c => Ok(c),
+        }
+    }
+
+    fn parse_class(&mut self) -> Result<Ast, RegexError> {
+        let start = self.pos - 1;
+        let mut class = CharClass::new(&[], self.eat('^'));
+
+        // A `]` straight after the opening bracket is a literal
+        let mut first = true;
+        loop {
+            let item_start = self.pos;
+            let Some(c) = self.peek() else {
+                self.pos = start;
+                return Err(self.error("unclosed character class"));
+            };
+            self.pos += 1;
+
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+
+            let lo = if c == '\\' {
+                let Some(escaped) = self.peek() else { continue };
+                self.pos += 1;
+
+                if let Some(escaped_class) = self.escaped_class(escaped) {
+                    if escaped_class.negated {
+                        class.excluded.push(escaped_class.ranges);
+                    } else {
+                        class.ranges.extend(escaped_class.ranges);
+                    }
+                    continue;
+                }
+                self.escaped_char(escaped)?
+            } else {
+                c
+            };
+
+            // A range, unless the `-` is last and so literal
+            if self.peek() == Some('-') && let Some(hi) = self.chars.get(self.pos + 1).copied() && hi != ']' {
+                self.pos += 2;
+                let hi = if hi == '\\' {
+                    let Some(escaped) = self.peek() else { continue };
+                    self.pos += 1;
+                    self.escaped_char(escaped)?
+                } else {
+                    hi
+                };
+
+                if hi < lo {
+                    self.pos = item_start;
+                    return Err(self.error(format!("range `{lo}-{hi}` is out of order")));
+                }
+                class.ranges.push((lo, hi));
+            } else {
+                class.ranges.push((lo, lo));
+            }
+        }
+
+        Ok(Ast::Class(class))
+    }
+
+    fn parse_quantifiers(&mut self, atom: Ast) -> Result<Ast, RegexError> {
+        let Some((min, max)) = self.parse_quantifier(&atom)? else {
+            return Ok(atom);
+        };
+        let greedy = !self.eat('?');
+
+        // A quantifier applied to a quantifier, like `a**`, is a mistake, so it needs a group
+        let start = self.pos;
+        if self.parse_quantifier(&atom)?.is_some() {
+            self.pos = start;
+            return Err(self.error("nothing to repeat, as the previous item is already repeated"));
+        }
+
+        Ok(Ast::Repeat { ast: Box::new(atom), min, max, greedy })
+    }
+
+    /// Parses a single quantifier for `atom`, without its laziness. Returns [None], without
+    /// moving, if there isn't one.
+    fn parse_quantifier(&mut self, atom: &Ast) -> Result<Option<(usize, Option<usize>)>, RegexError> {
+        let start = self.pos;
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => match self.parse_counts()? {
+                Some(counts) => counts,
+                None => return Ok(None), // Not a valid quantifier, so `{` is a literal
+            },
+            _ => return Ok(None),
+        };
+        if self.pos == start {
+            self.pos += 1;
+        }
+
+        if matches!(atom, Ast::Assert(_) | Ast::Empty) {
+            self.pos = start;
+            return Err(self.error("nothing to repeat"));
+        }
+        if let Some(max) = max && max < min {
+            self.pos = start;
+            return Err(self.error(format!("repetition `{{{min},{max}}}` is out of order")));
+        }
+        Ok(Some((min, max)))
+    }
+
+    /// Parses `{n}`, `{n,}` or `{n,m}`. Returns [None], without moving, if it isn't one of them.
+    fn parse_counts(&mut self) -> Result<Option<(usize, Option<usize>)>, RegexError> {
+        let start = self.pos;
+        let Some(len) = self.chars[start..].iter().position(|c| *c == '}') else {
+            return Ok(None);
+        };
+        let inside = self.chars[start + 1..start + len].iter().collect::<String>();
+
+        let parse = |s: &str| s.parse::<usize>().ok();
+        let counts = match inside.split_once(',') {
+            None => parse(&inside).map(|n| (n, Some(n))),
+            Some((min, "")) => parse(min).map(|n| (n, None)),
+            Some((min, max)) => parse(min).zip(parse(max)).map(|(min, max)| (min, Some(max))),
+        };
+
+        if counts.is_some() {
+            self.pos = start + len + 1;
+        }
+        Ok(counts)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Class(CharClass),
+    Literal(char),
+    Assert(Assertion),
+
+    /// Continue at both targets, preferring the first.
+    Split(usize, usize),
+
+    Jump(usize),
+
+    /// Record the current position in a capture slot.
+    Save(usize),
+
+    Match,
+}
+
+fn compile(ast: &Ast, program: &mut Vec<Inst>) {
+    match ast {
+        Ast::Empty => (),
+        Ast::Literal(c) => program.push(Inst::Literal(*c)),
+        Ast::Class(class) => program.push(Inst::Class(class.clone())),
+        Ast::Assert(assertion) => program.push(Inst::Assert(*assertion)),
+
+        Ast::Group(inner, index) => {
+            if let Some(index) = index {
+                program.push(Inst::Save(index * 2));
+            }
+            compile(inner, program);
+            if let Some(index) = index {
+                program.push(Inst::Save(index * 2 + 1));
+            }
+        },
+
+        Ast::Concat(items) => {
+            for item in items {
+                compile(item, program);
+            }
+        },
+
+        Ast::Alternate(alternatives) => {
+            // Each alternative but the last is preceded by a split to try the rest, and followed by
+            // a jump to the end
+            let mut jumps = vec![];
+            for (i, alternative) in alternatives.iter().enumerate() {
+                if i < alternatives.len() - 1 {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(alternative, program);
+                    jumps.push(program.len());
+                    program.push(Inst::Jump(0));
+                    program[split] = Inst::Split(split + 1, program.len());
+                } else {
+                    compile(alternative, program);
+                }
+            }
+
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jump(end);
+            }
+        },
+
+        Ast::Repeat { ast, min, max, greedy } => {
+            let split = |preferred: usize, other: usize| match greedy {
+                true => Inst::Split(preferred, other),
+                false => Inst::Split(other, preferred),
+            };
+
+            for _ in 0..*min {
+                compile(ast, program);
+            }
+
+            match max {
+                None => {
+                    let start = program.len();
+                    program.push(Inst::Jump(0)); // Placeholder for split
+                    compile(ast, program);
+                    program.push(Inst::Jump(start));
+                    program[start] = split(start + 1, program.len());
+                },
+
+                Some(max) => {
+                    let mut splits = vec![];
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Jump(0)); // Placeholder for split
+                        compile(ast, program);
+                    }
+
+                    let end = program.len();
+                    for s in splits {
+                        program[s] = split(s + 1, end);
+                    }
+                },
+            }
+        },
+    }
+}
+
+/// Runs a compiled program over some text, advancing every possible thread one character at a time.
+struct PikeVm<'a> {
+    regex: &'a Regex,
+    text: &'a [char],
+
+    /// For each instruction, the last step it was added to a thread list at, to avoid duplicates.
+    added_at: Vec<Option<usize>>,
+}
+
+struct Thread {
+    pc: usize,
+    slots: Vec<Option<usize>>,
+}
+
+impl<'a> PikeVm<'a> {
+    fn new(regex: &'a Regex, text: &'a [char]) -> Self {
+        PikeVm { regex, text, added_at: vec![None; regex.program.len()] }
+    }
+
+    fn run(&mut self, start: usize) -> Option<Captures> {
+        let regex = self.regex;
+        let slot_count = regex.groups * 2;
+        let mut current = vec![];
+        let mut next = vec![];
+        let mut matched: Option<Vec<Option<usize>>> = None;
+
+        for pos in start..=self.text.len() {
+            // Start a new attempt here, at lower priority than those already running, unless
+            // something has already matched further left
+            if matched.is_none() {
+                self.add_thread(&mut current, 0, pos, vec![None; slot_count]);
+            }
+            // Once nothing more can start, there's nothing left to run. Before that, an attempt can
+            // die straight away on an assertion, which says nothing about later positions.
+            if current.is_empty() && matched.is_some() {
+                break;
+            }
+
+            for thread in current.drain(..) {
+                match &regex.program[thread.pc] {
+                    Inst::Match => {
+                        // Lower priority threads can't win, so drop them
+                        matched = Some(thread.slots);
+                        break;
+                    },
+                    Inst::Literal(c) => {
+                        if self.text.get(pos) == Some(c) {
+                            self.add_thread(&mut next, thread.pc + 1, pos + 1, thread.slots);
+                        }
+                    },
+                    Inst::Class(class) => {
+                        if let Some(c) = self.text.get(pos) && class.matches(*c) {
+                            self.add_thread(&mut next, thread.pc + 1, pos + 1, thread.slots);
+                        }
+                    },
+                    _ => unreachable!("control instructions are followed when adding threads"),
+                }
+            }
+
+            std::mem::swap(&mut current, &mut next);
+        }
+
+        matched.map(|slots| {
+            (0..regex.groups)
+                .map(|group| slots[group * 2].zip(slots[group * 2 + 1]))
+                .collect()
+        })
+    }
+
+    /// Adds a thread, following control instructions straight away so that only threads waiting
+    /// on a character (or matching) end up in the list.
+    fn add_thread(&mut self, list: &mut Vec<Thread>, pc: usize, pos: usize, mut slots: Vec<Option<usize>>) {
+        if self.added_at[pc] == Some(pos) {
+            return;
+        }
+        self.added_at[pc] = Some(pos);
+
+        let regex = self.regex;
+        match &regex.program[pc] {
+            Inst::Jump(target) => self.add_thread(list, *target, pos, slots),
+            Inst::Split(preferred, other) => {
+                let (preferred, other) = (*preferred, *other);
+                self.add_thread(list, preferred, pos, slots.clone());
+                self.add_thread(list, other, pos, slots);
+            },
+            Inst::Save(slot) => {
+                slots[*slot] = Some(pos);
+                self.add_thread(list, pc + 1, pos, slots);
+            },
+            Inst::Assert(assertion) => {
+                if self.assertion_holds(*assertion, pos) {
+                    self.add_thread(list, pc + 1, pos, slots);
+                }
+            },
+            Inst::Literal(_) | Inst::Class(_) | Inst::Match => list.push(Thread { pc, slots }),
+        }
+    }
+
+    fn assertion_holds(&self, assertion: Assertion, pos: usize) -> bool {
+        let word_before = pos > 0 && is_word_char(self.text[pos - 1]);
+        let word_after = self.text.get(pos).is_some_and(|c| is_word_char(*c));
+
+        match assertion {
+            Assertion::Start => pos == 0,
+            Assertion::End => pos == self.text.len(),
+            Assertion::WordBoundary => word_before != word_after,
+            Assertion::NotWordBoundary => word_before == word_after,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    fn find_all(pattern: &str, text: &str) -> Vec<(usize, usize)> {
+        Regex::new(pattern).unwrap().find_all(&chars(text)).iter().map(|c| c[0].unwrap()).collect()
+    }
+
+    fn replace_all(pattern: &str, text: &str, replacement: &str) -> String {
+        Regex::new(pattern).unwrap().replace_all(&chars(text), &chars(replacement)).into_iter().collect()
+    }
+
+    fn error(pattern: &str) -> (String, usize) {
+        let error = Regex::new(pattern).unwrap_err();
+        (error.message, error.pos)
+    }
+
+    #[test]
+    fn nested_quantifiers_are_errors() {
+        let message = "nothing to repeat, as the previous item is already repeated".to_owned();
+        assert_eq!(error("a**"), (message.clone(), 2));
+        assert_eq!(error("ab+?*"), (message.clone(), 4));
+        assert_eq!(error("x{2}{3}"), (message, 4));
+
+        // Laziness and groups are fine, as is a `{` which isn't a quantifier
+        assert_eq!(find_all("a+?", "aaa"), [(0, 1), (1, 2), (2, 3)]);
+        assert_eq!(find_all("(?:a*)*b", "aab"), [(0, 3)]);
+        assert_eq!(find_all("a*{x}", "aa{x}"), [(0, 5)]);
+    }
+
+    #[test]
+    fn error_positions() {
+        assert_eq!(error("*a"), ("nothing to repeat before `*`".to_owned(), 0));
+        assert_eq!(error("^*"), ("nothing to repeat".to_owned(), 1));
+        assert_eq!(error("a(b"), ("unclosed group".to_owned(), 1));
+        assert_eq!(error("ab)"), ("unmatched `)`".to_owned(), 2));
+        assert_eq!(error("x[a-"), ("unclosed character class".to_owned(), 1));
+        assert_eq!(error("[z-a]"), ("range `z-a` is out of order".to_owned(), 1));
+        assert_eq!(error("a{3,1}"), ("repetition `{3,1}` is out of order".to_owned(), 1));
+        assert_eq!(error(r"a\q"), (r"unknown escape `\q`".to_owned(), 1));
+        assert_eq!(error(r"ab\"), (r"pattern ends with `\`".to_owned(), 3));
+    }
+
+    #[test]
+    fn empty_matches_step_by_character() {
+        assert_eq!(find_all("x*", "ab"), [(0, 0), (1, 1), (2, 2)]);
+        assert_eq!(find_all("a*", "baa"), [(0, 0), (1, 3), (3, 3)]);
+        assert_eq!(find_all(r"\b", "ab cd"), [(0, 0), (2, 2), (3, 3), (5, 5)]);
+        assert_eq!(find_all(r"\bx", "axb x"), [(4, 5)]);
+
+        assert_eq!(replace_all("x*", "é😀", "-"), "-é-😀-");
+        assert_eq!(replace_all("", "", "-"), "-");
+    }
+
+    #[test]
+    fn replacement_escapes() {
+        assert_eq!(replace_all(r"(\w+)=(\d+)", "a=1, é_b=22", "$2:$1"), "1:a, é22:_b");
+        assert_eq!(replace_all("a", "cat", "[$0]"), "c[a]t");
+        assert_eq!(replace_all("a", "cat", "$$0"), "c$0t");
+
+        // Groups which didn't take part are empty, and other `$`s are literal
+        assert_eq!(replace_all("(x)|y", "xy", "<$1>"), "<x><>");
+        assert_eq!(replace_all("a", "a", "$2$"), "$2$");
+    }
+
+    #[test]
+    fn captures() {
+        let regex = Regex::new("(a)(?:(b)|c)(d)?").unwrap();
+        assert_eq!(regex.group_count(), 4);
+        assert_eq!(regex.find_at(&chars("xacd"), 0), Some(vec![Some((1, 4)), Some((1, 2)), None, Some((3, 4))]));
+        assert_eq!(regex.find_at(&chars("ab"), 1), None);
+    }
+}
@@ -0,0 +1,44 @@
+use std::{error::Error, io::{BufRead, Write}};
+
+use crate::json::Json;
+
+// JSON-RPC messages framed with a `Content-Length` header, as used by both the Language Server
+// Protocol and the Debug Adapter Protocol.
+
+/// Reads the next message. Returns `None` once the input has ended.
+pub fn read_message(input: &mut impl BufRead) -> Result<Option<Json>, Box<dyn Error>> {
+    let mut content_length = None;
+
+    // Headers, terminated by an empty line
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            // Tolerate stray blank lines between messages
+            if content_length.is_some() {
+                break;
+            }
+            continue;
+        }
+
+        if let Some((name, value)) = line.split_once(':') && name.eq_ignore_ascii_case("Content-Length") {
+            content_length = Some(value.trim().parse::<usize>()?);
+        }
+    }
+
+    let mut body = vec![0; content_length.unwrap()];
+    input.read_exact(&mut body)?;
+    Ok(Some(Json::parse(&String::from_utf8(body)?)?))
+}
+
+/// Writes a message, and flushes it so that the other end sees it straight away.
+pub fn write_message(output: &mut impl Write, message: &Json) -> Result<(), Box<dyn Error>> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    output.flush()?;
+    Ok(())
+}
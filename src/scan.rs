@@ -0,0 +1,225 @@
+//! Scanf-style templates for pulling typed values out of input, used by `scan` and `scan-all`.
+//!
+//! A template is literal text with placeholders:
+//!   - `{int}` is an integer, optionally negative
+//!   - `{char}` is any single character
+//!   - `{word}` is a run of non-whitespace characters
+//!   - `{str}` is any text, as little as possible for the rest of the template to match
+//!
+//! `{{` and `}}` are literal braces. Everything else, including whitespace, must match exactly.
+//! Templates are translated into a [Regex], so they match the same way a regex would.
+
+use std::{error::Error, fmt::Display};
+
+use crate::{eval::Value, regex::{Captures, Regex}};
+
+/// A compiled template.
+#[derive(Debug, Clone)]
+pub struct Template {
+    template: String,
+    placeholders: Vec<Placeholder>,
+
+    /// Matches the template anywhere in some text.
+    regex: Regex,
+
+    /// Matches only the whole text.
+    anchored: Regex,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    Int,
+    Char,
+    Word,
+    Str,
+}
+
+impl Placeholder {
+    fn regex(self) -> &'static str {
+        match self {
+            Placeholder::Int => "(-?\\d+)",
+            Placeholder::Char => "([\\s\\S])",
+            Placeholder::Word => "(\\S+)",
+            Placeholder::Str => "([\\s\\S]*?)",
+        }
+    }
+}
+
+impl Template {
+    /// Compiles a template.
+    pub fn new(template: &str) -> Result<Template, TemplateError> {
+        let chars = template.chars().collect::<Vec<_>>();
+        let mut placeholders = vec![];
+        let mut pattern = String::new();
+
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '{' if chars.get(i + 1) == Some(&'{') => {
+                    pattern.push_str("\\{");
+                    i += 2;
+                },
+                '}' if chars.get(i + 1) == Some(&'}') => {
+                    pattern.push_str("\\}");
+                    i += 2;
+                },
+
+                '{' => {
+                    let Some(len) = chars[i..].iter().position(|c| *c == '}') else {
+                        return Err(TemplateError { message: "unclosed placeholder".to_owned(), pos: i });
+                    };
+                    let name = chars[i + 1..i + len].iter().collect::<String>();
+                    let placeholder = match name.as_str() {
+                        "int" => Placeholder::Int,
+                        "char" => Placeholder::Char,
+                        "word" => Placeholder::Word,
+                        "str" => Placeholder::Str,
+                        _ => return Err(TemplateError { message: format!("unknown placeholder `{{{name}}}`"), pos: i }),
+                    };
+
+                    placeholders.push(placeholder);
+                    pattern.push_str(placeholder.regex());
+                    i += len + 1;
+                },
+                '}' => return Err(TemplateError { message: "unmatched `}`; use `}}` for a literal brace".to_owned(), pos: i }),
+
+                c => {
+                    if !c.is_alphanumeric() && !c.is_whitespace() {
+                        pattern.push('\\');
+                    }
+                    pattern.push(c);
+                    i += 1;
+                },
+            }
+        }
+
+        let regex = Regex::new(&pattern).expect("template produced invalid regex");
+        let anchored = Regex::new(&format!("^(?:{pattern})$")).expect("template produced invalid regex");
+        Ok(Template { template: template.to_owned(), placeholders, regex, anchored })
+    }
+
+    /// The template this was compiled from.
+    pub fn template(&self) -> &str {
+        &self.template
+    }
+
+    /// Matches the whole of some text, returning the value of each placeholder.
+    pub fn scan(&self, text: &[char]) -> Option<Result<Vec<Value>, TemplateError>> {
+        self.anchored.find_at(text, 0).map(|captures| self.values(text, captures))
+    }
+
+    /// Finds every non-overlapping match in some text, returning the values for each.
+    pub fn scan_all(&self, text: &[char]) -> Result<Vec<Vec<Value>>, TemplateError> {
+        self.regex.find_all(text)
+            .into_iter()
+            .map(|captures| self.values(text, captures))
+            .collect()
+    }
+
+    fn values(&self, text: &[char], captures: Captures) -> Result<Vec<Value>, TemplateError> {
+        self.placeholders.iter()
+            .zip(captures.into_iter().skip(1))
+            .map(|(placeholder, group)| {
+                let (start, end) = group.expect("placeholder groups always take part in a match");
+                let matched = &text[start..end];
+                Ok(match placeholder {
+                    Placeholder::Int => {
+                        let s = matched.iter().collect::<String>();
+                        let i = s.parse().map_err(|_| TemplateError { message: format!("integer out of range: `{s}`"), pos: start })?;
+                        Value::Integer(i)
+                    },
+                    Placeholder::Char => Value::Char(matched[0]),
+                    Placeholder::Word | Placeholder::Str => Value::Array(matched.iter().copied().map(Value::Char).collect()),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Error in a template, or converting a match, with the character offset it happened at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateError {
+    pub message: String,
+    pub pos: usize,
+}
+
+impl Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (at position {})", self.message, self.pos)
+    }
+}
+impl Error for TemplateError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(s: &str) -> Vec<char> {
+        s.chars().collect()
+    }
+
+    fn string(s: &str) -> Value {
+        Value::Array(s.chars().map(Value::Char).collect())
+    }
+
+    fn scan(template: &str, text: &str) -> Option<Result<Vec<Value>, TemplateError>> {
+        Template::new(template).unwrap().scan(&chars(text))
+    }
+
+    fn error(template: &str) -> TemplateError {
+        Template::new(template).unwrap_err()
+    }
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(
+            scan("{int},{int} -> {char}: {word} {str}!", "-3,14 -> é: a-b c d!"),
+            Some(Ok(vec![Value::Integer(-3), Value::Integer(14), Value::Char('é'), string("a-b"), string("c d")])),
+        );
+
+        // `{str}` takes as little as possible, but can be empty
+        assert_eq!(scan("{str}x{str}", "axbxc"), Some(Ok(vec![string("a"), string("bxc")])));
+        assert_eq!(scan("[{str}]", "[]"), Some(Ok(vec![string("")])));
+    }
+
+    #[test]
+    fn literal_text_is_matched_exactly() {
+        assert_eq!(scan("{{{int}}}", "{7}"), Some(Ok(vec![Value::Integer(7)])));
+        assert_eq!(scan("a.b*({int})", "a.b*(1)"), Some(Ok(vec![Value::Integer(1)])));
+        assert_eq!(scan("a.b", "axb"), None);
+        assert_eq!(scan("a  b", "a b"), None);
+    }
+
+    #[test]
+    fn scan_matches_the_whole_text() {
+        assert_eq!(scan("{int}", "12 "), None);
+        assert_eq!(scan("x{int}", "yx12"), None);
+        assert_eq!(scan("{int}", "-"), None);
+    }
+
+    #[test]
+    fn scan_all_finds_every_match() {
+        let template = Template::new("mul({int},{int})").unwrap();
+        assert_eq!(
+            template.scan_all(&chars("mul(1,2)mul(3, 4)xmul(-5,60)")),
+            Ok(vec![vec![Value::Integer(1), Value::Integer(2)], vec![Value::Integer(-5), Value::Integer(60)]]),
+        );
+        assert_eq!(template.scan_all(&chars("")), Ok(vec![]));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("a{int"), TemplateError { message: "unclosed placeholder".to_owned(), pos: 1 });
+        assert_eq!(error("é{float}"), TemplateError { message: "unknown placeholder `{float}`".to_owned(), pos: 1 });
+        assert_eq!(error("{}"), TemplateError { message: "unknown placeholder `{}`".to_owned(), pos: 0 });
+        assert_eq!(
+            error("{int}} {int}"),
+            TemplateError { message: "unmatched `}`; use `}}` for a literal brace".to_owned(), pos: 5 },
+        );
+
+        assert_eq!(
+            scan("é {int}", "é 99999999999999999999"),
+            Some(Err(TemplateError { message: "integer out of range: `99999999999999999999`".to_owned(), pos: 2 })),
+        );
+    }
+}
@@ -0,0 +1,215 @@
+//! Graph search over states of any [Value], used by `bfs`, `dfs`, `dijkstra` and `astar`.
+//!
+//! The graph is implicit: callbacks give the neighbours of each state and say whether a state is
+//! the goal, so only the states actually reached are ever built.
+
+use std::{cmp::Reverse, collections::{BinaryHeap, HashMap, VecDeque}};
+
+use crate::eval::{ExecutionError, Value};
+
+/// A path found by a search.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Path {
+    /// Total cost of the path, which is its number of steps if edges aren't weighted.
+    pub cost: isize,
+
+    /// Every state from the start to the goal, inclusive.
+    pub states: Vec<Value>,
+}
+
+/// Assigns each distinct state an index, and remembers how it was reached.
+#[derive(Default)]
+struct States {
+    indices: HashMap<Value, usize>,
+    values: Vec<Value>,
+    parents: Vec<Option<usize>>,
+}
+
+impl States {
+    /// The index of a state, and whether it's new.
+    fn intern(&mut self, state: Value) -> (usize, bool) {
+        if let Some(index) = self.indices.get(&state) {
+            return (*index, false);
+        }
+
+        let index = self.values.len();
+        self.indices.insert(state.clone(), index);
+        self.values.push(state);
+        self.parents.push(None);
+        (index, true)
+    }
+
+    fn path(&self, mut index: usize, cost: isize) -> Path {
+        let mut states = vec![self.values[index].clone()];
+        while let Some(parent) = self.parents[index] {
+            states.push(self.values[parent].clone());
+            index = parent;
+        }
+        states.reverse();
+        Path { cost, states }
+    }
+}
+
+pub type Neighbours<'a> = dyn FnMut(&Value) -> Result<Vec<Value>, ExecutionError> + 'a;
+pub type WeightedNeighbours<'a> = dyn FnMut(&Value) -> Result<Vec<(Value, isize)>, ExecutionError> + 'a;
+pub type Predicate<'a> = dyn FnMut(&Value) -> Result<bool, ExecutionError> + 'a;
+pub type Heuristic<'a> = dyn FnMut(&Value) -> Result<isize, ExecutionError> + 'a;
+
+/// Breadth-first search, finding a path with the fewest steps.
+pub fn bfs(start: Value, neighbours: &mut Neighbours, is_goal: &mut Predicate) -> Result<Option<Path>, ExecutionError> {
+    let mut states = States::default();
+    let (start, _) = states.intern(start);
+    let mut distances = vec![0];
+    let mut queue = VecDeque::from([start]);
+
+    while let Some(index) = queue.pop_front() {
+        let state = states.values[index].clone();
+        if is_goal(&state)? {
+            return Ok(Some(states.path(index, distances[index])));
+        }
+
+        for neighbour in neighbours(&state)? {
+            let (neighbour, is_new) = states.intern(neighbour);
+            if is_new {
+                states.parents[neighbour] = Some(index);
+                distances.push(distances[index] + 1);
+                queue.push_back(neighbour);
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Depth-first search, finding any path. Neighbours are explored in the order they're given.
+pub fn dfs(start: Value, neighbours: &mut Neighbours, is_goal: &mut Predicate) -> Result<Option<Path>, ExecutionError> {
+    let mut states = States::default();
+    let (start, _) = states.intern(start);
+    let mut visited = vec![];
+    let mut depths = vec![];
+
+    // Each entry is a state, and the state it was reached from
+    let mut stack = vec![(start, None)];
+    while let Some((index, parent)) = stack.pop() {
+        if visited.get(index).copied().unwrap_or(false) {
+            continue;
+        }
+        if visited.len() <= index {
+            visited.resize(index + 1, false);
+            depths.resize(index + 1, 0);
+        }
+        visited[index] = true;
+        states.parents[index] = parent;
+        depths[index] = parent.map_or(0, |p| depths[p] + 1);
+
+        let state = states.values[index].clone();
+        if is_goal(&state)? {
+            return Ok(Some(states.path(index, depths[index])));
+        }
+
+        // Push in reverse, so the first neighbour is explored first
+        let mut next = neighbours(&state)?.into_iter()
+            .map(|neighbour| states.intern(neighbour).0)
+            .filter(|n| !visited.get(*n).copied().unwrap_or(false))
+            .collect::<Vec<_>>();
+        next.reverse();
+        stack.extend(next.into_iter().map(|n| (n, Some(index))));
+    }
+
+    Ok(None)
+}
+
+/// Dijkstra's algorithm, finding a path with the lowest total cost. Costs can't be negative.
+pub fn dijkstra(start: Value, neighbours: &mut WeightedNeighbours, is_goal: &mut Predicate) -> Result<Option<Path>, ExecutionError> {
+    astar(start, neighbours, is_goal, &mut |_| Ok(0))
+}
+
+/// A* search, finding a path with the lowest total cost, guided by a heuristic estimate of the cost
+/// remaining from each state. The heuristic must never overestimate, or the path found might not
+/// be the cheapest.
+///
+/// States are explored again if a cheaper path to them is found later, so the heuristic doesn't
+/// also need to be consistent, though it's faster if it is.
+pub fn astar(start: Value, neighbours: &mut WeightedNeighbours, is_goal: &mut Predicate, heuristic: &mut Heuristic) -> Result<Option<Path>, ExecutionError> {
+    let mut states = States::default();
+    let (start, _) = states.intern(start);
+
+    // The cheapest cost found so far to reach each state
+    let mut costs = vec![Some(0)];
+
+    // Ordered by estimated total cost, then by cost so far
+    let mut queue = BinaryHeap::new();
+    queue.push(Reverse((heuristic(&states.values[start])?, 0, start)));
+
+    while let Some(Reverse((_, cost, index))) = queue.pop() {
+        // Skip states which have been reached more cheaply since this entry was queued
+        if costs[index].is_some_and(|c| cost > c) {
+            continue;
+        }
+
+        let state = states.values[index].clone();
+        if is_goal(&state)? {
+            return Ok(Some(states.path(index, cost)));
+        }
+
+        for (neighbour, step) in neighbours(&state)? {
+            if step < 0 {
+                return Err(ExecutionError::new(format!("edge costs can't be negative, but got {step}")));
+            }
+
+            let (neighbour, is_new) = states.intern(neighbour);
+            if is_new {
+                costs.push(None);
+            }
+
+            let new_cost = cost + step;
+            if costs[neighbour].is_none_or(|c| new_cost < c) {
+                costs[neighbour] = Some(new_cost);
+                states.parents[neighbour] = Some(index);
+                let estimate = new_cost + heuristic(&states.values[neighbour])?;
+                queue.push(Reverse((estimate, new_cost, neighbour)));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn astar_finds_cheapest_path_with_inconsistent_heuristic() {
+        // 0 -> 1 -> 2 -> 3 costs 5, but 2 is first reached directly from 0 for 3, and the heuristic
+        // makes 1 look expensive, so 2 is explored before its cheaper path through 1 is found.
+        // The heuristic never overestimates, but isn't consistent: h(0) = 0 < 1 + h(1) = 5
+        let edges: &[(isize, isize, isize)] = &[(0, 1, 1), (1, 2, 1), (0, 2, 3), (2, 3, 3)];
+        let path = astar(
+            Value::Integer(0),
+            &mut |state| Ok(edges.iter()
+                .filter(|(from, _, _)| Value::Integer(*from) == *state)
+                .map(|(_, to, cost)| (Value::Integer(*to), *cost))
+                .collect()),
+            &mut |state| Ok(*state == Value::Integer(3)),
+            &mut |state| Ok(if *state == Value::Integer(1) { 4 } else { 0 }),
+        ).unwrap().unwrap();
+
+        assert_eq!(path.cost, 5);
+        assert_eq!(path.states, [0, 1, 2, 3].map(Value::Integer));
+    }
+
+    #[test]
+    fn dijkstra_finds_cheapest_path() {
+        let path = dijkstra(
+            Value::Integer(0),
+            &mut |state| {
+                let n = state.clone().into_integer()?;
+                Ok(vec![(Value::Integer(n + 1), 1), (Value::Integer(n + 2), 3)])
+            },
+            &mut |state| Ok(*state == Value::Integer(4)),
+        ).unwrap().unwrap();
+
+        assert_eq!(path.cost, 4);
+    }
+}
@@ -1,11 +1,12 @@
-use std::{error::Error, rc::Rc};
+use std::error::Error;
 
-use crate::loc::{Loc, LocSource};
+use crate::{loc::{Loc, LocSource}, parser::SyntaxError};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Atom {
     LiteralInteger(isize),
     LiteralChar(char),
+    LiteralString(String),
     Action(String),
     Binding(String),
 }
@@ -15,6 +16,12 @@ pub enum TokenKind {
     Atom(Atom),
     LBrace,
     RBrace,
+    LParen,
+    RParen,
+
+    // Trivia - only produced by `tokenize_with_trivia`
+    Comment(String),
+    Whitespace(String),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -27,13 +34,31 @@ impl Token {
     pub fn new(kind: TokenKind, loc: Loc) -> Self {
         Self { kind, loc }
     }
+
+    /// Whether this token is a comment or whitespace.
+    pub fn is_trivia(&self) -> bool {
+        matches!(self.kind, TokenKind::Comment(_) | TokenKind::Whitespace(_))
+    }
 }
 
 pub fn tokenize(source: &LocSource) -> Result<Vec<Token>, Box<dyn Error>> {    
+    let mut tokens = tokenize_with_trivia(source)?;
+    tokens.retain(|t| !t.is_trivia());
+    Ok(tokens)
+}
+
+/// Like [tokenize], but also keeps comments and whitespace as trivia tokens, so that the original
+/// source can be reconstructed exactly.
+pub fn tokenize_with_trivia(source: &LocSource) -> Result<Vec<Token>, Box<dyn Error>> {
     split_tokens(source)
-        .map(|(token, loc)| (tokenize_one(&token), loc))
-        .map(|(kind, loc)|
-            kind.map(|kind| Token::new(kind, loc)))
+        .map(|(piece, loc)| {
+            let kind = match piece {
+                Piece::Token(token) => tokenize_one(&token).map_err(|e| SyntaxError::new(e.to_string(), &loc))?,
+                Piece::Comment(comment) => TokenKind::Comment(comment),
+                Piece::Whitespace(whitespace) => TokenKind::Whitespace(whitespace),
+            };
+            Ok(Token::new(kind, loc))
+        })
         .collect()
 }
 
@@ -41,9 +66,9 @@ pub fn tokenize(source: &LocSource) -> Result<Vec<Token>, Box<dyn Error>> {
 fn tokenize_one(token: &str) -> Result<TokenKind, Box<dyn Error>> {
     if let Ok(num) = token.parse() {
         Ok(TokenKind::Atom(Atom::LiteralInteger(num)))
-    } else if token.chars().all(|c| is_valid_identifier_char(c)) {
+    } else if token.chars().all(is_valid_identifier_char) {
         Ok(TokenKind::Atom(Atom::Action(token.to_owned())))
-    } else if token.starts_with('$') && token.chars().skip(1).all(|c| is_valid_identifier_char(c)) {
+    } else if token.starts_with('$') && token.chars().skip(1).all(is_valid_identifier_char) {
         Ok(TokenKind::Atom(Atom::Binding(token.to_owned())))
     } else if token.starts_with('\'') && token.ends_with('\'') {
         let chars = token.chars().collect::<Vec<_>>();
@@ -53,50 +78,99 @@ fn tokenize_one(token: &str) -> Result<TokenKind, Box<dyn Error>> {
 
         let c = chars[1];
         Ok(TokenKind::Atom(Atom::LiteralChar(c)))
+    } else if let Some(contents) = token.strip_prefix('"') {
+        Ok(TokenKind::Atom(Atom::LiteralString(unescape_string(contents)?)))
     } else if token == "{" {
         Ok(TokenKind::LBrace)
     } else if token == "}" {
         Ok(TokenKind::RBrace)
+    } else if token == "(" {
+        Ok(TokenKind::LParen)
+    } else if token == ")" {
+        Ok(TokenKind::RParen)
     } else {
         Err(format!("unknown token `{token}`").into())
     }
 }
 
-/// Splits input source into token strings with associated [Loc]s, dropping any comments encountered
-/// while doing so.
-fn split_tokens(source: &LocSource) -> impl Iterator<Item = (String, Loc)> {
-    let chars = source.contents
-        .chars()
-        .chain([' '].into_iter()) // Force a final buffer flush by adding some whitespace on the end
-        .enumerate();
+/// Converts the contents of a string literal, after the opening quote, to the string it represents.
+fn unescape_string(contents: &str) -> Result<String, Box<dyn Error>> {
+    let mut s = String::new();
+    let mut chars = contents.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if chars.as_str().is_empty() => return Ok(s),
+            '"' => return Err("string literal must be followed by whitespace".into()),
+            '\\' => s.push(match chars.next() {
+                Some('"') => '"',
+                Some('\\') => '\\',
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some(c) => return Err(format!("invalid escape sequence in string literal: `\\{c}`").into()),
+                None => break,
+            }),
+            c => s.push(c),
+        }
+    }
+
+    Err("unterminated string literal".into())
+}
+
+/// A piece of source code found by [split_tokens].
+enum Piece {
+    Token(String),
+    Comment(String),
+    Whitespace(String),
+}
+
+/// Splits input source into token strings, comments and runs of whitespace, with associated [Loc]s.
+fn split_tokens(source: &LocSource) -> impl Iterator<Item = (Piece, Loc)> {
+    let chars = source.contents.chars().collect::<Vec<_>>();
 
-    let mut buffer: Option<(String, usize)> = None;
     let mut items = vec![];
-    for (i, char) in chars {
-        if buffer.clone().map(|(s, _)| s).as_deref() == Some("//") {
-            // Magical special case! If the buffer contains `//`, drop all tokens until we encounter
-            // a newline to terminate the comment
-            if char == '\n' {
-                // Reset buffer
-                buffer = None;
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+
+        // Find where this piece ends, and what it is
+        let piece: fn(String) -> Piece;
+        if chars[i].is_whitespace() {
+            while i < chars.len() && chars[i].is_whitespace() {
+                i += 1;
             }
-        } else if char.is_whitespace() {
-            // If there is a buffer, 'finalize' it into the list of items
-            // (Otherwise, we can harmlessly skip the consecutive whitespace)
-            if let Some((contents, start)) = buffer {
-                let loc = Loc::new(source.clone(), start, contents.len());
-                items.push((contents, loc));
+            piece = Piece::Whitespace;
+        } else if chars[i..].starts_with(&['/', '/']) {
+            // A token starting with `//` begins a comment, which runs until the end of the line
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
             }
-            buffer = None;
+            piece = Piece::Comment;
+        } else if chars[i] == '"' {
+            // String literals can contain whitespace, so run to the closing quote before looking
+            // for the end of the token
+            i += 1;
+            while i < chars.len() && chars[i] != '"' {
+                if chars[i] == '\\' {
+                    i += 1;
+                }
+                i += 1;
+            }
+            i = (i + 1).min(chars.len());
+            while i < chars.len() && !chars[i].is_whitespace() {
+                i += 1;
+            }
+            piece = Piece::Token;
         } else {
-            buffer = match buffer {
-                // If there's no buffer, start filling one up
-                None => Some((char.to_string(), i)),
-
-                // If there's already one, add to it
-                Some((contents, start)) => Some((contents + &char.to_string(), start))
+            while i < chars.len() && !chars[i].is_whitespace() {
+                i += 1;
             }
+            piece = Piece::Token;
         }
+
+        let contents = chars[start..i].iter().collect::<String>();
+        let loc = Loc::new(source.clone(), start, i - start);
+        items.push((piece(contents), loc));
     }
 
     items.into_iter()
@@ -0,0 +1,241 @@
+use std::{error::Error, io::Write, ops::RangeInclusive};
+
+use crate::{eval::{ExecutionError, Hook, Interpreter, Value}, parser::Node};
+
+/// How many values from the top of the stack to show before and after each atom.
+const STACK_ITEMS: usize = 3;
+
+/// How many characters of each stack value to show before cutting it short.
+const SUMMARY_LENGTH: usize = 30;
+
+/// A range of lines in a source file, written like `code.stk:10-20`. See
+/// [crate::loc::LocSource::matches_file] for how the file is matched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineRange {
+    pub file: String,
+    pub lines: RangeInclusive<usize>,
+}
+
+impl LineRange {
+    /// Parses a range written as `file:from-to`, `file:line`, or either of those without the file
+    /// to refer to `default_file`.
+    pub fn parse(spec: &str, default_file: &str) -> Result<LineRange, Box<dyn Error>> {
+        let (file, lines) = spec.rsplit_once(':').unwrap_or((default_file, spec));
+        let invalid = || format!("invalid line range `{spec}`, expected something like `code.stk:10-20`");
+
+        let (from, to) = lines.split_once('-').unwrap_or((lines, lines));
+        let from = from.parse().map_err(|_| invalid())?;
+        let to = to.parse().map_err(|_| invalid())?;
+
+        Ok(LineRange { file: file.to_owned(), lines: from..=to })
+    }
+
+    fn contains(&self, node: &Node) -> bool {
+        let (line, _) = node.loc.line_col();
+        self.lines.contains(&line) && node.loc.source.matches_file(&self.file)
+    }
+}
+
+/// Restricts which atoms are traced. An atom is traced if it's within any of the `actions` (or
+/// there aren't any), and also within any of the `ranges` (or there aren't any).
+#[derive(Debug, Clone, Default)]
+pub struct TraceFilter {
+    /// Names of user actions whose definitions to trace, including any blocks written within them.
+    /// Actions which they call aren't traced, unless also named here.
+    pub actions: Vec<String>,
+
+    pub ranges: Vec<LineRange>,
+}
+
+impl TraceFilter {
+    fn includes(&self, interpreter: &Interpreter, node: &Node) -> bool {
+        let in_action = self.actions.is_empty() || self.actions.iter().any(|name|
+            interpreter.user_action(name).is_some_and(|body|
+                body.loc.source == node.loc.source
+                && body.loc.range().contains(&node.loc.pos)
+            )
+        );
+        let in_range = self.ranges.is_empty() || self.ranges.iter().any(|range| range.contains(node));
+
+        in_action && in_range
+    }
+}
+
+/// Writes a line for each atom executed, with where it is, which action or block it's in, and the
+/// top of the stack before and after it.
+///
+/// Atoms which execute others (like calls to user actions, or `map`) are written once before and
+/// once after, with anything they traced in between:
+///
+/// ```text
+/// code.stk:5:3 in top-level: inc  [5] ...
+/// code.stk:1:14 in inc: 1  [5] -> [5, 1]
+/// code.stk:1:16 in inc: +  [5, 1] -> [6]
+/// code.stk:5:3 in top-level: inc finished -> [6]
+/// ```
+pub struct Tracer {
+    output: Box<dyn Write>,
+    filter: TraceFilter,
+
+    /// Whether each atom currently executing is being traced, innermost last.
+    traced: Vec<bool>,
+
+    /// The line for the innermost traced atom, if nothing else has been traced since it started.
+    /// Completed with the stack after the atom once it finishes, or with `...` if something else
+    /// gets traced first.
+    pending: Option<String>,
+}
+
+impl Tracer {
+    pub fn new(output: Box<dyn Write>, filter: TraceFilter) -> Self {
+        Tracer { output, filter, traced: vec![], pending: None }
+    }
+
+    fn describe(interpreter: &Interpreter, node: &Node) -> String {
+        let (line, col) = node.loc.line_col();
+        let frame = interpreter.call_stack().last()
+            .map(|f| f.to_string())
+            .unwrap_or("top-level".to_owned());
+        format!("{}:{line}:{col} in {frame}: {}", node.loc.source.name, node.loc.contents())
+    }
+
+    fn flush_pending(&mut self) -> Result<(), ExecutionError> {
+        if let Some(line) = self.pending.take() {
+            writeln!(self.output, "{line} ...").map_err(|e| ExecutionError::new(e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+impl Hook for Tracer {
+    fn before_atom(&mut self, interpreter: &Interpreter, node: &Node) -> Result<(), ExecutionError> {
+        let traced = self.filter.includes(interpreter, node);
+        self.traced.push(traced);
+
+        if traced {
+            self.flush_pending()?;
+            self.pending = Some(format!(
+                "{}  {}", Self::describe(interpreter, node), stack_summary(interpreter.stack()),
+            ));
+        }
+        Ok(())
+    }
+
+    fn after_atom(&mut self, interpreter: &Interpreter, node: &Node) -> Result<(), ExecutionError> {
+        if !self.traced.pop().unwrap_or(false) {
+            return Ok(())
+        }
+
+        let after = stack_summary(interpreter.stack());
+        let result = match self.pending.take() {
+            Some(line) => writeln!(self.output, "{line} -> {after}"),
+            None => writeln!(self.output, "{} finished -> {after}", Self::describe(interpreter, node)),
+        };
+        result.map_err(|e| ExecutionError::new(e.to_string()))
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        // If execution stopped with an error, the atom which caused it is still pending
+        let _ = self.flush_pending();
+        let _ = self.output.flush();
+    }
+}
+
+/// The top few items of the stack, with the top last, like `[..., 1, [2, 3], 4]`.
+fn stack_summary(stack: &[Value]) -> String {
+    let shown = &stack[stack.len().saturating_sub(STACK_ITEMS)..];
+    let mut items = shown.iter().map(|v| v.summary(SUMMARY_LENGTH)).collect::<Vec<_>>();
+    if shown.len() < stack.len() {
+        items.insert(0, "...".to_owned());
+    }
+    format!("[{}]", items.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::code_to_node;
+
+    use super::*;
+
+    /// Collects what's written to it, and can still be read after being given away.
+    #[derive(Clone, Default)]
+    struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Runs code with a tracer, and returns whether it succeeded and what was traced.
+    fn trace(code: &str, filter: TraceFilter) -> (bool, String) {
+        let output = SharedOutput::default();
+        let mut interpreter = Interpreter::new();
+        interpreter.add_hook(Box::new(Tracer::new(Box::new(output.clone()), filter)));
+        let succeeded = interpreter.execute(&code_to_node(code, "code.stk").unwrap()).is_ok();
+
+        drop(interpreter);
+        let traced = String::from_utf8(output.0.take()).unwrap();
+        (succeeded, traced)
+    }
+
+    #[test]
+    fn line_ranges() {
+        assert_eq!(LineRange::parse("code.stk:10-20", "x").unwrap(), LineRange { file: "code.stk".to_owned(), lines: 10..=20 });
+        assert_eq!(LineRange::parse("7", "x").unwrap(), LineRange { file: "x".to_owned(), lines: 7..=7 });
+        assert_eq!(
+            LineRange::parse("code.stk:ten", "x").unwrap_err().to_string(),
+            "invalid line range `code.stk:ten`, expected something like `code.stk:10-20`",
+        );
+    }
+
+    #[test]
+    fn traces_atoms_in_actions() {
+        let filter = TraceFilter { actions: vec!["inc".to_owned()], ranges: vec![] };
+        let (succeeded, traced) = trace("{ ( n -- n ) 1 + } $inc ::\n5 inc", filter);
+        assert!(succeeded);
+        assert_eq!(traced, "\
+code.stk:1:14 in inc: 1  [5] -> [5, 1]
+code.stk:1:16 in inc: +  [5, 1] -> [6]
+");
+
+        let filter = TraceFilter { actions: vec![], ranges: vec![LineRange::parse("2", "code.stk").unwrap()] };
+        let (_, traced) = trace("{ ( n -- n ) 1 + } $inc ::\n5 inc", filter);
+        assert_eq!(traced, "\
+code.stk:2:1 in top-level: 5  [] -> [5]
+code.stk:2:3 in top-level: inc  [5] -> [6]
+");
+    }
+
+    #[test]
+    fn nested_atoms_are_traced_in_between() {
+        let (_, traced) = trace("{ 1 + } $inc ::\n5 inc", TraceFilter::default());
+        assert_eq!(traced, "\
+code.stk:1:9 in top-level: $inc  [(block)] -> [(block), (unbound binding: $inc)]
+code.stk:1:14 in top-level: ::  [(block), (unbound binding: $inc)] -> []
+code.stk:2:1 in top-level: 5  [] -> [5]
+code.stk:2:3 in top-level: inc  [5] ...
+code.stk:1:3 in inc: 1  [5] -> [5, 1]
+code.stk:1:5 in inc: +  [5, 1] -> [6]
+code.stk:2:3 in top-level: inc finished -> [6]
+");
+    }
+
+    #[test]
+    fn traces_the_atom_which_failed() {
+        let (succeeded, traced) = trace("1 +", TraceFilter::default());
+        assert!(!succeeded);
+        assert_eq!(traced, "\
+code.stk:1:1 in top-level: 1  [] -> [1]
+code.stk:1:3 in top-level: +  [1] ...
+");
+    }
+}
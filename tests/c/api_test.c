@@ -0,0 +1,104 @@
+/* Exercises the C API: compiled and run by tests/c_api.rs. */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "stk.h"
+
+static int failures = 0;
+
+#define CHECK(condition) do { \
+    if (!(condition)) { \
+        fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, #condition); \
+        failures++; \
+    } \
+} while (0)
+
+static void test_integers(StkInterpreter *interpreter) {
+    intptr_t result = 0;
+
+    stk_push_integer(interpreter, 20);
+    stk_push_integer(interpreter, 22);
+    CHECK(stk_execute(interpreter, "+", NULL));
+    CHECK(stk_stack_size(interpreter) == 1);
+    CHECK(stk_pop_integer(interpreter, &result));
+    CHECK(result == 42);
+}
+
+static void test_strings(StkInterpreter *interpreter) {
+    char *result;
+
+    CHECK(stk_push_string(interpreter, "hello"));
+    CHECK(stk_execute(interpreter, "reverse", "strings.stk"));
+    result = stk_pop_string(interpreter);
+    CHECK(result != NULL && strcmp(result, "olleh") == 0);
+    stk_string_free(result);
+}
+
+static void test_arrays(StkInterpreter *interpreter) {
+    intptr_t item = 0;
+    size_t count = 0;
+
+    stk_push_integer(interpreter, 1);
+    stk_push_integer(interpreter, 2);
+    stk_push_integer(interpreter, 3);
+    CHECK(stk_push_array(interpreter, 3));
+    CHECK(stk_execute(interpreter, "{ 10 * } map", NULL));
+
+    CHECK(stk_pop_array(interpreter, &count));
+    CHECK(count == 3);
+    CHECK(stk_pop_integer(interpreter, &item) && item == 30);
+    CHECK(stk_pop_integer(interpreter, &item) && item == 20);
+    CHECK(stk_pop_integer(interpreter, &item) && item == 10);
+
+    /* Can't take more values than there are */
+    CHECK(!stk_push_array(interpreter, 1));
+}
+
+static void test_errors(StkInterpreter *interpreter) {
+    const char *name = NULL;
+    size_t line = 0, column = 0;
+    intptr_t result = 0;
+
+    /* Errors while executing have a location */
+    CHECK(!stk_execute(interpreter, "1 2 +\n  no-such-action", "errors.stk"));
+    CHECK(stk_last_error(interpreter) != NULL);
+    CHECK(strstr(stk_last_error(interpreter), "no-such-action") != NULL);
+    CHECK(stk_last_error_location(interpreter, &name, &line, &column));
+    CHECK(name != NULL && strcmp(name, "errors.stk") == 0);
+    CHECK(line == 2 && column == 3);
+    CHECK(stk_pop_integer(interpreter, &result) && result == 3);
+
+    /* So do syntax errors */
+    CHECK(!stk_execute(interpreter, "{ 1", "syntax.stk"));
+    CHECK(stk_last_error_location(interpreter, &name, &line, &column));
+    CHECK(name != NULL && strcmp(name, "syntax.stk") == 0);
+
+    /* Type errors from popping don't */
+    stk_push_integer(interpreter, 1);
+    CHECK(stk_pop_string(interpreter) == NULL);
+    CHECK(stk_last_error(interpreter) != NULL);
+    CHECK(!stk_last_error_location(interpreter, &name, &line, &column));
+}
+
+int main(void) {
+    StkInterpreter *interpreter = stk_interpreter_new();
+    CHECK(stk_last_error(interpreter) == NULL);
+    CHECK(stk_load_stdlib(interpreter));
+
+    test_integers(interpreter);
+    test_strings(interpreter);
+    test_arrays(interpreter);
+    test_errors(interpreter);
+    CHECK(stk_stack_size(interpreter) == 0);
+
+    stk_interpreter_free(interpreter);
+    stk_interpreter_free(NULL);
+
+    if (failures > 0) {
+        fprintf(stderr, "%d check(s) failed\n", failures);
+        return EXIT_FAILURE;
+    }
+    return EXIT_SUCCESS;
+}
@@ -0,0 +1,34 @@
+//! Compiles `tests/c/api_test.c` against the static library and generated header, and runs it.
+//! Needs a C compiler, `cc`.
+
+use std::{env::{current_exe, temp_dir}, fs::{create_dir_all, remove_dir_all, write}, process::Command};
+
+use advent_of_code_2024::capi::C_HEADER;
+
+#[test]
+fn c_api() {
+    // This test binary is in `target/<profile>/deps`, and the library in `target/<profile>`
+    let exe = current_exe().unwrap();
+    let profile_dir = exe.parent().unwrap().parent().unwrap();
+    let library = profile_dir.join("libadvent_of_code_2024.a");
+    assert!(library.exists(), "static library not found at {}", library.display());
+
+    let build_dir = temp_dir().join(format!("stk-c-api-{}", std::process::id()));
+    create_dir_all(&build_dir).unwrap();
+    write(build_dir.join("stk.h"), C_HEADER).unwrap();
+    let program = build_dir.join("api_test");
+
+    let status = Command::new("cc")
+        .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/c/api_test.c"))
+        .arg("-I").arg(&build_dir)
+        .arg(&library)
+        .args(["-lpthread", "-ldl", "-lm", "-o"])
+        .arg(&program)
+        .status()
+        .expect("couldn't run the C compiler");
+    assert!(status.success(), "compiling the C test program failed");
+
+    let status = Command::new(&program).status().unwrap();
+    remove_dir_all(&build_dir).unwrap();
+    assert!(status.success(), "the C test program failed");
+}
@@ -0,0 +1,123 @@
+//! Scripts a debugging session with the `dap` subcommand: sets a breakpoint, inspects the call
+//! stack and value stack when it's hit, and then continues to the end. Every request should
+//! succeed, and the program should stop and then finish. Run with `--nocapture` to see the whole
+//! conversation.
+
+use std::{env::temp_dir, fs::{remove_file, write}, io::{BufRead, BufReader, Write}, process::{ChildStdin, ChildStdout, Command, Stdio}};
+
+const PROGRAM: &str = "\
+{ ( n -- n ) 1 + } $inc ::
+
+5 inc $x :
+[ 1 , 2 , 3 ] { inc } map
+$x println
+";
+
+struct Client {
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    seq: usize,
+}
+
+impl Client {
+    /// Sends a request, then prints messages until its response, which must be successful and is
+    /// returned.
+    fn request(&mut self, command: &str, arguments: &str) -> String {
+        self.seq += 1;
+        let message = format!(r#"{{ "seq": {}, "type": "request", "command": "{command}", "arguments": {arguments} }}"#, self.seq);
+        println!(">>> {message}");
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{message}", message.len()).unwrap();
+        self.stdin.flush().unwrap();
+
+        let request_seq = format!(r#""request_seq":{}"#, self.seq);
+        let response = self.wait_for(&request_seq);
+        assert!(response.contains(r#""success":true"#), "`{command}` failed: {response}");
+        response
+    }
+
+    /// Prints messages until one contains `pattern`, and returns it.
+    fn wait_for(&mut self, pattern: &str) -> String {
+        loop {
+            let message = read_message(&mut self.stdout)
+                .unwrap_or_else(|| panic!("server exited while waiting for {pattern}"));
+            println!("<<< {message}");
+            if message.contains(pattern) {
+                return message;
+            }
+        }
+    }
+}
+
+#[test]
+fn dap_session() {
+    let program_path = temp_dir().join(format!("stk-dap-{}.stk", std::process::id()));
+    write(&program_path, PROGRAM).unwrap();
+    let program_path = program_path.canonicalize().unwrap().to_string_lossy().to_string();
+
+    let mut server = Command::new(env!("CARGO_BIN_EXE_advent-of-code-2024"))
+        .arg("dap")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("couldn't start the server");
+    let mut client = Client {
+        stdin: server.stdin.take().unwrap(),
+        stdout: BufReader::new(server.stdout.take().unwrap()),
+        seq: 0,
+    };
+
+    client.request("initialize", r#"{ "adapterID": "stk" }"#);
+    client.request("launch", &format!(r#"{{ "program": "{program_path}" }}"#));
+
+    // Break on the line which calls `map`
+    client.request("setBreakpoints", &format!(
+        r#"{{ "source": {{ "path": "{program_path}" }}, "breakpoints": [{{ "line": 4 }}] }}"#
+    ));
+    client.request("configurationDone", "{}");
+    client.wait_for(r#""event":"stopped""#);
+
+    client.request("threads", "{}");
+    let trace = client.request("stackTrace", r#"{ "threadId": 1 }"#);
+    assert!(trace.contains(r#""line":4"#), "not stopped on the breakpoint: {trace}");
+    let scopes = client.request("scopes", r#"{ "frameId": 0 }"#);
+    let stack_reference = find_number(&scopes, "variablesReference").expect("no variables reference");
+    client.request("variables", &format!(r#"{{ "variablesReference": {stack_reference} }}"#));
+
+    client.request("setBreakpoints", &format!(r#"{{ "source": {{ "path": "{program_path}" }}, "breakpoints": [] }}"#));
+    client.request("continue", r#"{ "threadId": 1 }"#);
+    let output = client.wait_for(r#""event":"output""#);
+    assert!(output.contains(r#""output":"6\n""#), "unexpected output: {output}");
+    client.wait_for(r#""event":"terminated""#);
+    client.request("disconnect", "{}");
+
+    drop(client);
+    let status = server.wait().unwrap();
+    remove_file(&program_path).unwrap();
+    assert!(status.success(), "server exited with {status}");
+}
+
+fn read_message(input: &mut impl BufRead) -> Option<String> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line).unwrap() == 0 {
+            return None;
+        }
+        match line.trim_end().split_once(": ") {
+            Some(("Content-Length", value)) => length = Some(value.parse().unwrap()),
+            _ if line.trim_end().is_empty() => break,
+            _ => (),
+        }
+    }
+
+    let mut body = vec![0; length?];
+    input.read_exact(&mut body).unwrap();
+    Some(String::from_utf8(body).unwrap())
+}
+
+/// Finds the first number following a key in some JSON, without bothering to parse it properly.
+fn find_number(json: &str, key: &str) -> Option<usize> {
+    let start = json.find(&format!(r#""{key}":"#))? + key.len() + 3;
+    let digits = json[start..].chars().take_while(|c| c.is_ascii_digit()).collect::<String>();
+    digits.parse().ok()
+}
@@ -0,0 +1,100 @@
+//! Scripts a conversation with the `lsp` subcommand: opens a small document, makes one of each kind
+//! of request, then opens an empty document and changes it to only a comment. Every request should
+//! get a successful response, and the server should exit cleanly. Run with `--nocapture` to see the
+//! whole conversation.
+
+use std::{io::{BufRead, BufReader, Write}, process::{Command, Stdio}};
+
+const DOCUMENT: &str = r#"// Adds one.
+// [n] inc --> [n+1]
+{ ( n -- n ) 1 + } $inc ::
+
+5 inc $x :
+$x inc println
+$x sum
+"#;
+
+#[test]
+fn lsp_conversation() {
+    let mut server = Command::new(env!("CARGO_BIN_EXE_advent-of-code-2024"))
+        .arg("lsp")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("couldn't start the server");
+
+    let uri = "file:///example.stk";
+    let empty_uri = "file:///empty.stk";
+    let document = DOCUMENT.replace('\n', "\\n").replace('"', "\\\"");
+    let at = |line: usize, character: usize| format!(
+        r#""textDocument": {{ "uri": "{uri}" }}, "position": {{ "line": {line}, "character": {character} }}"#
+    );
+
+    let script = [
+        r#"{ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": { "capabilities": {} } }"#.to_owned(),
+        r#"{ "jsonrpc": "2.0", "method": "initialized", "params": {} }"#.to_owned(),
+        format!(r#"{{ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {{ "textDocument": {{ "uri": "{uri}", "languageId": "stk", "version": 1, "text": "{document}" }} }} }}"#),
+        // `inc` in `5 inc`
+        format!(r#"{{ "jsonrpc": "2.0", "id": 2, "method": "textDocument/hover", "params": {{ {} }} }}"#, at(4, 3)),
+        format!(r#"{{ "jsonrpc": "2.0", "id": 3, "method": "textDocument/definition", "params": {{ {} }} }}"#, at(4, 3)),
+        format!(r#"{{ "jsonrpc": "2.0", "id": 4, "method": "textDocument/references", "params": {{ {}, "context": {{ "includeDeclaration": true }} }} }}"#, at(4, 3)),
+        // `$x` in `$x inc println`
+        format!(r#"{{ "jsonrpc": "2.0", "id": 5, "method": "textDocument/definition", "params": {{ {} }} }}"#, at(5, 1)),
+        // `sum`, from the stdlib
+        format!(r#"{{ "jsonrpc": "2.0", "id": 6, "method": "textDocument/hover", "params": {{ {} }} }}"#, at(6, 4)),
+        format!(r#"{{ "jsonrpc": "2.0", "id": 7, "method": "textDocument/completion", "params": {{ {} }} }}"#, at(6, 0)),
+        // Editors open new buffers empty, and they can be edited down to nothing but comments
+        format!(r#"{{ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {{ "textDocument": {{ "uri": "{empty_uri}", "languageId": "stk", "version": 1, "text": "" }} }} }}"#),
+        format!(r#"{{ "jsonrpc": "2.0", "method": "textDocument/didChange", "params": {{ "textDocument": {{ "uri": "{empty_uri}", "version": 2 }}, "contentChanges": [{{ "text": "// nothing yet\n" }}] }} }}"#),
+        format!(r#"{{ "jsonrpc": "2.0", "id": 8, "method": "textDocument/hover", "params": {{ "textDocument": {{ "uri": "{empty_uri}" }}, "position": {{ "line": 0, "character": 3 }} }} }}"#),
+        r#"{ "jsonrpc": "2.0", "id": 9, "method": "shutdown" }"#.to_owned(),
+        r#"{ "jsonrpc": "2.0", "method": "exit" }"#.to_owned(),
+    ];
+
+    let mut stdin = server.stdin.take().unwrap();
+    for message in &script {
+        println!(">>> {message}");
+        write!(stdin, "Content-Length: {}\r\n\r\n{message}", message.len()).unwrap();
+    }
+    drop(stdin);
+
+    // The server handles messages in order, so just read everything it sends until it exits
+    let mut stdout = BufReader::new(server.stdout.take().unwrap());
+    let mut responses = Vec::new();
+    while let Some(message) = read_message(&mut stdout) {
+        println!("<<< {message}");
+        if message.contains(r#""id":"#) {
+            responses.push(message);
+        }
+    }
+
+    let status = server.wait().unwrap();
+    assert!(status.success(), "server exited with {status}");
+
+    let requests = script.iter().filter(|m| m.contains(r#""id":"#)).count();
+    assert_eq!(responses.len(), requests, "every request should get exactly one response");
+    for response in &responses {
+        assert!(!response.contains(r#""error":"#), "request failed: {response}");
+    }
+    // The hover over `inc` shows its doc comment and effect
+    assert!(responses[1].contains("Adds one."), "unexpected hover: {}", responses[1]);
+}
+
+fn read_message(input: &mut impl BufRead) -> Option<String> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line).unwrap() == 0 {
+            return None;
+        }
+        match line.trim_end().split_once(": ") {
+            Some(("Content-Length", value)) => length = Some(value.parse().unwrap()),
+            _ if line.trim_end().is_empty() => break,
+            _ => (),
+        }
+    }
+
+    let mut body = vec![0; length?];
+    input.read_exact(&mut body).unwrap();
+    Some(String::from_utf8(body).unwrap())
+}